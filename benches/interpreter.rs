@@ -0,0 +1,91 @@
+//! Benchmarks for each stage of the interpreter on a few representative programs. Run
+//! with `cargo bench`; criterion reports the change against the previous run.
+
+use crafting_interpreters_rust::{parser, scanner, Interpreter};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// Lox has no functions yet, so Fibonacci is computed with a loop.
+const FIB: &str = "
+var a = 0;
+var b = 1;
+for (var i = 0; i < 1000; i = i + 1) {
+    var next = a + b;
+    a = b;
+    b = next;
+}
+";
+
+const LOOPS: &str = "
+var total = 0;
+for (var i = 0; i < 100; i = i + 1) {
+    for (var j = 0; j < 100; j = j + 1) {
+        if (j > i) break;
+        total = total + j;
+    }
+}
+";
+
+const CONCAT: &str = "
+var text = \"\";
+var i = 0;
+while (i < 1000) {
+    text = text + \"lox\";
+    i = i + 1;
+}
+";
+
+// Only arithmetic on numbers, so the `jit` feature can compile the function.
+const ARITHMETIC: &str = "
+var norm = (x, y) => if (x > y) { x * x - y } else { y * y - x / 2 };
+var total = 0;
+for (var i = 0; i < 1000; i = i + 1) {
+    total = total + norm(i, 500);
+}
+";
+
+const PROGRAMS: [(&str, &str); 4] = [("fib", FIB), ("loops", LOOPS), ("concat", CONCAT), ("arithmetic", ARITHMETIC)];
+
+fn scan(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        c.bench_function(&format!("scan {name}"), |b| b.iter(|| scanner::scan_tokens(black_box(source)).unwrap()));
+    }
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        let tokens = scanner::scan_tokens(source).unwrap();
+        c.bench_function(&format!("parse {name}"), |b| b.iter(|| parser::parse_tokens(black_box(tokens.clone())).unwrap()));
+    }
+}
+
+fn execute(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        let ast = parser::parse_source(source).unwrap();
+        c.bench_function(&format!("execute {name}"), |b| {
+            b.iter(|| Interpreter::new(false).interpret(black_box(&ast)).unwrap())
+        });
+    }
+}
+
+#[cfg(feature = "jit")]
+fn jit(c: &mut Criterion) {
+    use crafting_interpreters_rust::jit::JitMode;
+
+    let ast = parser::parse_source(ARITHMETIC).unwrap();
+    for mode in [JitMode::Off, JitMode::Always] {
+        c.bench_function(&format!("execute arithmetic (jit {mode:?})"), |b| {
+            b.iter(|| {
+                let mut interpreter = Interpreter::new(false);
+                interpreter.set_jit(mode);
+                interpreter.interpret(black_box(&ast)).unwrap()
+            })
+        });
+    }
+}
+
+#[cfg(feature = "jit")]
+criterion_group!(benches, scan, parse, execute, jit);
+#[cfg(not(feature = "jit"))]
+criterion_group!(benches, scan, parse, execute);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+//! Parses the tokens scanned from arbitrary input, which must produce a syntax tree and
+//! errors but never panic. Run with `cargo fuzz run parse`.
+
+#![no_main]
+
+use crafting_interpreters_rust::{parser, scanner};
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (tokens, _) = scanner::scan_tokens_lossy(&String::from_utf8_lossy(data));
+    parser::parse_tokens(tokens).ok();
+});
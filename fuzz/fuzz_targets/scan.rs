@@ -0,0 +1,12 @@
+//! Scans arbitrary input, which must produce tokens and errors but never panic.
+//! Run with `cargo fuzz run scan`.
+
+#![no_main]
+
+use crafting_interpreters_rust::scanner;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    scanner::scan_tokens_lossy(&String::from_utf8_lossy(data));
+});
@@ -0,0 +1,35 @@
+/* C API for the Lox interpreter. Build the crate with `--features ffi` and link against
+ * the resulting cdylib. */
+
+#ifndef LOX_H
+#define LOX_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define LOX_OK 0
+#define LOX_COMPILE_ERROR 1
+#define LOX_RUNTIME_ERROR 2
+#define LOX_INVALID_ARGUMENT 3
+
+typedef struct LoxInterpreter LoxInterpreter;
+
+/* Creates an interpreter. Release it with lox_free. */
+LoxInterpreter *lox_new(void);
+
+/* Runs NUL-terminated, UTF-8 source and returns one of the LOX_* codes. */
+int lox_eval(LoxInterpreter *lox, const char *source);
+
+/* The value of the last expression statement run by lox_eval, or its error messages.
+ * Owned by the interpreter and valid until the next call on it. */
+const char *lox_get_string_result(const LoxInterpreter *lox);
+
+/* Destroys an interpreter. Passing NULL does nothing. */
+void lox_free(LoxInterpreter *lox);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
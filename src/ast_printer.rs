@@ -0,0 +1,184 @@
+use crate::scanner::Literal;
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+pub fn print_statements(ast: &Ast, statements: &[StmtId]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for &statement in statements {
+        print_stmt(ast, statement, 0, &mut lines);
+    }
+    lines.join("\n")
+}
+
+pub fn print_expr(ast: &Ast, expr: ExprId) -> String {
+    match &ast[expr] {
+        Expr::Assign { name, value } => {
+            parenthesize(ast, &format!("= {}", name.lexeme), &[*value])
+        },
+        Expr::Binary { .. } | Expr::Logical { .. } => {
+            // Chains nest to the left, so `a + b + c` opens all of its parentheses up front,
+            // as `(+ (+ a b) c)`, rather than recursing into every operation.
+            let (first, links) = ast.chain(expr);
+            let mut result: String = links.iter().rev().map(|(operator, _)| format!("({} ", operator.lexeme)).collect();
+            result.push_str(&print_expr(ast, first));
+            for (_, operand) in links {
+                result.push(' ');
+                result.push_str(&print_expr(ast, operand));
+                result.push(')');
+            }
+            result
+        },
+        Expr::Call { callee, paren: _, arguments } => {
+            let mut exprs: Vec<ExprId> = vec![*callee];
+            exprs.extend(arguments.iter());
+            parenthesize(ast, "call", &exprs)
+        },
+        Expr::Grouping { expression } => {
+            parenthesize(ast, "group", &[*expression])
+        },
+        Expr::Literal { value } => print_literal(value),
+        Expr::Unary { operator, right } => {
+            parenthesize(ast, &operator.lexeme, &[*right])
+        },
+        Expr::Variable { name } => name.lexeme.to_string(),
+        Expr::Block { statements, value } => {
+            // Statements print over several lines, which an expression has to fit on one.
+            let mut result = String::from("(block");
+            for &statement in statements {
+                for line in print_statements(ast, &[statement]).lines() {
+                    result.push(' ');
+                    result.push_str(line.trim());
+                }
+            }
+            if let Some(value) = value {
+                result.push(' ');
+                result.push_str(&print_expr(ast, *value));
+            }
+            result.push(')');
+            result
+        },
+        Expr::If { keyword: _, condition, then_branch, else_branch } => {
+            let mut exprs: Vec<ExprId> = vec![*condition, *then_branch];
+            exprs.extend(else_branch.iter());
+            parenthesize(ast, "if", &exprs)
+        },
+        Expr::Lambda { params, arrow: _, body } => {
+            let params: Vec<&str> = params.iter().map(|param| &*param.lexeme).collect();
+            format!("(lambda ({}) {})", params.join(" "), print_expr(ast, *body))
+        },
+        Expr::Error { token: _ } => String::from("(error)"),
+    }
+}
+
+fn print_stmt(ast: &Ast, stmt: StmtId, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+
+    match &ast[stmt] {
+        Stmt::Block { statements } => {
+            lines.push(format!("{indent}(block"));
+            for &statement in statements {
+                print_stmt(ast, statement, depth + 1, lines);
+            }
+            lines.push(format!("{indent})"));
+        },
+        Stmt::Expression { expression } => {
+            lines.push(format!("{indent}(; {})", print_expr(ast, *expression)));
+        },
+        Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+            lines.push(format!("{indent}(if {}", print_expr(ast, *condition)));
+            print_stmt(ast, *then_branch, depth + 1, lines);
+            if let Some(else_branch) = else_branch {
+                lines.push(format!("{indent} else"));
+                print_stmt(ast, *else_branch, depth + 1, lines);
+            }
+            lines.push(format!("{indent})"));
+        },
+        Stmt::Print { keyword: _, expression } => {
+            lines.push(format!("{indent}(print {})", print_expr(ast, *expression)));
+        },
+        Stmt::Variable { name, initializer } => {
+            match initializer {
+                Some(initializer) => lines.push(format!("{indent}(var {} {})", name.lexeme, print_expr(ast, *initializer))),
+                None => lines.push(format!("{indent}(var {})", name.lexeme)),
+            }
+        },
+        Stmt::While { keyword: _, condition, body } => {
+            lines.push(format!("{indent}(while {}", print_expr(ast, *condition)));
+            print_stmt(ast, *body, depth + 1, lines);
+            lines.push(format!("{indent})"));
+        },
+        Stmt::For { keyword: _, initializer, condition, increment, body } => {
+            let initializer = initializer.map_or(String::from("_"), |initializer| print_statements(ast, &[initializer]));
+            let condition = condition.map_or(String::from("_"), |condition| print_expr(ast, condition));
+            let increment = increment.map_or(String::from("_"), |increment| print_expr(ast, increment));
+            lines.push(format!("{indent}(for {initializer} {condition} {increment}"));
+            print_stmt(ast, *body, depth + 1, lines);
+            lines.push(format!("{indent})"));
+        },
+        Stmt::Break {} => {
+            lines.push(format!("{indent}(break)"));
+        },
+        Stmt::Import { keyword: _, module } => {
+            lines.push(format!("{indent}(import native {})", module.lexeme));
+        },
+        Stmt::Debugger { keyword: _ } => {
+            lines.push(format!("{indent}(debugger)"));
+        },
+        Stmt::Error { tokens } => {
+            let lexemes: Vec<&str> = tokens.iter().map(|token| &*token.lexeme).collect();
+            lines.push(format!("{indent}(error {})", lexemes.join(" ")));
+        },
+    }
+}
+
+fn print_literal(literal: &Literal) -> String {
+    match literal {
+        Literal::Identifier(text) => text.to_string(),
+        Literal::Str(text) => format!("\"{text}\""),
+        Literal::Number(number) => number.to_string(),
+        Literal::Bool(value) => value.to_string(),
+        Literal::Nil => String::from("nil"),
+    }
+}
+
+fn parenthesize(ast: &Ast, name: &str, exprs: &[ExprId]) -> String {
+    let mut result = format!("({name}");
+    for &expr in exprs {
+        result.push(' ');
+        result.push_str(&print_expr(ast, expr));
+    }
+    result.push(')');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_tokens;
+    use crate::scanner::scan_tokens;
+
+    fn print_source(source: &str) -> String {
+        let tokens = scan_tokens(source).unwrap();
+        let ast = parse_tokens(tokens).unwrap();
+        print_statements(&ast, &ast.statements)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(print_source("1 + 2 * 3;"), "(; (+ 1 (* 2 3)))");
+        assert_eq!(print_source("(1 + 2) * -3;"), "(; (* (group (+ 1 2)) (- 3)))");
+    }
+
+    #[test]
+    fn nested_statements() {
+        let printed = print_source("var a = 1; while (a < 3) { print a; a = a + 1; }");
+        let expected = "(var a 1)\n\
+                        (while (< a 3)\n  \
+                          (block\n    \
+                            (print a)\n    \
+                            (; (= a (+ a 1)))\n  \
+                          )\n\
+                        )";
+
+        assert_eq!(printed, expected);
+    }
+}
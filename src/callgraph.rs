@@ -0,0 +1,426 @@
+//! Which functions call which, found from the source without running it, as `jlox
+//! callgraph` shows it.
+//!
+//! Lox functions have no names of their own, so a function here is a variable declared
+//! with a lambda, like `var add = (a, b) => a + b;`. Calls are attributed to the innermost
+//! such function they appear in, or to the script. A call through any other variable, like
+//! a parameter, can't be followed without running the program and is kept by name only.
+
+use crate::error::CompileError;
+use crate::native::{self, DebugModule, NativeModule};
+use crate::parser;
+use crate::scanner::{Literal, Token};
+use crate::stack::SCRIPT;
+use crate::symbols::{SymbolId, SymbolTable};
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+use crate::threads::ThreadsModule;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub type FunctionId = usize;
+
+/// The top level of the script, which is always function 0.
+pub const SCRIPT_FUNCTION: FunctionId = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// The line of the declaration, or 0 for the script.
+    pub line: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Function(FunctionId),
+    /// A function of a native module the script imports.
+    Native(String),
+    /// A variable that doesn't hold a lambda where it is declared, like a parameter.
+    Variable(String),
+    /// A name the script never declares or imports.
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub caller: FunctionId,
+    pub callee: Callee,
+    pub line: i32,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    pub functions: Vec<Function>,
+    /// Every call of a name, in source order. Calls of other expressions, like `f()()`,
+    /// are left out.
+    pub calls: Vec<Call>,
+}
+
+impl CallGraph {
+    pub fn build(source: &str) -> Result<Self, Vec<CompileError>> {
+        let ast = parser::parse_source(source)?;
+        let table = SymbolTable::build(&ast);
+        let mut builder = Builder {
+            ast: &ast,
+            references: table.references.iter().map(|reference| (reference.name.span.start, reference.symbol)).collect(),
+            declarations: table.symbols.iter().enumerate().map(|(id, symbol)| (symbol.name.span.start, id)).collect(),
+            functions: HashMap::new(),
+            natives: Vec::new(),
+            graph: CallGraph { functions: vec![Function { name: SCRIPT.to_string(), line: 0 }], calls: Vec::new() },
+            caller: SCRIPT_FUNCTION,
+        };
+
+        // Functions and imports first, since a call can come before either.
+        for &statement in &ast.statements {
+            builder.declarations_in(statement);
+        }
+        for &statement in &ast.statements {
+            builder.stmt(statement);
+        }
+        Ok(builder.graph)
+    }
+
+    /// The calls of names that are neither declared nor imported, which fail when run,
+    /// with those names.
+    pub fn unknown_calls(&self) -> impl Iterator<Item = (&str, &Call)> {
+        self.calls.iter().filter_map(|call| match &call.callee {
+            Callee::Unknown(name) => Some((name.as_str(), call)),
+            _ => None,
+        })
+    }
+
+    /// One `caller -> callee` line per pair that calls, with the lines of the calls.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (caller, callee, lines) in self.edges() {
+            let callee = match callee {
+                Callee::Function(function) => self.describe(*function),
+                Callee::Native(name) => format!("{name} (native)"),
+                Callee::Variable(name) => format!("{name} (variable)"),
+                Callee::Unknown(name) => format!("{name} (unknown)"),
+            };
+            let lines: Vec<String> = lines.iter().map(i32::to_string).collect();
+            let _ = writeln!(text, "{} -> {callee} on line {}", self.describe(caller), lines.join(", "));
+        }
+        text
+    }
+
+    /// The graph in Graphviz's DOT language. Unknown names are drawn in red.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n    node [shape=box];\n");
+        for (id, function) in self.functions.iter().enumerate() {
+            let _ = writeln!(dot, "    f{id} [label={}];", quote(&self.describe_function(id, function)));
+        }
+
+        // Other callees are a node per name.
+        let mut others: Vec<&Callee> = Vec::new();
+        for (caller, callee, _) in self.edges() {
+            let (name, style) = match callee {
+                Callee::Function(function) => {
+                    let _ = writeln!(dot, "    f{caller} -> f{function};");
+                    continue;
+                },
+                Callee::Native(name) => (name, "shape=ellipse"),
+                Callee::Variable(name) => (name, "shape=ellipse, style=dashed"),
+                Callee::Unknown(name) => (name, "shape=ellipse, color=red, fontcolor=red"),
+            };
+            let node = others.iter().position(|other| *other == callee).unwrap_or_else(|| {
+                let _ = writeln!(dot, "    n{} [label={}, {style}];", others.len(), quote(name));
+                others.push(callee);
+                others.len() - 1
+            });
+            let _ = writeln!(dot, "    f{caller} -> n{node};");
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Each caller and callee once, in the order of their first call, with the lines of
+    // every call between them.
+    fn edges(&self) -> Vec<(FunctionId, &Callee, Vec<i32>)> {
+        let mut edges: Vec<(FunctionId, &Callee, Vec<i32>)> = Vec::new();
+        for call in &self.calls {
+            match edges.iter_mut().find(|(caller, callee, _)| *caller == call.caller && **callee == call.callee) {
+                Some((_, _, lines)) => lines.push(call.line),
+                None => edges.push((call.caller, &call.callee, vec![call.line])),
+            }
+        }
+        edges
+    }
+
+    fn describe(&self, function: FunctionId) -> String {
+        self.describe_function(function, &self.functions[function])
+    }
+
+    fn describe_function(&self, id: FunctionId, function: &Function) -> String {
+        if id == SCRIPT_FUNCTION {
+            function.name.clone()
+        } else {
+            format!("{} (line {})", function.name, function.line)
+        }
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+struct Builder<'a> {
+    ast: &'a Ast,
+    // The symbol each referenced name resolves to, and each declared one, by where it starts.
+    references: HashMap<usize, Option<SymbolId>>,
+    declarations: HashMap<usize, SymbolId>,
+    functions: HashMap<SymbolId, FunctionId>,
+    // The functions of the imported native modules.
+    natives: Vec<String>,
+    graph: CallGraph,
+    caller: FunctionId,
+}
+
+impl Builder<'_> {
+    fn declarations_in(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        match &ast[stmt] {
+            Stmt::Variable { name, initializer: Some(initializer) } => {
+                if matches!(ast[*initializer], Expr::Lambda { .. }) {
+                    let symbol = self.declarations[&name.span.start];
+                    self.graph.functions.push(Function { name: name.lexeme.to_string(), line: name.line });
+                    self.functions.insert(symbol, self.graph.functions.len() - 1);
+                }
+                self.declarations_in_expr(*initializer);
+            },
+            Stmt::Import { keyword: _, module } => {
+                let module = match &module.literal {
+                    Some(Literal::Str(module)) => module,
+                    _ => return,
+                };
+                // The modules `jlox` registers as well as the built-in ones.
+                let mut modules = native::builtin_modules();
+                modules.extend([Box::new(ThreadsModule) as Box<dyn NativeModule>, Box::new(DebugModule)]);
+                if let Some(module) = modules.into_iter().find(|builtin| builtin.name() == &**module) {
+                    self.natives.extend(module.functions().into_iter().map(|function| function.name.to_string()));
+                }
+            },
+            Stmt::Block { statements } => {
+                for &statement in statements {
+                    self.declarations_in(statement);
+                }
+            },
+            Stmt::Expression { expression } | Stmt::Print { keyword: _, expression } => self.declarations_in_expr(*expression),
+            Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+                self.declarations_in_expr(*condition);
+                self.declarations_in(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.declarations_in(*else_branch);
+                }
+            },
+            Stmt::While { keyword: _, condition, body } => {
+                self.declarations_in_expr(*condition);
+                self.declarations_in(*body);
+            },
+            Stmt::For { keyword: _, initializer, condition, increment, body } => {
+                if let Some(initializer) = initializer {
+                    self.declarations_in(*initializer);
+                }
+                for &expression in condition.iter().chain(increment) {
+                    self.declarations_in_expr(expression);
+                }
+                self.declarations_in(*body);
+            },
+            Stmt::Variable { name: _, initializer: None } | Stmt::Break {} | Stmt::Debugger { keyword: _ } | Stmt::Error { tokens: _ } => {},
+        }
+    }
+
+    // Functions are declared in blocks and lambda bodies too, which only expressions hold.
+    fn declarations_in_expr(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        match &ast[expr] {
+            Expr::Block { statements, value } => {
+                for &statement in statements {
+                    self.declarations_in(statement);
+                }
+                if let Some(value) = value {
+                    self.declarations_in_expr(*value);
+                }
+            },
+            Expr::Lambda { params: _, arrow: _, body } => self.declarations_in_expr(*body),
+            Expr::If { keyword: _, condition, then_branch, else_branch } => {
+                self.declarations_in_expr(*condition);
+                self.declarations_in_expr(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.declarations_in_expr(*else_branch);
+                }
+            },
+            Expr::Call { callee, paren: _, arguments } => {
+                self.declarations_in_expr(*callee);
+                for &argument in arguments {
+                    self.declarations_in_expr(argument);
+                }
+            },
+            Expr::Assign { name: _, value } => self.declarations_in_expr(*value),
+            Expr::Binary { .. } | Expr::Logical { .. } => {
+                for operand in ast.chain_operands(expr) {
+                    self.declarations_in_expr(operand);
+                }
+            },
+            Expr::Grouping { expression } | Expr::Unary { operator: _, right: expression } => self.declarations_in_expr(*expression),
+            Expr::Literal { value: _ } | Expr::Variable { name: _ } | Expr::Error { token: _ } => {},
+        }
+    }
+
+    fn stmt(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        match &ast[stmt] {
+            Stmt::Block { statements } => {
+                for &statement in statements {
+                    self.stmt(statement);
+                }
+            },
+            Stmt::Expression { expression } | Stmt::Print { keyword: _, expression } => self.expr(*expression),
+            Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+                self.expr(*condition);
+                self.stmt(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(*else_branch);
+                }
+            },
+            Stmt::Variable { name, initializer: Some(initializer) } => {
+                let function = self.declarations.get(&name.span.start).and_then(|symbol| self.functions.get(symbol));
+                match function {
+                    Some(&function) => {
+                        let caller = std::mem::replace(&mut self.caller, function);
+                        self.expr(*initializer);
+                        self.caller = caller;
+                    },
+                    None => self.expr(*initializer),
+                }
+            },
+            Stmt::While { keyword: _, condition, body } => {
+                self.expr(*condition);
+                self.stmt(*body);
+            },
+            Stmt::For { keyword: _, initializer, condition, increment, body } => {
+                if let Some(initializer) = initializer {
+                    self.stmt(*initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expr(*condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(*increment);
+                }
+                self.stmt(*body);
+            },
+            Stmt::Variable { name: _, initializer: None } | Stmt::Import { keyword: _, module: _ } | Stmt::Break {}
+            | Stmt::Debugger { keyword: _ } | Stmt::Error { tokens: _ } => {},
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        match &ast[expr] {
+            Expr::Call { callee, paren: _, arguments } => {
+                if let Expr::Variable { name } = &ast[*callee] {
+                    self.call(name);
+                }
+                self.expr(*callee);
+                for &argument in arguments {
+                    self.expr(argument);
+                }
+            },
+            Expr::Assign { name: _, value } => self.expr(*value),
+            Expr::Binary { .. } | Expr::Logical { .. } => {
+                for operand in ast.chain_operands(expr) {
+                    self.expr(operand);
+                }
+            },
+            Expr::Grouping { expression } | Expr::Unary { operator: _, right: expression } => self.expr(*expression),
+            Expr::Block { statements, value } => {
+                for &statement in statements {
+                    self.stmt(statement);
+                }
+                if let Some(value) = value {
+                    self.expr(*value);
+                }
+            },
+            Expr::If { keyword: _, condition, then_branch, else_branch } => {
+                self.expr(*condition);
+                self.expr(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expr(*else_branch);
+                }
+            },
+            Expr::Lambda { params: _, arrow: _, body } => self.expr(*body),
+            Expr::Literal { value: _ } | Expr::Variable { name: _ } | Expr::Error { token: _ } => {},
+        }
+    }
+
+    fn call(&mut self, name: &Token) {
+        let symbol = self.references.get(&name.span.start).copied().flatten();
+        let callee = match symbol {
+            Some(symbol) => match self.functions.get(&symbol) {
+                Some(&function) => Callee::Function(function),
+                None => Callee::Variable(name.lexeme.to_string()),
+            },
+            None if self.natives.iter().any(|native| *native == *name.lexeme) => Callee::Native(name.lexeme.to_string()),
+            None => Callee::Unknown(name.lexeme.to_string()),
+        };
+        self.graph.calls.push(Call { caller: self.caller, callee, line: name.line, column: name.column });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+import native \"time\";
+var square = (x) => x * x;
+var sum = (n) => {
+    var helper = (i) => square(i) + square(i);
+    helper(n)
+};
+var apply = (f) => f(1);
+print sum(3) + clock() + missing();
+";
+
+    #[test]
+    fn calls() {
+        let graph = CallGraph::build(SOURCE).unwrap();
+        let names: Vec<&str> = graph.functions.iter().map(|function| &*function.name).collect();
+        assert_eq!(names, vec![SCRIPT, "square", "sum", "helper", "apply"]);
+
+        let calls: Vec<(FunctionId, &Callee, i32)> = graph.calls.iter().map(|call| (call.caller, &call.callee, call.line)).collect();
+        assert_eq!(calls, vec![
+            (3, &Callee::Function(1), 4),
+            (3, &Callee::Function(1), 4),
+            (2, &Callee::Function(3), 5),
+            (4, &Callee::Variable(String::from("f")), 7),
+            (0, &Callee::Function(2), 8),
+            (0, &Callee::Native(String::from("clock")), 8),
+            (0, &Callee::Unknown(String::from("missing")), 8),
+        ]);
+        assert_eq!(graph.unknown_calls().count(), 1);
+    }
+
+    #[test]
+    fn formats() {
+        let graph = CallGraph::build(SOURCE).unwrap();
+        assert_eq!(graph.to_text(), "\
+helper (line 4) -> square (line 2) on line 4, 4
+sum (line 3) -> helper (line 4) on line 5
+apply (line 7) -> f (variable) on line 7
+<script> -> sum (line 3) on line 8
+<script> -> clock (native) on line 8
+<script> -> missing (unknown) on line 8
+");
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph calls {\n"));
+        assert!(dot.contains("    f0 [label=\"<script>\"];\n    f1 [label=\"square (line 2)\"];\n"));
+        assert!(dot.contains("    f3 -> f1;\n"));
+        assert!(dot.contains("    n2 [label=\"missing\", shape=ellipse, color=red, fontcolor=red];\n    f0 -> n2;\n"));
+        assert_eq!(dot.matches("f3 -> f1").count(), 1);
+    }
+}
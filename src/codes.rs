@@ -0,0 +1,297 @@
+//! Stable codes for every error, so they can be looked up and searched for, and tests can
+//! check which error happened without matching on its message. Codes starting with `E0`
+//! are found before the program runs, by the scanner or the parser, and `E1` ones while it
+//! runs. Lints have `W` codes, see `Lint::code`.
+//!
+//! `jlox --explain <code>` prints the longer explanation of a code.
+
+use crate::lint::Lint;
+
+use std::fmt;
+
+/// What kind of error a diagnostic is. A code never changes meaning once it is given out;
+/// errors that go away keep their code unused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    UnterminatedString,
+    UnexpectedCharacter,
+    ExpectedExpression,
+    ExpectedToken,
+    BreakOutsideLoop,
+    TooDeeplyNested,
+    TooManyArguments,
+    InvalidAssignmentTarget,
+    MissingLeftOperand,
+    LiteralWithoutValue,
+    OperandNotNumber,
+    OperandTypes,
+    DivisionByZero,
+    UndefinedVariable,
+    PreludeAssignment,
+    NotCallable,
+    WrongArgumentCount,
+    StackOverflow,
+    NativeFunction,
+    UnknownModule,
+    FuelExhausted,
+    Interrupted,
+    MemoryLimit,
+    ParseErrorReached,
+    Internal,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 25] = [
+        ErrorCode::UnterminatedString, ErrorCode::UnexpectedCharacter, ErrorCode::ExpectedExpression,
+        ErrorCode::ExpectedToken, ErrorCode::BreakOutsideLoop, ErrorCode::TooDeeplyNested,
+        ErrorCode::TooManyArguments, ErrorCode::InvalidAssignmentTarget, ErrorCode::MissingLeftOperand,
+        ErrorCode::LiteralWithoutValue, ErrorCode::OperandNotNumber, ErrorCode::OperandTypes,
+        ErrorCode::DivisionByZero, ErrorCode::UndefinedVariable, ErrorCode::PreludeAssignment,
+        ErrorCode::NotCallable, ErrorCode::WrongArgumentCount, ErrorCode::StackOverflow,
+        ErrorCode::NativeFunction, ErrorCode::UnknownModule, ErrorCode::FuelExhausted,
+        ErrorCode::Interrupted, ErrorCode::MemoryLimit, ErrorCode::ParseErrorReached, ErrorCode::Internal,
+    ];
+
+    /// The code as it is shown, like `E1004`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UnterminatedString => "E0001",
+            ErrorCode::UnexpectedCharacter => "E0002",
+            ErrorCode::ExpectedExpression => "E0101",
+            ErrorCode::ExpectedToken => "E0102",
+            ErrorCode::BreakOutsideLoop => "E0103",
+            ErrorCode::TooDeeplyNested => "E0104",
+            ErrorCode::TooManyArguments => "E0105",
+            ErrorCode::InvalidAssignmentTarget => "E0106",
+            ErrorCode::MissingLeftOperand => "E0107",
+            ErrorCode::LiteralWithoutValue => "E0108",
+            ErrorCode::OperandNotNumber => "E1001",
+            ErrorCode::OperandTypes => "E1002",
+            ErrorCode::DivisionByZero => "E1003",
+            ErrorCode::UndefinedVariable => "E1004",
+            ErrorCode::PreludeAssignment => "E1005",
+            ErrorCode::NotCallable => "E1006",
+            ErrorCode::WrongArgumentCount => "E1007",
+            ErrorCode::StackOverflow => "E1008",
+            ErrorCode::NativeFunction => "E1009",
+            ErrorCode::UnknownModule => "E1010",
+            ErrorCode::FuelExhausted => "E1011",
+            ErrorCode::Interrupted => "E1012",
+            ErrorCode::MemoryLimit => "E1013",
+            ErrorCode::ParseErrorReached => "E1014",
+            ErrorCode::Internal => "E1015",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL.into_iter().find(|error| error.as_str().eq_ignore_ascii_case(code))
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            ErrorCode::UnterminatedString => "Unterminated string",
+            ErrorCode::UnexpectedCharacter => "Unexpected character",
+            ErrorCode::ExpectedExpression => "Expected an expression",
+            ErrorCode::ExpectedToken => "Expected a different token",
+            ErrorCode::BreakOutsideLoop => "'break' outside of a loop",
+            ErrorCode::TooDeeplyNested => "Nested too deeply",
+            ErrorCode::TooManyArguments => "Too many parameters or arguments",
+            ErrorCode::InvalidAssignmentTarget => "Invalid assignment target",
+            ErrorCode::MissingLeftOperand => "Binary operator without left operand",
+            ErrorCode::LiteralWithoutValue => "Literal without a value",
+            ErrorCode::OperandNotNumber => "Operand must be a number",
+            ErrorCode::OperandTypes => "Operands have the wrong types",
+            ErrorCode::DivisionByZero => "Division by zero",
+            ErrorCode::UndefinedVariable => "Undefined variable",
+            ErrorCode::PreludeAssignment => "Assignment to a prelude variable",
+            ErrorCode::NotCallable => "Called something that is not a function",
+            ErrorCode::WrongArgumentCount => "Wrong number of arguments",
+            ErrorCode::StackOverflow => "Stack overflow",
+            ErrorCode::NativeFunction => "Native function failed",
+            ErrorCode::UnknownModule => "Unknown native module",
+            ErrorCode::FuelExhausted => "Fuel exhausted",
+            ErrorCode::Interrupted => "Interrupted",
+            ErrorCode::MemoryLimit => "Memory limit exceeded",
+            ErrorCode::ParseErrorReached => "Ran code that failed to parse",
+            ErrorCode::Internal => "Internal error",
+        }
+    }
+
+    /// What the error means and how to fix it.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            ErrorCode::UnterminatedString => "\
+A string was opened with `\"` but the file ended before the closing `\"`. Strings may
+span several lines, so the missing quote can be far above where the file ends.",
+            ErrorCode::UnexpectedCharacter => "\
+The scanner found a character that isn't part of any token, like `@` or `#`. Remove it,
+or put it inside a string if it was meant as text.",
+            ErrorCode::ExpectedExpression => "\
+The parser needed a value here, like a number, a string, a variable or a parenthesized
+expression, but found something else. Often an operator is missing its right operand.",
+            ErrorCode::ExpectedToken => "\
+The parser needed a specific token, usually punctuation like `;`, `)` or `}`, and found
+another one. The message says which token was expected and after what.",
+            ErrorCode::BreakOutsideLoop => "\
+`break` leaves the innermost `while` or `for` loop, so it can only be used inside one.",
+            ErrorCode::TooDeeplyNested => "\
+Statements and expressions can only be nested so deep, which keeps the parser and the
+interpreter from running out of stack. Split them up with variables or functions.",
+            ErrorCode::TooManyArguments => "\
+A function can have at most 255 parameters, and a call can pass at most 255 arguments.",
+            ErrorCode::InvalidAssignmentTarget => "\
+Only a variable can be assigned to. The left side of `=` was some other expression.",
+            ErrorCode::MissingLeftOperand => "\
+A binary operator like `*` or `==` started an expression, so it has nothing on its left.
+The parser skips the operator and parses its right operand to keep going.",
+            ErrorCode::LiteralWithoutValue => "\
+A number or string token carried no value. Tokens from the scanner always have one, so
+this only happens with tokens built by hand and handed to the parser.",
+            ErrorCode::OperandNotNumber => "\
+The unary `-` and `!` operators only work on numbers, but got another kind of value.",
+            ErrorCode::OperandTypes => "\
+A binary operator got values of kinds it doesn't work on. Arithmetic and comparisons
+take numbers, `+` takes two numbers or two strings, and `==` and `!=` compare values of
+the same kind.",
+            ErrorCode::DivisionByZero => "\
+The right operand of `/` was 0. Check the divisor before dividing. In the jlox dialect
+this isn't an error and gives infinity or NaN like the book's interpreter.",
+            ErrorCode::UndefinedVariable => "\
+A variable was read or assigned before any `var` declared it, or outside of the scope
+that declared it. Declare the variable first, and check the name for typos; the message
+suggests a close name if there is one in scope.",
+            ErrorCode::PreludeAssignment => "\
+Variables from a prelude the host set up are shared between scripts, so a script can't
+assign to them. Declare a variable of its own with the same name with `var` instead.",
+            ErrorCode::NotCallable => "\
+Only functions can be called, but the value before `(` was something else.",
+            ErrorCode::WrongArgumentCount => "\
+A function was called with more or fewer arguments than it has parameters.",
+            ErrorCode::StackOverflow => "\
+Calls were nested deeper than the maximum call depth, which is usually a recursive
+function that never stops calling itself. `--max-call-depth` raises the limit for
+programs that really need to go deeper.",
+            ErrorCode::NativeFunction => "\
+A function from a native module reported an error, most often because it was given an
+argument of the wrong kind. The message comes from the function and says what it expected.",
+            ErrorCode::UnknownModule => "\
+`import native` names a module that this interpreter doesn't have. The built-in modules
+are \"time\", \"strings\", \"io\", \"threads\", \"random\" and \"debug\"; hosts can register more.",
+            ErrorCode::FuelExhausted => "\
+The host gave the program a budget of steps with `Interpreter::set_fuel` and it used
+them all up, which stops programs that run too long, like endless loops.",
+            ErrorCode::Interrupted => "\
+The program was stopped from outside while it ran, for example with Ctrl-C in the REPL.",
+            ErrorCode::MemoryLimit => "\
+The program holds more memory than the host allowed with `Interpreter::set_memory_limit`.",
+            ErrorCode::ParseErrorReached => "\
+The program was run despite parse errors, and got to a part that didn't parse. Fix the
+parse errors reported for the same lines.",
+            ErrorCode::Internal => "\
+The interpreter got into a state it should never be in. Please report it as a bug, with
+the program that caused it.",
+        }
+    }
+
+    /// A program that fails with this error, if a script can cause it on its own.
+    pub fn example(self) -> Option<&'static str> {
+        match self {
+            ErrorCode::UnterminatedString => Some("print \"hello;"),
+            ErrorCode::UnexpectedCharacter => Some("var price = 3 @ 4;"),
+            ErrorCode::ExpectedExpression => Some("print 1 +;"),
+            ErrorCode::ExpectedToken => Some("print (1 + 2;"),
+            ErrorCode::BreakOutsideLoop => Some("break;"),
+            ErrorCode::TooDeeplyNested => None,
+            ErrorCode::TooManyArguments => None,
+            ErrorCode::InvalidAssignmentTarget => Some("var a = 1;\na + 1 = 3;"),
+            ErrorCode::MissingLeftOperand => Some("print * 2;"),
+            ErrorCode::LiteralWithoutValue => None,
+            ErrorCode::OperandNotNumber => Some("print -\"five\";"),
+            ErrorCode::OperandTypes => Some("print \"total: \" + 3;"),
+            ErrorCode::DivisionByZero => Some("var count = 0;\nprint 10 / count;"),
+            ErrorCode::UndefinedVariable => Some("print count;\nvar count = 1;"),
+            ErrorCode::PreludeAssignment => None,
+            ErrorCode::NotCallable => Some("var name = \"lox\";\nname();"),
+            ErrorCode::WrongArgumentCount => Some("var add = (a, b) => a + b;\nprint add(1);"),
+            ErrorCode::StackOverflow => Some("var forever = (n) => forever(n + 1);\nforever(0);"),
+            ErrorCode::NativeFunction => Some("import native \"strings\";\nappend(\"text\", \"more\");"),
+            ErrorCode::UnknownModule => Some("import native \"network\";"),
+            ErrorCode::FuelExhausted | ErrorCode::Interrupted | ErrorCode::MemoryLimit => None,
+            ErrorCode::ParseErrorReached | ErrorCode::Internal => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The text `jlox --explain` prints for an error or lint code, or `None` if there is no
+/// such code.
+pub fn explain(code: &str) -> Option<String> {
+    if let Some(error) = ErrorCode::from_code(code) {
+        let mut text = format!("{}: {}\n\n{}\n", error.as_str(), error.title(), error.explanation());
+        if let Some(example) = error.example() {
+            text.push_str(&format!("\nFor example:\n\n{}\n", indent(example)));
+        }
+        return Some(text);
+    }
+
+    let lint = Lint::from_code(code)?;
+    let default = if lint.enabled_by_default() { "on" } else { "off" };
+    Some(format!(
+        "{}: the {} lint, -W{} ({default} by default)\n\n{}\n\nFor example:\n\n{}\n",
+        lint.code(), lint.name(), lint.name(), lint.explanation(), indent(lint.example()),
+    ))
+}
+
+fn indent(source: &str) -> String {
+    source.lines().map(|line| format!("    {line}")).collect::<Vec<String>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LoxError;
+    use crate::interpreter::Interpreter;
+    use crate::lint;
+    use crate::parser;
+
+    use std::collections::HashSet;
+
+    fn code_of(source: &str) -> Option<&'static str> {
+        match Interpreter::new(false).eval(source) {
+            Ok(_) => None,
+            Err(LoxError::Compile(errors)) => Some(errors[0].code()),
+            Err(LoxError::Runtime(error)) => Some(error.code.as_str()),
+        }
+    }
+
+    #[test]
+    fn examples() {
+        for error in ErrorCode::ALL {
+            if let Some(example) = error.example() {
+                assert_eq!(code_of(example), Some(error.as_str()), "the example of {error}");
+            }
+        }
+        for lint in Lint::ALL {
+            let warnings = lint::check(&parser::parse_source(lint.example()).unwrap());
+            assert!(warnings.iter().any(|warning| warning.lint == lint), "the example of {}", lint.code());
+        }
+    }
+
+    #[test]
+    fn codes() {
+        let codes: HashSet<&str> = ErrorCode::ALL.into_iter().map(ErrorCode::as_str)
+            .chain(Lint::ALL.into_iter().map(Lint::code))
+            .collect();
+        assert_eq!(codes.len(), ErrorCode::ALL.len() + Lint::ALL.len());
+
+        assert_eq!(ErrorCode::from_code("e1004"), Some(ErrorCode::UndefinedVariable));
+        assert!(explain("E1004").unwrap().starts_with("E1004: Undefined variable\n"));
+        assert!(explain("W0001").unwrap().contains("-Wunused"));
+        assert_eq!(explain("E9999"), None);
+    }
+}
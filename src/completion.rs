@@ -0,0 +1,112 @@
+//! Names to complete at the cursor, as the REPL offers them on Tab: the variables defined
+//! so far in the session, those declared in the input being typed that are in scope at the
+//! cursor, and the keywords.
+
+use crate::codes::ErrorCode;
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::scanner::{self, Dialect, Scanner, TokenType};
+use crate::symbols::{Position, SymbolTable};
+
+/// The names an interpreter has defined, taken when the input starts, so completing
+/// doesn't need the interpreter itself.
+#[derive(Debug, Clone, Default)]
+pub struct Completions {
+    globals: Vec<String>,
+    dialect: Dialect,
+}
+
+impl Completions {
+    pub fn of(interpreter: &Interpreter) -> Self {
+        Self { globals: interpreter.globals().into_iter().map(|(name, _)| name).collect(), dialect: interpreter.dialect() }
+    }
+
+    /// Where the name being typed before `cursor` in `input` starts, as a byte offset, and
+    /// the names it could be completed to, sorted. There are none inside strings and comments.
+    pub fn complete(&self, input: &str, cursor: usize) -> (usize, Vec<String>) {
+        complete(&self.globals, self.dialect, input, cursor)
+    }
+}
+
+fn complete(globals: &[String], dialect: Dialect, input: &str, cursor: usize) -> (usize, Vec<String>) {
+    let before = &input[..cursor];
+    let start = before.char_indices().rev()
+        .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+        .last()
+        .map_or(cursor, |(offset, _)| offset);
+    let prefix = &before[start..];
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        return (start, Vec::new());
+    }
+
+    // The tokens before the name, to know whether it is code at all.
+    let (tokens, errors) = scanner::scan_tokens_with_comments(&before[..start]);
+    let in_string = errors.iter().any(|error| error.code == ErrorCode::UnterminatedString);
+    // A comment runs to the end of its line.
+    let in_comment = tokens.iter().rev().nth(1)
+        .is_some_and(|token| token.token_type == TokenType::Comment && !before[token.span.end..start].contains('\n'));
+    if in_string || in_comment {
+        return (start, Vec::new());
+    }
+
+    let mut names: Vec<String> = globals.to_vec();
+    names.extend(scanner::keywords(dialect).into_keys());
+
+    // The input up to the cursor is usually unfinished, so it is closed off to parse.
+    let mut open: Vec<TokenType> = Vec::new();
+    for token in &tokens {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace => open.push(token.token_type),
+            TokenType::RightParen | TokenType::RightBrace => { open.pop(); },
+            _ => {},
+        }
+    }
+    let mut closed = before.to_string();
+    for &bracket in open.iter().rev() {
+        closed.push_str(if bracket == TokenType::LeftParen { ")" } else { "; }" });
+    }
+    closed.push(';');
+
+    let (ast, _) = Parser::from_scanner(Scanner::new(&closed).with_dialect(dialect)).parse_lossy();
+    let table = SymbolTable::build(&ast);
+    let line_start = before[..start].rfind('\n').map_or(0, |newline| newline + 1);
+    let position = Position {
+        line: before[..start].matches('\n').count() as i32 + 1,
+        column: before[line_start..start].chars().count() + 1,
+    };
+    names.extend(table.visible_at(position).into_iter().map(|symbol| table.symbols[symbol].name.lexeme.to_string()));
+
+    names.retain(|name| name.starts_with(prefix));
+    names.sort();
+    names.dedup();
+    (start, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(completions: &Completions, input: &str) -> Vec<String> {
+        completions.complete(input, input.len()).1
+    }
+
+    #[test]
+    fn names() {
+        let mut interpreter = Interpreter::new(true);
+        interpreter.eval("var total = 1; var toggle = () => !total; import native \"time\";").unwrap();
+        let completions = Completions::of(&interpreter);
+
+        assert_eq!(completions.complete("print to", 8), (6, vec![String::from("toggle"), String::from("total")]));
+        assert_eq!(candidates(&completions, "cl"), vec!["class", "clock"]);
+        assert_eq!(candidates(&completions, "wh"), vec!["while"]);
+
+        // Names declared in the input, where they are in scope.
+        assert_eq!(candidates(&completions, "var count = 2; { var counter = 3; print coun"), vec!["count", "counter"]);
+        assert!(candidates(&completions, "{ var counter = 3; } print coun").is_empty());
+        assert_eq!(candidates(&completions, "var f = (items, index) => f(it"), vec!["items"]);
+
+        assert!(candidates(&completions, "print \"to").is_empty());
+        assert!(candidates(&completions, "print 1; // to").is_empty());
+        assert!(candidates(&completions, "print 1").is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+//! Checks the interpreter against the test suite of the book's reference implementations,
+//! as found in the `test` directory of a craftinginterpreters checkout. Tests run in the
+//! `jlox` dialect, and comments in each test say what it should do:
+//!
+//! - `// expect: text` is the next line it prints,
+//! - `// expect runtime error: message` is the runtime error that stops it on that line,
+//! - `// Error at 'x': message` is a compile error on that line, and
+//!   `// [line N] Error ...` or `// [java line N] Error ...` one on line N.
+//!
+//! Like upstream's `test.py`, a chapter decides which tests apply: the ones that only use
+//! what the interpreter has by the end of that chapter.
+
+use crate::error::LoxError;
+use crate::interpreter::Interpreter;
+use crate::scanner::{Dialect, Scanner, TokenType};
+
+// Directories of tests for other stages of the book or for clox alone.
+const SKIPPED_DIRECTORIES: [&str; 4] = ["benchmark", "expressions", "limit", "scanning"];
+
+// Tests whose error only the resolver from chapter 11 reports.
+const NEEDS_RESOLVER: [&str; 2] = ["variable/duplicate_local.lox", "variable/use_local_in_initializer.lox"];
+
+// Keeps a test that never stops from hanging the run.
+const FUEL: u64 = 10_000_000;
+
+/// The chapter of the book whose jlox the tests are run as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chapter {
+    /// Chapter 8, "Statements and State".
+    Statements,
+    /// Chapter 9, "Control Flow", the last one this interpreter has everything for.
+    ControlFlow,
+}
+
+impl Chapter {
+    pub fn from_number(number: u32) -> Option<Chapter> {
+        match number {
+            8 => Some(Chapter::Statements),
+            9 => Some(Chapter::ControlFlow),
+            _ => None,
+        }
+    }
+
+    // Tokens of the features jlox doesn't have yet by the end of the chapter. A `.` is
+    // only ever a property access.
+    fn missing(self) -> &'static [TokenType] {
+        const LATER: [TokenType; 12] = [
+            TokenType::If, TokenType::Else, TokenType::While, TokenType::For, TokenType::And, TokenType::Or,
+            TokenType::Fun, TokenType::Return, TokenType::Class, TokenType::This, TokenType::Super, TokenType::Dot,
+        ];
+        match self {
+            Chapter::Statements => &LATER,
+            Chapter::ControlFlow => &LATER[6..],
+        }
+    }
+}
+
+/// Whether the test at `path`, relative to the test directory, applies to `chapter`.
+pub fn applies(path: &str, source: &str, chapter: Chapter) -> bool {
+    let path = path.replace('\\', "/");
+    if SKIPPED_DIRECTORIES.iter().any(|directory| path.starts_with(&format!("{directory}/"))) || NEEDS_RESOLVER.contains(&path.as_str()) {
+        return false;
+    }
+
+    let jlox = Dialect { jlox: true, ..Dialect::default() };
+    Scanner::new(source).with_dialect(jlox)
+        .filter_map(Result::ok)
+        .all(|token| !chapter.missing().contains(&token.token_type))
+}
+
+/// What a test says it should do.
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    /// As jlox prints them, like `[line 3] Error at 'x': message`.
+    pub compile_errors: Vec<String>,
+    /// The line and message.
+    pub runtime_error: Option<(i32, String)>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+
+        for (line, text) in (1..).zip(source.lines()) {
+            let Some((_, comment)) = text.split_once("// ") else { continue; };
+
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expectations.runtime_error = Some((line, message.to_string()));
+            } else if comment.starts_with("Error") {
+                expectations.compile_errors.push(format!("[line {line}] {comment}"));
+            } else if let Some(error) = comment.strip_prefix('[') {
+                // Errors only clox reports are for the other implementation.
+                if error.starts_with("c line ") {
+                    continue;
+                }
+                let error = error.strip_prefix("java ").unwrap_or(error);
+                if error.starts_with("line ") && error.contains("] Error") {
+                    expectations.compile_errors.push(format!("[{error}"));
+                }
+            }
+        }
+
+        expectations
+    }
+}
+
+/// Runs a test and describes every way it fell short of its expectations.
+pub fn run(source: &str) -> Result<(), Vec<String>> {
+    let expected = Expectations::parse(source);
+
+    let mut interpreter = Interpreter::new(false);
+    interpreter.capture_output();
+    interpreter.set_dialect(Dialect { jlox: true, ..Dialect::default() });
+    interpreter.set_fuel(Some(FUEL));
+    let result = interpreter.eval(source);
+
+    let mut failures: Vec<String> = Vec::new();
+    let output = interpreter.captured_output();
+    for index in 0..expected.output.len().max(output.len()) {
+        match (expected.output.get(index), output.get(index)) {
+            (Some(expected), Some(actual)) if expected == actual => {},
+            (Some(expected), Some(actual)) => failures.push(format!("Expected output '{expected}' but got '{actual}'.")),
+            (Some(expected), None) => failures.push(format!("Missing output '{expected}'.")),
+            (None, Some(actual)) => failures.push(format!("Unexpected output '{actual}'.")),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    let compile_errors: Vec<String> = match &result {
+        Err(LoxError::Compile(errors)) => errors.iter().map(ToString::to_string).collect(),
+        _ => Vec::new(),
+    };
+    if compile_errors != expected.compile_errors {
+        failures.push(format!("Expected compile errors {:?} but got {:?}.", expected.compile_errors, compile_errors));
+    }
+
+    let runtime_error = match result {
+        Err(LoxError::Runtime(error)) => Some((error.line, error.message)),
+        _ => None,
+    };
+    if runtime_error != expected.runtime_error {
+        failures.push(format!("Expected runtime error {:?} but got {:?}.", expected.runtime_error, runtime_error));
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expectations() {
+        let source = "print 1; // expect: 1\n\
+                      var a = b; // expect runtime error: Undefined variable 'b'.\n\
+                      print; // Error at ';': Expect expression.\n\
+                      // [java line 5] Error at end: Expect ';' after value.\n\
+                      // [c line 5] Error at end: Expect ';' after value.";
+
+        assert_eq!(Expectations::parse(source), Expectations {
+            output: vec![String::from("1")],
+            compile_errors: vec![String::from("[line 3] Error at ';': Expect expression."), String::from("[line 5] Error at end: Expect ';' after value.")],
+            runtime_error: Some((2, String::from("Undefined variable 'b'."))),
+        });
+    }
+
+    #[test]
+    fn chapters() {
+        assert!(applies("while/syntax.lox", "while (false) print 1;", Chapter::ControlFlow));
+        assert!(!applies("while/syntax.lox", "while (false) print 1;", Chapter::Statements));
+        assert!(!applies("function/empty_body.lox", "fun f() {}", Chapter::ControlFlow));
+        assert!(!applies("scanning/numbers.lox", "1;", Chapter::ControlFlow));
+    }
+
+    #[test]
+    fn run_tests() {
+        assert!(run("print 1 + 2; // expect: 3\nprint 1 / 0; // expect: Infinity").is_ok());
+        assert!(run("print -nil; // expect runtime error: Operand must be a number.").is_ok());
+
+        let failures = run("print 1; // expect: 2").unwrap_err();
+        assert_eq!(failures, vec![String::from("Expected output '2' but got '1'.")]);
+    }
+}
@@ -0,0 +1,374 @@
+//! A concrete syntax tree, which keeps every byte of the source: tokens along with the
+//! whitespace, comments and stray characters between them. Tools that rewrite or color
+//! source work on it, and `SyntaxNode::text` gives back exactly what was parsed.
+//!
+//! The tree is laid over the `Ast` the parser builds: each node covers the span of one
+//! statement or expression and knows which, so a tool can go from what it finds in the
+//! tree to the `Stmt` or `Expr` the interpreter runs. Tokens that belong to no smaller
+//! node, like a statement's `;`, sit in the node of the statement. A chain of operators
+//! like `a + b + c` is a single node, with every operand as a child.
+
+use crate::error::CompileError;
+use crate::parser;
+use crate::scanner::{Scanner, Token, TokenType};
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+use std::ops::Range;
+
+/// What a node of the tree is: the whole program, or the statement or expression of the
+/// same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    Program,
+    BlockStmt,
+    ExpressionStmt,
+    IfStmt,
+    PrintStmt,
+    VariableStmt,
+    WhileStmt,
+    ForStmt,
+    BreakStmt,
+    ImportStmt,
+    DebuggerStmt,
+    ErrorStmt,
+    AssignExpr,
+    BinaryExpr,
+    CallExpr,
+    GroupingExpr,
+    LiteralExpr,
+    LogicalExpr,
+    UnaryExpr,
+    VariableExpr,
+    BlockExpr,
+    IfExpr,
+    LambdaExpr,
+    ErrorExpr,
+}
+
+/// The statement or expression a node stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeId {
+    Stmt(StmtId),
+    Expr(ExprId),
+}
+
+/// What a token of the tree is. Everything that isn't a `Token` is trivia, which the
+/// parser never sees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Token(TokenType),
+    Whitespace,
+    Comment,
+    /// Characters the scanner rejected, like `@` or a string that never ends.
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Byte offsets in the source.
+    pub range: Range<usize>,
+}
+
+impl SyntaxToken {
+    pub fn is_trivia(&self) -> bool {
+        !matches!(self.kind, TokenKind::Token(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    /// `None` for the program.
+    pub id: Option<NodeId>,
+    /// Byte offsets in the source.
+    pub range: Range<usize>,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    /// The source the node was parsed from, byte for byte.
+    pub fn text(&self) -> String {
+        self.tokens().iter().map(|token| token.text.as_str()).collect()
+    }
+
+    /// Every token under the node, trivia included, in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens: Vec<&SyntaxToken> = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    /// The nodes right under this one.
+    pub fn child_nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+}
+
+/// A program's concrete syntax tree, with the `Ast` its nodes refer to.
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    pub root: SyntaxNode,
+    pub ast: Ast,
+    /// The scan and parse errors; the tree covers the whole source either way.
+    pub errors: Vec<CompileError>,
+}
+
+impl SyntaxTree {
+    /// The `Ast` for the interpreter, which only has one when there were no errors.
+    pub fn into_ast(self) -> Result<Ast, Vec<CompileError>> {
+        if self.errors.is_empty() {
+            Ok(self.ast)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Parses `source` into a tree that holds all of it, even the parts with errors.
+pub fn parse(source: &str) -> SyntaxTree {
+    let (ast, errors) = parser::parse_source_lossy(source);
+    let tokens: Vec<Token> = Scanner::new(source)
+        .filter_map(Result::ok)
+        .filter(|token| token.token_type != TokenType::EOF)
+        .collect();
+
+    let mut builder = Builder { source, ast: &ast, tokens, next_token: 0, position: 0 };
+    let statements = ast.statements.iter().map(|&statement| NodeId::Stmt(statement)).collect();
+    let root = builder.node(SyntaxKind::Program, None, source.len(), statements);
+
+    SyntaxTree { root, ast, errors }
+}
+
+struct Builder<'a> {
+    source: &'a str,
+    ast: &'a Ast,
+    tokens: Vec<Token>,
+    next_token: usize,
+    // Everything before it is in the tree already.
+    position: usize,
+}
+
+impl Builder<'_> {
+    // The node from `position` to `end`, with `children` as its nodes and everything
+    // between them as its tokens.
+    fn node(&mut self, kind: SyntaxKind, id: Option<NodeId>, end: usize, children: Vec<NodeId>) -> SyntaxNode {
+        let start = self.position;
+        let mut elements: Vec<SyntaxElement> = Vec::new();
+        let mut children = children.into_iter().peekable();
+
+        while self.position < end {
+            // Children start where one of their tokens does, so trivia before them stays here.
+            while children.peek().is_some_and(|&child| self.range(child).start < self.position || self.range(child).is_empty()) {
+                children.next();
+            }
+            match children.peek() {
+                Some(&child) if self.range(child).start == self.position => {
+                    children.next();
+                    elements.push(SyntaxElement::Node(self.child(child)));
+                },
+                next => {
+                    let limit = next.map_or(end, |&child| self.range(child).start.min(end));
+                    elements.push(SyntaxElement::Token(self.token(limit)));
+                },
+            }
+        }
+
+        SyntaxNode { kind, id, range: start..self.position, children: elements }
+    }
+
+    fn child(&mut self, id: NodeId) -> SyntaxNode {
+        let end = self.range(id).end;
+        let (kind, children) = match id {
+            NodeId::Stmt(stmt) => stmt_children(self.ast, stmt),
+            NodeId::Expr(expr) => expr_children(self.ast, expr),
+        };
+        self.node(kind, Some(id), end, children)
+    }
+
+    fn range(&self, id: NodeId) -> Range<usize> {
+        let span = match id {
+            NodeId::Stmt(stmt) => self.ast.stmt_span(stmt),
+            NodeId::Expr(expr) => self.ast.expr_span(expr),
+        };
+        span.start..span.end
+    }
+
+    // The token at `position`, or the trivia from there to the next token or `limit`.
+    fn token(&mut self, limit: usize) -> SyntaxToken {
+        while self.tokens.get(self.next_token).is_some_and(|token| token.span.start < self.position) {
+            self.next_token += 1;
+        }
+
+        let start = self.position;
+        let (kind, end) = match self.tokens.get(self.next_token) {
+            Some(token) if token.span.start == start => {
+                self.next_token += 1;
+                (TokenKind::Token(token.token_type), token.span.end)
+            },
+            next => {
+                let limit = next.map_or(limit, |token| token.span.start.min(limit));
+                trivia(&self.source[start..limit])
+            },
+        };
+
+        let end = if matches!(kind, TokenKind::Token(_)) { end } else { start + end };
+        self.position = end;
+        SyntaxToken { kind, text: self.source[start..end].to_string(), range: start..end }
+    }
+}
+
+// The kind and length of the trivia `text` starts with.
+fn trivia(text: &str) -> (TokenKind, usize) {
+    let length_while = |predicate: fn(char) -> bool| text.find(|c: char| !predicate(c)).unwrap_or(text.len());
+
+    if text.starts_with(char::is_whitespace) {
+        (TokenKind::Whitespace, length_while(char::is_whitespace))
+    } else if text.starts_with("//") {
+        (TokenKind::Comment, text.find('\n').unwrap_or(text.len()))
+    } else if text.starts_with('"') {
+        // A string without its closing quote runs to the end of the source.
+        (TokenKind::Invalid, text.len())
+    } else {
+        (TokenKind::Invalid, text.chars().next().map_or(0, char::len_utf8))
+    }
+}
+
+fn stmt_children(ast: &Ast, stmt: StmtId) -> (SyntaxKind, Vec<NodeId>) {
+    let exprs = |exprs: &[Option<ExprId>]| exprs.iter().flatten().map(|&expr| NodeId::Expr(expr)).collect::<Vec<NodeId>>();
+    let stmts = |stmts: &[StmtId]| stmts.iter().map(|&stmt| NodeId::Stmt(stmt)).collect::<Vec<NodeId>>();
+
+    match &ast[stmt] {
+        Stmt::Block { statements } => (SyntaxKind::BlockStmt, stmts(statements)),
+        Stmt::Expression { expression } => (SyntaxKind::ExpressionStmt, exprs(&[Some(*expression)])),
+        Stmt::If { condition, then_branch, else_branch, .. } => {
+            let mut children = exprs(&[Some(*condition)]);
+            children.extend(stmts(&[*then_branch]));
+            children.extend(else_branch.map(NodeId::Stmt));
+            (SyntaxKind::IfStmt, children)
+        },
+        Stmt::Print { expression, .. } => (SyntaxKind::PrintStmt, exprs(&[Some(*expression)])),
+        Stmt::Variable { initializer, .. } => (SyntaxKind::VariableStmt, exprs(&[*initializer])),
+        Stmt::While { condition, body, .. } => {
+            let mut children = exprs(&[Some(*condition)]);
+            children.extend(stmts(&[*body]));
+            (SyntaxKind::WhileStmt, children)
+        },
+        Stmt::For { initializer, condition, increment, body, .. } => {
+            let mut children: Vec<NodeId> = initializer.map(NodeId::Stmt).into_iter().collect();
+            children.extend(exprs(&[*condition, *increment]));
+            children.extend(stmts(&[*body]));
+            (SyntaxKind::ForStmt, children)
+        },
+        Stmt::Break {} => (SyntaxKind::BreakStmt, Vec::new()),
+        Stmt::Import { .. } => (SyntaxKind::ImportStmt, Vec::new()),
+        Stmt::Debugger { .. } => (SyntaxKind::DebuggerStmt, Vec::new()),
+        Stmt::Error { .. } => (SyntaxKind::ErrorStmt, Vec::new()),
+    }
+}
+
+fn expr_children(ast: &Ast, expr: ExprId) -> (SyntaxKind, Vec<NodeId>) {
+    let exprs = |exprs: &[Option<ExprId>]| exprs.iter().flatten().map(|&expr| NodeId::Expr(expr)).collect::<Vec<NodeId>>();
+
+    match &ast[expr] {
+        Expr::Assign { value, .. } => (SyntaxKind::AssignExpr, exprs(&[Some(*value)])),
+        // A whole chain like `a + b + c` is one node, which keeps long ones from making
+        // the tree thousands of levels deep.
+        Expr::Binary { .. } => (SyntaxKind::BinaryExpr, ast.chain_operands(expr).into_iter().map(NodeId::Expr).collect()),
+        Expr::Call { callee, arguments, .. } => {
+            let mut children = exprs(&[Some(*callee)]);
+            children.extend(arguments.iter().map(|&argument| NodeId::Expr(argument)));
+            (SyntaxKind::CallExpr, children)
+        },
+        Expr::Grouping { expression } => (SyntaxKind::GroupingExpr, exprs(&[Some(*expression)])),
+        Expr::Literal { .. } => (SyntaxKind::LiteralExpr, Vec::new()),
+        Expr::Logical { .. } => (SyntaxKind::LogicalExpr, ast.chain_operands(expr).into_iter().map(NodeId::Expr).collect()),
+        Expr::Unary { right, .. } => (SyntaxKind::UnaryExpr, exprs(&[Some(*right)])),
+        Expr::Variable { .. } => (SyntaxKind::VariableExpr, Vec::new()),
+        Expr::Block { statements, value } => {
+            let mut children: Vec<NodeId> = statements.iter().map(|&statement| NodeId::Stmt(statement)).collect();
+            children.extend(exprs(&[*value]));
+            (SyntaxKind::BlockExpr, children)
+        },
+        Expr::If { condition, then_branch, else_branch, .. } => {
+            (SyntaxKind::IfExpr, exprs(&[Some(*condition), Some(*then_branch), *else_branch]))
+        },
+        Expr::Lambda { body, .. } => (SyntaxKind::LambdaExpr, exprs(&[Some(*body)])),
+        Expr::Error { .. } => (SyntaxKind::ErrorExpr, Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, Value};
+
+    #[test]
+    fn lossless() {
+        let sources = [
+            "",
+            "  // only a comment",
+            "var a = 1; // one\n\n{\n\tprint a  +  2;\n}\n",
+            "var f = (x, y) => { var z = x * y; z };\nprint f(2, 3);",
+            "print 1 @ 2;\nprint \"é\" + \"ü\";\nvar s = \"never ends",
+            "print (1;\nvar = 2;\nfor (var i = 0; i < 3; i = i + 1) print i;",
+        ];
+        for source in sources {
+            let tree = parse(source);
+            assert_eq!(tree.root.text(), source);
+            assert_eq!(tree.root.range, 0..source.len());
+            for token in tree.root.tokens() {
+                assert_eq!(&source[token.range.clone()], token.text);
+            }
+        }
+    }
+
+    #[test]
+    fn nodes() {
+        let tree = parse("print 1 + 2; // three\nvar a;");
+        let statements: Vec<&SyntaxNode> = tree.root.child_nodes().collect();
+        assert_eq!(statements.iter().map(|node| node.kind).collect::<Vec<_>>(), vec![SyntaxKind::PrintStmt, SyntaxKind::VariableStmt]);
+
+        // The comment and line break between the statements belong to the program.
+        assert_eq!(statements[0].text(), "print 1 + 2;");
+        let kinds: Vec<TokenKind> = tree.root.children.iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Token(token) => Some(token.kind),
+                SyntaxElement::Node(_) => None,
+            })
+            .collect();
+        assert_eq!(kinds, vec![TokenKind::Whitespace, TokenKind::Comment, TokenKind::Whitespace]);
+
+        let binary = statements[0].child_nodes().next().unwrap();
+        assert_eq!((binary.kind, binary.text().as_str()), (SyntaxKind::BinaryExpr, "1 + 2"));
+        let Some(NodeId::Expr(expr)) = binary.id else { panic!("expected an expression") };
+        assert!(matches!(tree.ast[expr], Expr::Binary { .. }));
+    }
+
+    #[test]
+    fn into_ast() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.interpret(&parse("var a = 2 * 3;").into_ast().unwrap()).unwrap();
+        assert_eq!(interpreter.eval("a;").unwrap(), Value::Number(6.0));
+        assert!(parse("var = 1;").into_ast().is_err());
+    }
+}
@@ -0,0 +1,369 @@
+//! A Debug Adapter Protocol server, started with `jlox dap`, so editors like VS Code can
+//! run a script with line breakpoints, step through statements and inspect variables.
+//!
+//! Messages are JSON with a `Content-Length` header, read from stdin and written to
+//! stdout. The program's own output is forwarded as `output` events, since stdout belongs
+//! to the protocol. Stepping is built on the interpreter's observer API: the debugger
+//! pauses inside `before_statement`, or `breakpoint` for a `debugger;` statement, and
+//! answers requests until it is told to go on. What it shows of the stack and variables
+//! comes from the interpreter's `CallStack`.
+
+use crafting_interpreters_rust::environment::Environment;
+use crafting_interpreters_rust::interpreter::Interpreter;
+use crafting_interpreters_rust::native::DebugModule;
+use crafting_interpreters_rust::observer::Observer;
+use crafting_interpreters_rust::source::read_source;
+use crafting_interpreters_rust::stack::{CallStack, FrameView};
+use crafting_interpreters_rust::syntax::{Ast, Stmt, StmtId};
+use crafting_interpreters_rust::threads::ThreadsModule;
+
+use serde_json::{json, Value as Json};
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+
+// Lox scripts run on a single thread, which is reported to the editor with this id.
+const THREAD_ID: i64 = 1;
+
+struct Connection {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    seq: i64,
+}
+
+impl Connection {
+    /// The next message, or `None` once the editor has closed the stream.
+    fn read(&mut self) -> Option<Json> {
+        let mut length: Option<usize> = None;
+
+        loop {
+            let mut header = String::new();
+            if self.input.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().ok();
+            }
+        }
+
+        let mut body = vec![0; length?];
+        self.input.read_exact(&mut body).ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let body = message.to_string();
+        // The editor going away mid-session leaves nobody to report the failure to.
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len()).ok();
+        self.output.flush().ok();
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+struct Debugger {
+    connection: Connection,
+    program: Option<String>,
+    breakpoints: HashSet<i32>,
+    // Pause at the next statement, after a step request or with `stopOnEntry`.
+    stepping: bool,
+}
+
+impl Debugger {
+    /// Answers the requests that make sense at any point of the session. Returns false for
+    /// the ones the caller has to handle itself.
+    fn handle_common(&mut self, request: &Json) -> bool {
+        match request["command"].as_str().unwrap_or_default() {
+            "setBreakpoints" => {
+                // Only one program runs per session, so the source path isn't checked.
+                let lines: Vec<i64> = request["arguments"]["breakpoints"].as_array()
+                    .map(|breakpoints| breakpoints.iter().filter_map(|breakpoint| breakpoint["line"].as_i64()).collect())
+                    .unwrap_or_default();
+                self.breakpoints = lines.iter().map(|&line| line as i32).collect();
+
+                let verified: Vec<Json> = lines.iter().map(|line| json!({ "verified": true, "line": line })).collect();
+                self.connection.respond(request, json!({ "breakpoints": verified }));
+            },
+            "threads" => {
+                self.connection.respond(request, json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }));
+            },
+            "disconnect" => {
+                self.connection.respond(request, json!({}));
+                exit(0);
+            },
+            _ => { return false; }
+        }
+
+        true
+    }
+
+    /// Reports a stop to the editor and serves its requests until it resumes execution.
+    fn pause(&mut self, reason: &str, line: i32, frames: &[FrameView]) {
+        self.connection.event("stopped", json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }));
+
+        // Variables are fetched by reference, numbered from 1 across the scopes of every
+        // frame, innermost first.
+        let mut variables: Vec<Vec<Json>> = Vec::new();
+        let mut scopes: Vec<Vec<Json>> = Vec::new();
+        for frame in frames {
+            let count = frame.scopes.len();
+            let frame_scopes = frame.scopes.iter().enumerate()
+                .map(|(index, scope)| {
+                    variables.push(scope.iter()
+                        .map(|(name, value)| json!({ "name": name, "value": value.to_string(), "type": value.type_name(), "variablesReference": 0 }))
+                        .collect());
+                    let name = if index + 1 == count { String::from("Globals") } else { format!("Block {}", count - index - 1) };
+                    json!({ "name": name, "variablesReference": variables.len(), "expensive": false })
+                })
+                .collect();
+            scopes.push(frame_scopes);
+        }
+
+        loop {
+            let request = match self.connection.read() {
+                Some(request) => request,
+                None => exit(0),
+            };
+            if self.handle_common(&request) {
+                continue;
+            }
+
+            match request["command"].as_str().unwrap_or_default() {
+                "stackTrace" => {
+                    let body: Vec<Json> = frames.iter().enumerate()
+                        .map(|(index, frame)| {
+                            let line = frame.line.unwrap_or(line);
+                            json!({ "id": index + 1, "name": frame.function, "line": line, "column": 1, "source": { "path": self.program } })
+                        })
+                        .collect();
+                    self.connection.respond(&request, json!({ "stackFrames": body, "totalFrames": frames.len() }));
+                },
+                "scopes" => {
+                    let frame = request["arguments"]["frameId"].as_u64().unwrap_or_default() as usize;
+                    let body = scopes.get(frame.wrapping_sub(1)).cloned().unwrap_or_default();
+                    self.connection.respond(&request, json!({ "scopes": body }));
+                },
+                "variables" => {
+                    let reference = request["arguments"]["variablesReference"].as_u64().unwrap_or_default() as usize;
+                    let variables = variables.get(reference.wrapping_sub(1)).cloned().unwrap_or_default();
+                    self.connection.respond(&request, json!({ "variables": variables }));
+                },
+                "continue" => {
+                    self.stepping = false;
+                    self.connection.respond(&request, json!({ "allThreadsContinued": true }));
+                    return;
+                },
+                // Without functions there is nothing to step into or out of, so every step
+                // goes to the next statement.
+                "next" | "stepIn" | "stepOut" => {
+                    self.stepping = true;
+                    self.connection.respond(&request, json!({}));
+                    return;
+                },
+                _ => self.connection.respond(&request, json!({})),
+            }
+        }
+    }
+}
+
+struct DebugObserver {
+    debugger: Arc<Mutex<Debugger>>,
+    stack: CallStack,
+}
+
+impl Observer for DebugObserver {
+    fn before_statement(&mut self, ast: &Ast, stmt: StmtId, line: Option<i32>, _environment: &Environment) {
+        // A block stops at its first statement instead.
+        // A `debugger;` statement stops in `breakpoint` instead.
+        let line = match (&ast[stmt], line) {
+            (Stmt::Block { statements: _ }, _) | (Stmt::Debugger { keyword: _ }, _) | (_, None) => { return; },
+            (_, Some(line)) => line,
+        };
+
+        let mut debugger = self.debugger.lock().unwrap();
+        let reason = if debugger.breakpoints.contains(&line) {
+            "breakpoint"
+        } else if debugger.stepping {
+            "step"
+        } else {
+            return;
+        };
+        debugger.pause(reason, line, &self.stack.frames());
+    }
+
+    fn breakpoint(&mut self, line: i32, _environment: &Environment) {
+        self.debugger.lock().unwrap().pause("breakpoint", line, &self.stack.frames());
+    }
+}
+
+// Forwards what the program prints to the editor, a line at a time.
+struct OutputEvents {
+    debugger: Arc<Mutex<Debugger>>,
+    line: Vec<u8>,
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(bytes);
+
+        if let Some(end) = self.line.iter().rposition(|&byte| byte == b'\n') {
+            let lines: Vec<u8> = self.line.drain(..=end).collect();
+            let output = String::from_utf8_lossy(&lines);
+            self.debugger.lock().unwrap().connection.event("output", json!({ "category": "stdout", "output": output }));
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a debug session over `input` and `output` until the editor disconnects.
+pub fn serve(input: Box<dyn BufRead + Send>, output: Box<dyn Write + Send>) {
+    let debugger = Arc::new(Mutex::new(Debugger {
+        connection: Connection { input, output, seq: 0 },
+        program: None,
+        breakpoints: HashSet::new(),
+        stepping: false,
+    }));
+
+    // Configuration: everything up to 'configurationDone' happens before the program runs.
+    loop {
+        let mut guard = debugger.lock().unwrap();
+        let request = match guard.connection.read() {
+            Some(request) => request,
+            None => { return; }
+        };
+        if guard.handle_common(&request) {
+            continue;
+        }
+
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                guard.connection.respond(&request, json!({ "supportsConfigurationDoneRequest": true }));
+                guard.connection.event("initialized", json!({}));
+            },
+            "launch" => {
+                guard.program = request["arguments"]["program"].as_str().map(String::from);
+                guard.stepping = request["arguments"]["stopOnEntry"].as_bool().unwrap_or(false);
+                guard.connection.respond(&request, json!({}));
+            },
+            "configurationDone" => {
+                guard.connection.respond(&request, json!({}));
+                break;
+            },
+            _ => guard.connection.respond(&request, json!({})),
+        }
+    }
+
+    let program = debugger.lock().unwrap().program.clone();
+    let result = match program.as_deref().map(read_source) {
+        Some(Ok(contents)) => {
+            let mut interpreter = Interpreter::new(false);
+            interpreter.register_module(Box::new(ThreadsModule));
+            interpreter.register_module(Box::new(DebugModule));
+            interpreter.set_output(Box::new(OutputEvents { debugger: Arc::clone(&debugger), line: Vec::new() }));
+            let stack = interpreter.call_stack();
+            interpreter.add_observer(Box::new(DebugObserver { debugger: Arc::clone(&debugger), stack }));
+            interpreter.eval(&contents).map(|_| ()).map_err(|e| e.to_string())
+        },
+        Some(Err(e)) => Err(format!("Could not read '{}': {e}", program.unwrap_or_default())),
+        None => Err(String::from("No program to launch.")),
+    };
+
+    let mut guard = debugger.lock().unwrap();
+    if let Err(message) = result {
+        guard.connection.event("output", json!({ "category": "stderr", "output": format!("{message}\n") }));
+    }
+    guard.connection.event("terminated", json!({}));
+
+    // The editor still asks for threads and the like until it disconnects.
+    while let Some(request) = guard.connection.read() {
+        if !guard.handle_common(&request) {
+            guard.connection.respond(&request, json!({}));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn frame(message: Json) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn breakpoint() {
+        let program = std::env::temp_dir().join(format!("dap-test-{}.lox", std::process::id()));
+        fs::write(&program, "var a = 1;\n{\n  var b = a + 1;\n  print b;\n}\n").unwrap();
+
+        let requests = [
+            json!({ "seq": 1, "type": "request", "command": "initialize", "arguments": {} }),
+            json!({ "seq": 2, "type": "request", "command": "launch", "arguments": { "program": program } }),
+            json!({ "seq": 3, "type": "request", "command": "setBreakpoints", "arguments": { "breakpoints": [{ "line": 4 }] } }),
+            json!({ "seq": 4, "type": "request", "command": "configurationDone" }),
+            json!({ "seq": 5, "type": "request", "command": "scopes", "arguments": { "frameId": 1 } }),
+            json!({ "seq": 6, "type": "request", "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "seq": 7, "type": "request", "command": "continue", "arguments": { "threadId": 1 } }),
+        ];
+        let input: String = requests.into_iter().map(frame).collect();
+        let output = Buffer::default();
+
+        serve(Box::new(io::Cursor::new(input.into_bytes())), Box::new(output.clone()));
+        fs::remove_file(&program).ok();
+
+        let mut connection = Connection { input: Box::new(io::Cursor::new(output.0.lock().unwrap().clone())), output: Box::new(io::sink()), seq: 0 };
+        let mut messages: Vec<Json> = Vec::new();
+        while let Some(message) = connection.read() {
+            messages.push(message);
+        }
+
+        let events: Vec<&str> = messages.iter().filter_map(|message| message["event"].as_str()).collect();
+        assert_eq!(events, vec!["initialized", "stopped", "output", "terminated"]);
+
+        let variables = messages.iter().find(|message| message["command"] == "variables").unwrap();
+        assert_eq!(variables["body"]["variables"], json!([{ "name": "b", "value": "2", "type": "number", "variablesReference": 0 }]));
+
+        let output = messages.iter().find(|message| message["event"] == "output").unwrap();
+        assert_eq!(output["body"]["output"], "2\n");
+    }
+}
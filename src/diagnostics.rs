@@ -0,0 +1,231 @@
+use crate::error::{CompileError, LoxError};
+use crate::lint::{Lint, Warning};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, IsTerminal};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const UNDERLINE: &str = "\x1b[4m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether diagnostics are rendered with ANSI colors, as chosen by `--color`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    Auto,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            "auto" => Some(ColorChoice::Auto),
+            _ => None,
+        }
+    }
+
+    /// Diagnostics go to stderr, so `Auto` only colors them when stderr is a terminal.
+    pub fn use_color(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stderr().is_terminal(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+}
+
+/// Decides which diagnostics are reported and how, and writes them to stderr.
+pub struct Diagnostics {
+    color: bool,
+    levels: HashMap<Lint, Level>,
+    deny_warnings: bool,
+    // Write errors the way the book's jlox does, for `--compat=jlox`.
+    jlox: bool,
+    // Set in capture mode, where diagnostics are kept instead of written.
+    captured: Option<RefCell<Vec<String>>>,
+}
+
+impl Diagnostics {
+    pub fn new(color: bool) -> Self {
+        let levels = Lint::ALL.into_iter()
+            .map(|lint| (lint, if lint.enabled_by_default() { Level::Warn } else { Level::Allow }))
+            .collect();
+
+        Self { color, levels, deny_warnings: false, jlox: false, captured: None }
+    }
+
+    /// Records diagnostics, without colors, instead of writing them to stderr. Tests can
+    /// read them back with `captured`.
+    pub fn capture(&mut self) {
+        self.color = false;
+        self.captured = Some(RefCell::new(Vec::new()));
+    }
+
+    /// Everything emitted since `capture` was called, one entry per diagnostic.
+    pub fn captured(&self) -> Vec<String> {
+        match &self.captured {
+            Some(captured) => captured.borrow().clone(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn set_level(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn set_deny_warnings(&mut self, deny_warnings: bool) {
+        self.deny_warnings = deny_warnings;
+    }
+
+    /// Writes errors as the book's jlox does, without codes or source snippets, so a
+    /// script's output can be compared with the reference implementation's. Runtime errors
+    /// are the message and then `[line N]`.
+    pub fn set_jlox(&mut self, jlox: bool) {
+        self.jlox = jlox;
+    }
+
+    pub fn deny_warnings(&self) -> bool {
+        self.deny_warnings
+    }
+
+    /// Drops the warnings whose lint is allowed.
+    pub fn filter(&self, warnings: Vec<Warning>) -> Vec<Warning> {
+        warnings.into_iter()
+            .filter(|warning| self.levels.get(&warning.lint) == Some(&Level::Warn))
+            .collect()
+    }
+
+    pub fn emit_error(&self, error: &LoxError, source: &str) {
+        let mut rendered: Vec<String> = Vec::new();
+
+        match error {
+            LoxError::Compile(errors) if self.jlox => rendered.extend(errors.iter().map(CompileError::to_string)),
+            LoxError::Compile(errors) => {
+                for error in errors {
+                    // Denied warnings already end in the lint's name.
+                    let header = match error {
+                        CompileError::Lint(_) => error.to_string(),
+                        _ => format!("{error} [{}]", error.code()),
+                    };
+                    rendered.push(self.render(&header, RED, source, error.line(), error.column(), error.width()));
+                }
+            },
+            LoxError::Runtime(error) if self.jlox => rendered.push(format!("{}\n[line {}]", error.message, error.line)),
+            LoxError::Runtime(error) => {
+                let header = format!("{error} [{}]", error.code);
+                rendered.push(self.render(&header, RED, source, error.line, error.column, error.width));
+            },
+        }
+
+        for diagnostic in rendered {
+            self.write(diagnostic);
+        }
+    }
+
+    pub fn emit_warning(&self, warning: &Warning, source: &str) {
+        let width = warning.token.lexeme.chars().count();
+        self.write(self.render(warning, YELLOW, source, warning.token.line, warning.token.column, width));
+    }
+
+    fn write(&self, diagnostic: String) {
+        match &self.captured {
+            Some(captured) => captured.borrow_mut().push(diagnostic),
+            None => eprintln!("{diagnostic}"),
+        }
+    }
+
+    /// Renders a diagnostic followed by the offending source line with the location underlined.
+    fn render(&self, diagnostic: &dyn fmt::Display, style: &str, source: &str, line: i32, column: usize, width: usize) -> String {
+        let header = if self.color { format!("{style}{diagnostic}{RESET}") } else { diagnostic.to_string() };
+        format!("{header}\n{}", self.snippet(style, source, line, column, width))
+    }
+
+    fn snippet(&self, style: &str, source: &str, line: i32, column: usize, width: usize) -> String {
+        let text = match source.lines().nth((line - 1).max(0) as usize) {
+            Some(text) => text,
+            None => { return String::new(); }
+        };
+
+        // Column 0 means only the line is known.
+        if column == 0 {
+            return format!("{gutter} |\n{line} | {text}", gutter = " ".repeat(line.to_string().len()));
+        }
+
+        let width = width.max(1);
+        let start = column.saturating_sub(1);
+
+        // Keep tabs in the padding so the caret lines up with the source line.
+        let padding: String = text.chars()
+            .take(start)
+            .map(|character| if character == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = " ".repeat(line.to_string().len());
+        let carets = "^".repeat(width);
+
+        if self.color {
+            let before: String = text.chars().take(start).collect();
+            let span: String = text.chars().skip(start).take(width).collect();
+            let after: String = text.chars().skip(start + width).collect();
+
+            format!("{gutter} |\n{line} | {before}{UNDERLINE}{span}{RESET}{after}\n{gutter} | {padding}{style}{carets}{RESET}")
+        } else {
+            format!("{gutter} |\n{line} | {text}\n{gutter} | {padding}{carets}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::parser;
+    use crate::scanner::Dialect;
+
+    #[test]
+    fn capture() {
+        let source = "print 1 +;\nprint );";
+        let mut diagnostics = Diagnostics::new(true);
+        diagnostics.capture();
+
+        diagnostics.emit_error(&LoxError::from(parser::parse_source(source).unwrap_err()), source);
+
+        assert_eq!(diagnostics.captured(), vec![
+            "[line 1] Error at ';': Primary token not found. [E0101]\n  |\n1 | print 1 +;\n  |          ^",
+            "[line 2] Error at ')': Primary token not found. [E0101]\n  |\n2 | print );\n  |       ^",
+        ]);
+    }
+
+    #[test]
+    fn jlox() {
+        let source = "print 1;\nprint -\"a\";";
+        let mut diagnostics = Diagnostics::new(false);
+        diagnostics.capture();
+        diagnostics.set_jlox(true);
+
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_dialect(Dialect { jlox: true, ..Dialect::default() });
+        interpreter.capture_output();
+        diagnostics.emit_error(&interpreter.eval(source).unwrap_err(), source);
+
+        assert_eq!(diagnostics.captured(), vec!["Operand must be a number.\n[line 2]"]);
+
+        let source = "print 1 +;\nvar a = 1\nb;";
+        let errors = parser::parse_source_in(source, Dialect { jlox: true, ..Dialect::default() }).unwrap_err();
+        diagnostics.emit_error(&LoxError::from(errors), source);
+        assert_eq!(diagnostics.captured()[1..], [
+            "[line 1] Error at ';': Expect expression.",
+            "[line 3] Error at 'b': Expect ';' after variable declaration.",
+        ]);
+    }
+}
@@ -0,0 +1,250 @@
+//! What changed between two versions of a script, as `jlox diff` reports it. The
+//! top-level statements are compared as syntax trees rather than as text, so changes in
+//! layout, comments or redundant whitespace don't show up.
+//!
+//! Unchanged statements are matched up in order, like lines in a text diff. Of the rest,
+//! a declaration of the same variable on both sides is reported as moved or modified, and
+//! so is a statement of the same kind between the same unchanged ones. Anything else was
+//! added or removed.
+
+use crate::ast_printer;
+use crate::error::CompileError;
+use crate::formatter;
+use crate::parser;
+use crate::syntax::{Ast, Stmt};
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+// Longer expressions are cut short in summaries.
+const SUMMARY_LENGTH: usize = 40;
+
+/// A top-level statement, described briefly, like `var total` or `print total + 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub line: i32,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(Statement),
+    Removed(Statement),
+    /// A declaration that is the same but somewhere else among the statements.
+    Moved { before: Statement, after: Statement },
+    Modified { before: Statement, after: Statement },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(statement) => write!(f, "added {} at line {}", statement.summary, statement.line),
+            Change::Removed(statement) => write!(f, "removed {} at line {}", statement.summary, statement.line),
+            Change::Moved { before, after } => write!(f, "moved {} from line {} to {}", after.summary, before.line, after.line),
+            Change::Modified { before, after } if before.summary == after.summary =>
+                write!(f, "modified {} at line {} -> {}", after.summary, before.line, after.line),
+            Change::Modified { before, after } =>
+                write!(f, "modified {} at line {} -> {} at line {}", before.summary, before.line, after.summary, after.line),
+        }
+    }
+}
+
+/// A version of the script that doesn't parse.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffError {
+    Before(Vec<CompileError>),
+    After(Vec<CompileError>),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (DiffError::Before(errors) | DiffError::After(errors)) = self;
+        for (i, error) in errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DiffError {}
+
+/// The changes from `before` to `after`, in the order of `after`. Removed statements come
+/// before the statement of `after` that follows where they were.
+pub fn diff(before: &str, after: &str) -> Result<Vec<Change>, DiffError> {
+    let before = Version::parse(before).map_err(DiffError::Before)?;
+    let after = Version::parse(after).map_err(DiffError::After)?;
+
+    // Each change with where it goes in `after`, removals first.
+    let mut changes: Vec<((usize, bool), Change)> = Vec::new();
+    // What is left unpaired, removals with where they go.
+    let mut removed: Vec<(usize, usize)> = Vec::new();
+    let mut added: Vec<usize> = Vec::new();
+
+    let (mut old, mut new) = (0, 0);
+    for (next_old, next_new) in before.unchanged(&after).into_iter().chain([(before.len(), after.len())]) {
+        let mut gap: Vec<usize> = (new..next_new).collect();
+        for old in old..next_old {
+            let partner = before.declared(old).is_none()
+                .then(|| gap.iter().position(|&new| after.declared(new).is_none() && mem::discriminant(before.stmt(old)) == mem::discriminant(after.stmt(new))))
+                .flatten();
+            match partner {
+                Some(position) => {
+                    let new = gap.remove(position);
+                    changes.push(((new, true), Change::Modified { before: before.statement(old), after: after.statement(new) }));
+                },
+                None => removed.push((old, next_new)),
+            }
+        }
+        added.extend(gap);
+        (old, new) = (next_old + 1, next_new + 1);
+    }
+
+    for (old, place) in removed {
+        let partner = before.declared(old).and_then(|name| added.iter().position(|&new| after.declared(new) == Some(name)));
+        match partner {
+            Some(position) => {
+                let new = added.remove(position);
+                let (statement_before, statement_after) = (before.statement(old), after.statement(new));
+                let change = if before.printed[old] == after.printed[new] {
+                    Change::Moved { before: statement_before, after: statement_after }
+                } else {
+                    Change::Modified { before: statement_before, after: statement_after }
+                };
+                changes.push(((new, true), change));
+            },
+            None => changes.push(((place, false), Change::Removed(before.statement(old)))),
+        }
+    }
+    changes.extend(added.into_iter().map(|new| ((new, true), Change::Added(after.statement(new)))));
+
+    changes.sort_by_key(|&(place, _)| place);
+    Ok(changes.into_iter().map(|(_, change)| change).collect())
+}
+
+struct Version<'a> {
+    source: &'a str,
+    ast: Ast,
+    // Each top-level statement as the AST printer shows it, which leaves out the layout.
+    printed: Vec<String>,
+}
+
+impl<'a> Version<'a> {
+    fn parse(source: &'a str) -> Result<Self, Vec<CompileError>> {
+        let ast = parser::parse_source(source)?;
+        let printed = ast.statements.iter().map(|&statement| ast_printer::print_statements(&ast, &[statement])).collect();
+        Ok(Self { source, ast, printed })
+    }
+
+    fn len(&self) -> usize {
+        self.printed.len()
+    }
+
+    fn stmt(&self, index: usize) -> &Stmt {
+        &self.ast[self.ast.statements[index]]
+    }
+
+    fn declared(&self, index: usize) -> Option<&str> {
+        match self.stmt(index) {
+            Stmt::Variable { name, initializer: _ } => Some(&name.lexeme),
+            _ => None,
+        }
+    }
+
+    // The statements that are the same in both versions, as a longest common subsequence.
+    fn unchanged(&self, other: &Version) -> Vec<(usize, usize)> {
+        let (n, m) = (self.len(), other.len());
+        // lengths[i][j] is the length of the longest common subsequence of the statements
+        // from i and from j on.
+        let mut lengths = vec![vec![0; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = if self.printed[i] == other.printed[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+
+        let mut pairs: Vec<(usize, usize)> = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if self.printed[i] == other.printed[j] {
+                pairs.push((i, j));
+                i += 1;
+                j += 1;
+            } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        pairs
+    }
+
+    fn statement(&self, index: usize) -> Statement {
+        let start = self.ast.stmt_span(self.ast.statements[index]).start;
+        let line = self.source[..start].matches('\n').count() as i32 + 1;
+        Statement { line, summary: self.summary(index) }
+    }
+
+    fn summary(&self, index: usize) -> String {
+        let ast = &self.ast;
+        let expression = |expression| {
+            let text = formatter::format_expression(ast, expression);
+            match text.char_indices().nth(SUMMARY_LENGTH) {
+                Some((end, _)) => format!("{}...", &text[..end]),
+                None => text,
+            }
+        };
+
+        match self.stmt(index) {
+            Stmt::Variable { name, initializer: _ } => format!("var {}", name.lexeme),
+            Stmt::Expression { expression: value } => format!("{};", expression(*value)),
+            Stmt::Print { keyword: _, expression: value } => format!("print {}", expression(*value)),
+            Stmt::If { keyword: _, condition, then_branch: _, else_branch: _ } => format!("if ({})", expression(*condition)),
+            Stmt::While { keyword: _, condition, body: _ } => format!("while ({})", expression(*condition)),
+            Stmt::For { .. } => String::from("for loop"),
+            Stmt::Block { statements: _ } => String::from("block"),
+            Stmt::Import { keyword: _, module } => format!("import native {}", module.lexeme),
+            Stmt::Break {} => String::from("break"),
+            Stmt::Debugger { keyword: _ } => String::from("debugger"),
+            Stmt::Error { tokens: _ } => String::from("error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(before: &str, after: &str) -> Vec<String> {
+        diff(before, after).unwrap().iter().map(Change::to_string).collect()
+    }
+
+    #[test]
+    fn ignores_layout() {
+        let before = "var a = 1;\nprint a + 2; // sum\nif (a) print a; else { print 0; }\n";
+        let after = "var a=1;\n\n// sum\nprint (a+2-0) ;\nif (a)\n    print a;\nelse {\n    print 0;\n}\n";
+        assert_eq!(changes(before, after), vec!["modified print a + 2 at line 2 -> print (a + 2 - 0) at line 4"]);
+        assert!(changes(before, "var a = 1; print a + 2; if (a) print a; else { print 0; }").is_empty());
+    }
+
+    #[test]
+    fn reports_changes() {
+        let before = "var a = 1;\nvar b = 2;\nprint a;\nwhile (a < 3) a = a + 1;\nprint b;\n";
+        let after = "var b = 2;\nvar a = 5;\nprint a;\nprint b;\nvar c = a * b;\n{ print c; }\n";
+        assert_eq!(changes(before, after), vec![
+            "modified var a at line 1 -> 2",
+            "removed while (a < 3) at line 4",
+            "added var c at line 5",
+            "added block at line 6",
+        ]);
+        assert_eq!(changes("var a = 1;\nvar b = 2;\n", "var b = 2;\nvar a = 1;\n"), vec!["moved var a from line 1 to 2"]);
+        assert!(matches!(diff("var a = 1;", "var a = ;"), Err(DiffError::After(_))));
+    }
+}
@@ -0,0 +1,121 @@
+//! Documentation for Lox scripts, as shown by `jlox doc`. A run of `///` comments right
+//! above a top-level declaration documents it. Only variables can be declared at the top
+//! level so far, so those are what gets documented.
+
+use crate::error::CompileError;
+use crate::parser;
+use crate::scanner::{self, TokenType};
+use crate::syntax::Stmt;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub name: String,
+    /// How the declaration reads in source, like `var answer`.
+    pub signature: String,
+    /// The doc comment without its slashes, one entry per line.
+    pub docs: Vec<String>,
+    pub line: i32,
+}
+
+/// Every top-level declaration in `source`, documented or not, in source order.
+pub fn extract(source: &str) -> Result<Vec<Item>, Vec<CompileError>> {
+    let ast = parser::parse_source(source)?;
+
+    // Doc comments by line. A comment after code on the same line documents nothing.
+    let (tokens, _) = scanner::scan_tokens_with_comments(source);
+    let mut docs: HashMap<i32, String> = HashMap::new();
+    let mut code_line: Option<i32> = None;
+    for token in tokens {
+        if token.token_type != TokenType::Comment {
+            code_line = Some(token.line);
+            continue;
+        }
+        if let Some(text) = token.lexeme.strip_prefix("///") {
+            // Four or more slashes are a plain comment, as in Rust.
+            if !text.starts_with('/') && code_line != Some(token.line) {
+                docs.insert(token.line, text.strip_prefix(' ').unwrap_or(text).trim_end().to_string());
+            }
+        }
+    }
+
+    let items = ast.statements.iter()
+        .filter_map(|&statement| match &ast[statement] {
+            Stmt::Variable { name, initializer: _ } => Some(name),
+            _ => None,
+        })
+        .map(|name| {
+            let mut lines: Vec<String> = Vec::new();
+            let mut line = name.line - 1;
+            while let Some(text) = docs.get(&line) {
+                lines.push(text.clone());
+                line -= 1;
+            }
+            lines.reverse();
+
+            Item { name: name.lexeme.to_string(), signature: format!("var {}", name.lexeme), docs: lines, line: name.line }
+        })
+        .collect();
+
+    Ok(items)
+}
+
+pub fn to_markdown(title: &str, items: &[Item]) -> String {
+    let mut markdown = format!("# {title}\n");
+
+    for item in items {
+        markdown.push_str(&format!("\n## `{}`\n\n", item.signature));
+        if item.docs.is_empty() {
+            markdown.push_str(&format!("*Undocumented, declared on line {}.*\n", item.line));
+        } else {
+            markdown.push_str(&item.docs.join("\n"));
+            markdown.push('\n');
+        }
+    }
+
+    markdown
+}
+
+pub fn to_html(title: &str, items: &[Item]) -> String {
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n", escape(title));
+
+    for item in items {
+        html.push_str(&format!("<h2 id=\"{}\"><code>{}</code></h2>\n", escape(&item.name), escape(&item.signature)));
+        if item.docs.is_empty() {
+            html.push_str(&format!("<p><em>Undocumented, declared on line {}.</em></p>\n", item.line));
+        } else {
+            html.push_str(&format!("<p>{}</p>\n", escape(&item.docs.join("\n"))));
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doc_comments() {
+        let source = "/// The answer.\n/// Computed at length.\nvar answer = 42;\n\n// Not documentation.\nvar plain;\n//// Neither.\nvar ruled;\n{\n  /// Local, so not listed.\n  var inner;\n}\nvar a; /// Trailing.\nvar b;\n";
+        let items = extract(source).unwrap();
+
+        let docs: Vec<(&str, Vec<String>)> = items.iter().map(|item| (item.name.as_str(), item.docs.clone())).collect();
+        assert_eq!(docs, vec![
+            ("answer", vec![String::from("The answer."), String::from("Computed at length.")]),
+            ("plain", vec![]),
+            ("ruled", vec![]),
+            ("a", vec![]),
+            ("b", vec![]),
+        ]);
+
+        assert_eq!(to_markdown("answer.lox", &items[..2]), "# answer.lox\n\n## `var answer`\n\nThe answer.\nComputed at length.\n\n## `var plain`\n\n*Undocumented, declared on line 6.*\n");
+        assert!(to_html("<lox>", &items[..1]).contains("<title>&lt;lox&gt;</title>"));
+    }
+}
@@ -1,55 +1,151 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use crate::codes::ErrorCode;
+use crate::error::RuntimeError;
+use crate::globals::Globals;
+use crate::intern::Symbol;
 use crate::interpreter::Value;
 use crate::scanner::Token;
+use crate::shared::{Lock, Shared};
 
 #[derive(Clone)]
 pub struct Environment {
-    pub enclosing: Option<Rc<Environment>>,
-    values: RefCell<HashMap<String, Value>>,
+    pub enclosing: Option<Shared<Environment>>,
+    values: Lock<HashMap<Symbol, Value>>,
+    // Set on the outermost environment, whose variables live in the interpreter's table
+    // of globals instead.
+    globals: Option<Shared<Lock<Globals>>>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             enclosing: None,
-            values: RefCell::new(HashMap::new()),
+            values: Lock::new(HashMap::new()),
+            globals: None,
         }
     }
 
-    pub fn from(enclosing: Rc<Environment>) -> Self {
+    pub fn from(enclosing: Shared<Environment>) -> Self {
         Self {
             enclosing: Some(enclosing),
-            values: RefCell::new(HashMap::new()),
+            values: Lock::new(HashMap::new()),
+            globals: None,
+        }
+    }
+
+    /// The outermost environment, which reads and writes `globals`.
+    pub(crate) fn global(globals: Shared<Lock<Globals>>) -> Self {
+        Self {
+            enclosing: None,
+            values: Lock::new(HashMap::new()),
+            globals: Some(globals),
         }
     }
 
     pub fn get(&self, name: &Token) -> Option<Value> {
-        match self.values.borrow().get(&name.lexeme) {
+        self.get_name(&name.lexeme)
+    }
+
+    pub fn get_name(&self, name: &str) -> Option<Value> {
+        if let Some(globals) = &self.globals {
+            return globals.borrow().get_name(name);
+        }
+
+        match self.values.borrow().get(name) {
             Some(value) => Some(value.clone()),
             None => {
                 match &self.enclosing {
-                    Some(enclosing) => enclosing.get(name),
+                    Some(enclosing) => enclosing.get_name(name),
                     None => None
                 }
             }
         }
     }
 
-    pub fn define(&self, name: &Token, value: Value) {
-        self.values.borrow_mut().insert(name.lexeme.to_string(), value);
+    /// Defines `name` in this scope, returning the value it replaces if the scope already
+    /// had one.
+    pub fn define(&self, name: &Token, value: Value) -> Option<Value> {
+        if let Some(globals) = &self.globals {
+            let mut globals = globals.borrow_mut();
+            let id = globals.id(&name.lexeme);
+            return globals.define(id, value);
+        }
+
+        self.values.borrow_mut().insert(Symbol::clone(&name.lexeme), value)
     }
 
-    pub fn assign(&self, name: &Token, value: Value) -> Result<Value, String> {
-        if self.values.borrow().contains_key(&name.lexeme) {
-            self.values.borrow_mut().insert(name.lexeme.to_string(), value.clone());
+    pub fn define_name(&self, name: &str, value: Value) -> Option<Value> {
+        if let Some(globals) = &self.globals {
+            return globals.borrow_mut().define_name(name, value);
+        }
+
+        self.values.borrow_mut().insert(Symbol::from(name), value)
+    }
+
+    pub fn assign(&self, name: &Token, value: Value) -> Result<Value, RuntimeError> {
+        if let Some(globals) = &self.globals {
+            return globals.borrow_mut().assign_name(name, value);
+        }
+
+        if let Some(slot) = self.values.borrow_mut().get_mut(&*name.lexeme) {
+            *slot = value.clone();
             Ok(value)
         } else {
             match &self.enclosing {
                 Some(enclosing) => enclosing.assign(name, value),
-                None => Err(format!("Variable '{}' does not exist.", name.lexeme))
+                None => Err(RuntimeError::new(ErrorCode::UndefinedVariable, name, &format!("Variable '{}' does not exist.", name.lexeme)))
             }
         }
     }
-}
\ No newline at end of file
+
+    /// The variables defined in this scope alone, sorted by name.
+    pub fn values(&self) -> Vec<(String, Value)> {
+        if let Some(globals) = &self.globals {
+            return globals.borrow().values();
+        }
+
+        let mut values: Vec<(String, Value)> = self.values.borrow().iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        values.sort_by(|left, right| left.0.cmp(&right.0));
+        values
+    }
+
+    /// Every name visible from this environment, innermost scope first.
+    pub fn names(&self) -> Vec<String> {
+        if let Some(globals) = &self.globals {
+            return globals.borrow().names();
+        }
+
+        let mut names: Vec<String> = self.values.borrow().keys().map(|name| name.to_string()).collect();
+        if let Some(enclosing) = &self.enclosing {
+            names.extend(enclosing.names());
+        }
+        names
+    }
+
+    /// The approximate heap memory held by the values in this scope and the ones around it.
+    /// A read-only prelude is shared between interpreters, so it doesn't count.
+    pub fn heap_size(&self) -> usize {
+        if let Some(globals) = &self.globals {
+            return globals.borrow().heap_size();
+        }
+
+        self.scope_heap_size() + self.enclosing.as_ref().map_or(0, |enclosing| enclosing.heap_size())
+    }
+
+    /// Like `heap_size`, for the values in this scope alone.
+    pub(crate) fn scope_heap_size(&self) -> usize {
+        if let Some(globals) = &self.globals {
+            return globals.borrow().heap_size();
+        }
+
+        self.values.borrow().values().map(Value::heap_size).sum()
+    }
+}
@@ -0,0 +1,227 @@
+use crate::codes::ErrorCode;
+use crate::interpreter::Value;
+use crate::lint::Warning;
+use crate::scanner::{Token, TokenType};
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub code: ErrorCode,
+    pub line: i32,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ScanError {
+    pub fn new(code: ErrorCode, line: i32, column: usize, message: &str) -> Self {
+        Self { code, line, column, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScanError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub code: ErrorCode,
+    pub token: Token,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(code: ErrorCode, token: &Token, message: &str) -> Self {
+        Self { code, token: token.clone(), message: message.to_string() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.token.token_type == TokenType::EOF {
+            write!(f, "[line {}] Error at end: {}", self.token.line, self.message)
+        } else {
+            write!(f, "[line {}] Error at '{}': {}", self.token.line, self.token.lexeme, self.message)
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub code: ErrorCode,
+    pub line: i32,
+    pub column: usize,
+    pub width: usize,
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(code: ErrorCode, token: &Token, message: &str) -> Self {
+        Self {
+            code,
+            line: token.line,
+            column: token.column,
+            width: token.lexeme.chars().count(),
+            message: message.to_string(),
+        }
+    }
+
+    /// An error with only a line to point at, which diagnostics show without underlining.
+    pub fn at_line(code: ErrorCode, line: i32, message: &str) -> Self {
+        Self { code, line, column: 0, width: 0, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// An error found before execution starts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    Scan(ScanError),
+    Parse(ParseError),
+    // A warning that was turned into an error by --deny-warnings.
+    Lint(Warning),
+}
+
+impl CompileError {
+    pub fn line(&self) -> i32 {
+        match self {
+            CompileError::Scan(error) => error.line,
+            CompileError::Parse(error) => error.token.line,
+            CompileError::Lint(warning) => warning.token.line,
+        }
+    }
+
+    pub fn column(&self) -> usize {
+        match self {
+            CompileError::Scan(error) => error.column,
+            CompileError::Parse(error) => error.token.column,
+            CompileError::Lint(warning) => warning.token.column,
+        }
+    }
+
+    /// The error's code, or the lint's for a denied warning.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::Scan(error) => error.code.as_str(),
+            CompileError::Parse(error) => error.code.as_str(),
+            CompileError::Lint(warning) => warning.lint.code(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            CompileError::Scan(_) => 1,
+            CompileError::Parse(error) => error.token.lexeme.chars().count(),
+            CompileError::Lint(warning) => warning.token.lexeme.chars().count(),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Scan(error) => write!(f, "{error}"),
+            CompileError::Parse(error) => write!(f, "{error}"),
+            CompileError::Lint(warning) => {
+                write!(f, "[line {}] Error: {} [-W{}]", warning.token.line, warning.message, warning.lint.name())
+            },
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// A `Value` that could not be converted to the Rust type the host asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub value: Value,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expected a {}, found a {}.", self.expected, self.value.type_name())
+    }
+}
+
+impl Error for ConversionError {}
+
+/// A script that could not be loaded, see `source::read_source`.
+#[derive(Debug)]
+pub enum SourceError {
+    Io(io::Error),
+    /// The file isn't UTF-8; `offset` is the first byte that doesn't decode.
+    InvalidUtf8 { offset: usize },
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Io(error) => write!(f, "{error}"),
+            SourceError::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 at byte {offset}"),
+        }
+    }
+}
+
+impl Error for SourceError {}
+
+impl From<io::Error> for SourceError {
+    fn from(error: io::Error) -> Self {
+        SourceError::Io(error)
+    }
+}
+
+/// Any error produced while running a piece of Lox source through the whole pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    Compile(Vec<CompileError>),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Compile(errors) => write_all(f, errors),
+            LoxError::Runtime(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for LoxError {}
+
+impl From<Vec<CompileError>> for LoxError {
+    fn from(errors: Vec<CompileError>) -> Self {
+        LoxError::Compile(errors)
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(error: RuntimeError) -> Self {
+        LoxError::Runtime(error)
+    }
+}
+
+fn write_all<T: fmt::Display>(f: &mut fmt::Formatter<'_>, errors: &[T]) -> fmt::Result {
+    for (index, error) in errors.iter().enumerate() {
+        if index > 0 {
+            writeln!(f)?;
+        }
+        write!(f, "{error}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! C API for embedding the interpreter in non-Rust hosts; see `include/lox.h`.
+//!
+//! Every function takes the handle returned by `lox_new`. Strings handed out by the
+//! interpreter stay owned by it and are valid until the next call on the same handle.
+
+use crate::error::LoxError;
+use crate::interpreter::Interpreter;
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+pub const LOX_OK: c_int = 0;
+pub const LOX_COMPILE_ERROR: c_int = 1;
+pub const LOX_RUNTIME_ERROR: c_int = 2;
+pub const LOX_INVALID_ARGUMENT: c_int = 3;
+
+pub struct LoxInterpreter {
+    interpreter: Interpreter,
+    // The value of the last successful `lox_eval`, or the message of the last error.
+    result: CString,
+}
+
+impl LoxInterpreter {
+    fn set_result(&mut self, text: String) {
+        // C strings end at the first NUL, so any inside the text are dropped.
+        self.result = CString::new(text.replace('\0', "")).unwrap_or_default();
+    }
+}
+
+/// Creates an interpreter. Release it with `lox_free`.
+#[no_mangle]
+pub extern "C" fn lox_new() -> *mut LoxInterpreter {
+    Box::into_raw(Box::new(LoxInterpreter { interpreter: Interpreter::new(false), result: CString::default() }))
+}
+
+/// Runs `source` and returns one of the `LOX_*` codes. Afterwards `lox_get_string_result`
+/// gives the value of the last expression statement, or the error messages.
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new` and not have been freed, and `source` must be null or
+/// point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lox_eval(lox: *mut LoxInterpreter, source: *const c_char) -> c_int {
+    let lox = match lox.as_mut() {
+        Some(lox) => lox,
+        None => { return LOX_INVALID_ARGUMENT; }
+    };
+    if source.is_null() {
+        lox.set_result(String::from("Source is null."));
+        return LOX_INVALID_ARGUMENT;
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(_) => {
+            lox.set_result(String::from("Source is not valid UTF-8."));
+            return LOX_INVALID_ARGUMENT;
+        }
+    };
+
+    match lox.interpreter.eval(source) {
+        Ok(value) => {
+            lox.set_result(value.to_string());
+            LOX_OK
+        },
+        Err(e) => {
+            lox.set_result(e.to_string());
+            match e {
+                LoxError::Compile(_) => LOX_COMPILE_ERROR,
+                LoxError::Runtime(_) => LOX_RUNTIME_ERROR,
+            }
+        }
+    }
+}
+
+/// The result of the last `lox_eval` as text, or null if `lox` is null.
+///
+/// # Safety
+///
+/// `lox` must be null or come from `lox_new` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn lox_get_string_result(lox: *const LoxInterpreter) -> *const c_char {
+    match lox.as_ref() {
+        Some(lox) => lox.result.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Destroys an interpreter. Passing null does nothing.
+///
+/// # Safety
+///
+/// `lox` must be null or come from `lox_new`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lox_free(lox: *mut LoxInterpreter) {
+    if !lox.is_null() {
+        drop(Box::from_raw(lox));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(lox: *const LoxInterpreter) -> String {
+        unsafe { CStr::from_ptr(lox_get_string_result(lox)) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn eval() {
+        let lox = lox_new();
+
+        unsafe {
+            assert_eq!(lox_eval(lox, c"var a = 20; a + 22;".as_ptr()), LOX_OK);
+            assert_eq!(result(lox), "42");
+
+            assert_eq!(lox_eval(lox, c"a +;".as_ptr()), LOX_COMPILE_ERROR);
+            assert_eq!(result(lox), "[line 1] Error at ';': Primary token not found.");
+
+            assert_eq!(lox_eval(lox, c"-\"a\";".as_ptr()), LOX_RUNTIME_ERROR);
+            assert_eq!(lox_eval(lox, ptr::null()), LOX_INVALID_ARGUMENT);
+            assert_eq!(lox_eval(ptr::null_mut(), c"1;".as_ptr()), LOX_INVALID_ARGUMENT);
+
+            lox_free(lox);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Reprints Lox source in one canonical layout: four spaces of indentation, one statement
+//! per line, spaces around binary operators and opening braces on the line of the
+//! statement they belong to. Comments are kept, either on a line of their own or after
+//! the code they followed, and single blank lines between statements are kept too.
+
+use crate::error::CompileError;
+use crate::parser;
+use crate::scanner::{self, Literal, TokenType};
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+use std::collections::HashMap;
+
+const INDENT: &str = "    ";
+
+/// Formats `source`, which has to parse without errors.
+pub fn format_source(source: &str) -> Result<String, Vec<CompileError>> {
+    let ast = parser::parse_source(source)?;
+    let mut formatter = Formatter::new(source, &ast);
+
+    for &statement in &ast.statements {
+        formatter.stmt(statement, 0, "");
+    }
+    formatter.comments_before(i32::MAX, 0);
+
+    let mut formatted = formatter.lines.join("\n");
+    formatted.push('\n');
+    Ok(formatted)
+}
+
+struct Comment {
+    line: i32,
+    text: String,
+    // Whether code comes before the comment on its line.
+    trailing: bool,
+}
+
+struct Formatter<'a> {
+    source: Vec<&'a str>,
+    ast: &'a Ast,
+    comments: Vec<Comment>,
+    next_comment: usize,
+    // The line of the closing brace of every block, by the offset of its opening brace.
+    closing_lines: HashMap<usize, i32>,
+    lines: Vec<String>,
+    // The source line the last output line came from, to put trailing comments back.
+    last_line: Option<i32>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, ast: &'a Ast) -> Self {
+        let (tokens, _) = scanner::scan_tokens_with_comments(source);
+
+        let mut comments: Vec<Comment> = Vec::new();
+        let mut closing_lines: HashMap<usize, i32> = HashMap::new();
+        let mut open: Vec<usize> = Vec::new();
+        let mut code_line: Option<i32> = None;
+
+        for token in &tokens {
+            match token.token_type {
+                TokenType::Comment => {
+                    let text = token.lexeme.trim_end().to_string();
+                    comments.push(Comment { line: token.line, text, trailing: code_line == Some(token.line) });
+                    continue;
+                },
+                TokenType::LeftBrace => open.push(token.span.start),
+                TokenType::RightBrace => {
+                    if let Some(start) = open.pop() {
+                        closing_lines.insert(start, token.line);
+                    }
+                },
+                _ => {},
+            }
+            code_line = Some(token.line);
+        }
+
+        Self {
+            source: source.lines().collect(),
+            ast,
+            comments,
+            next_comment: 0,
+            closing_lines,
+            lines: Vec::new(),
+            last_line: None,
+        }
+    }
+
+    fn push(&mut self, depth: usize, text: &str, line: Option<i32>) {
+        self.lines.push(format!("{}{text}", INDENT.repeat(depth)));
+        self.last_line = line;
+    }
+
+    /// Keeps a blank line found in the source before `line`, except at the start of a block.
+    fn blank_line_before(&mut self, line: i32) {
+        let blank = line >= 2 && self.source.get(line as usize - 2).is_some_and(|text| text.trim().is_empty());
+        let after_code = self.lines.last().is_some_and(|last| !last.is_empty() && !last.ends_with('{'));
+
+        if blank && after_code {
+            self.lines.push(String::new());
+            self.last_line = None;
+        }
+    }
+
+    /// Writes out the comments that come before `line`.
+    fn comments_before(&mut self, line: i32, depth: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.line >= line {
+                break;
+            }
+            let (comment_line, text, trailing) = (comment.line, comment.text.clone(), comment.trailing);
+            self.next_comment += 1;
+
+            if trailing && self.last_line == Some(comment_line) {
+                if let Some(last) = self.lines.last_mut() {
+                    last.push(' ');
+                    last.push_str(&text);
+                }
+            } else {
+                self.blank_line_before(comment_line);
+                self.push(depth, &text, Some(comment_line));
+            }
+        }
+    }
+
+    /// Writes `stmt`, with `prefix`, such as `} else `, in front of its first line.
+    fn stmt(&mut self, stmt: StmtId, depth: usize, prefix: &str) {
+        let ast = self.ast;
+        let line = ast.stmt_span(stmt).line;
+        // A statement after a prefix shares its line, so whatever comes before has
+        // already been written.
+        if prefix.is_empty() {
+            self.comments_before(line, depth);
+            self.blank_line_before(line);
+        }
+
+        match &ast[stmt] {
+            Stmt::Block { statements } => self.block(stmt, statements, depth, prefix),
+            Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+                let header = format!("{prefix}if ({}) ", expr(ast, *condition));
+                self.stmt(*then_branch, depth, &header);
+
+                if let Some(else_branch) = else_branch {
+                    // After a block the else goes on the line of its closing brace, unless
+                    // a comment is in the way.
+                    let after_block = matches!(ast[*then_branch], Stmt::Block { statements: _ })
+                        && self.lines.last().is_some_and(|last| last.ends_with('}'));
+                    if after_block {
+                        let closing = self.lines.pop().unwrap_or_default();
+                        self.stmt(*else_branch, depth, &format!("{} else ", closing.trim_start()));
+                    } else {
+                        self.stmt(*else_branch, depth, "else ");
+                    }
+                }
+            },
+            Stmt::While { keyword: _, condition, body } => {
+                self.stmt(*body, depth, &format!("{prefix}while ({}) ", expr(ast, *condition)));
+            },
+            Stmt::For { keyword: _, initializer, condition, increment, body } => {
+                let initializer = initializer.map_or(String::from(";"), |initializer| simple(ast, initializer));
+                let condition = condition.map_or(String::new(), |condition| format!(" {}", expr(ast, condition)));
+                let increment = increment.map_or(String::new(), |increment| format!(" {}", expr(ast, increment)));
+                self.stmt(*body, depth, &format!("{prefix}for ({initializer}{condition};{increment}) "));
+            },
+            _ => self.push(depth, &format!("{prefix}{}", simple(ast, stmt)), Some(line)),
+        }
+    }
+
+    fn block(&mut self, stmt: StmtId, statements: &[StmtId], depth: usize, prefix: &str) {
+        let span = self.ast.stmt_span(stmt);
+        let (open, close) = (span.line, self.closing_lines.get(&span.start).copied().unwrap_or(span.line));
+
+        let empty = statements.is_empty() && self.comments.get(self.next_comment).is_none_or(|comment| comment.line >= close);
+        if empty {
+            self.push(depth, &format!("{prefix}{{}}"), Some(close));
+            return;
+        }
+
+        self.push(depth, &format!("{prefix}{{"), Some(open));
+        for &statement in statements {
+            self.stmt(statement, depth + 1, "");
+        }
+        self.comments_before(close, depth + 1);
+        self.push(depth, "}", Some(close));
+    }
+}
+
+// A statement that fits on one line.
+fn simple(ast: &Ast, stmt: StmtId) -> String {
+    match &ast[stmt] {
+        Stmt::Expression { expression } => format!("{};", expr(ast, *expression)),
+        Stmt::Print { keyword: _, expression } => format!("print {};", expr(ast, *expression)),
+        Stmt::Variable { name, initializer: Some(initializer) } => format!("var {} = {};", name.lexeme, expr(ast, *initializer)),
+        Stmt::Variable { name, initializer: None } => format!("var {};", name.lexeme),
+        Stmt::Break {} => String::from("break;"),
+        Stmt::Import { keyword: _, module } => format!("import native {};", module.lexeme),
+        Stmt::Debugger { keyword: _ } => String::from("debugger;"),
+        stmt => unreachable!("not a simple statement: {stmt:?}"),
+    }
+}
+
+// Any statement on a single line, for the statements inside a block expression.
+fn inline(ast: &Ast, stmt: StmtId) -> String {
+    match &ast[stmt] {
+        Stmt::Block { statements } => braced(statements.iter().map(|&statement| inline(ast, statement)).collect()),
+        Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+            let mut text = format!("if ({}) {}", expr(ast, *condition), inline(ast, *then_branch));
+            if let Some(else_branch) = else_branch {
+                text.push_str(&format!(" else {}", inline(ast, *else_branch)));
+            }
+            text
+        },
+        Stmt::While { keyword: _, condition, body } => format!("while ({}) {}", expr(ast, *condition), inline(ast, *body)),
+        Stmt::For { keyword: _, initializer, condition, increment, body } => {
+            let initializer = initializer.map_or(String::from(";"), |initializer| simple(ast, initializer));
+            let condition = condition.map_or(String::new(), |condition| format!(" {}", expr(ast, condition)));
+            let increment = increment.map_or(String::new(), |increment| format!(" {}", expr(ast, increment)));
+            format!("for ({initializer}{condition};{increment}) {}", inline(ast, *body))
+        },
+        _ => simple(ast, stmt),
+    }
+}
+
+fn braced(parts: Vec<String>) -> String {
+    if parts.is_empty() {
+        String::from("{}")
+    } else {
+        format!("{{ {} }}", parts.join(" "))
+    }
+}
+
+/// `expression` as source on a single line, like the formatter lays out expressions.
+pub fn format_expression(ast: &Ast, expression: ExprId) -> String {
+    expr(ast, expression)
+}
+
+fn expr(ast: &Ast, expression: ExprId) -> String {
+    match &ast[expression] {
+        Expr::Assign { name, value } => format!("{} = {}", name.lexeme, expr(ast, *value)),
+        Expr::Binary { .. } | Expr::Logical { .. } => {
+            let (first, links) = ast.chain(expression);
+            let mut text = expr(ast, first);
+            for (operator, operand) in links {
+                text.push_str(&format!(" {} {}", operator.lexeme, expr(ast, operand)));
+            }
+            text
+        },
+        Expr::Call { callee, paren: _, arguments } => {
+            let arguments: Vec<String> = arguments.iter().map(|&argument| expr(ast, argument)).collect();
+            format!("{}({})", expr(ast, *callee), arguments.join(", "))
+        },
+        Expr::Grouping { expression } => format!("({})", expr(ast, *expression)),
+        Expr::Literal { value } => match value {
+            Literal::Identifier(text) => text.to_string(),
+            Literal::Str(text) => format!("\"{text}\""),
+            Literal::Number(number) => number.to_string(),
+            Literal::Bool(value) => value.to_string(),
+            Literal::Nil => String::from("nil"),
+        },
+        Expr::Unary { operator, right } => format!("{}{}", operator.lexeme, expr(ast, *right)),
+        Expr::Variable { name } => name.lexeme.to_string(),
+        Expr::Block { statements, value } => {
+            let mut parts: Vec<String> = statements.iter().map(|&statement| inline(ast, statement)).collect();
+            parts.extend(value.map(|value| expr(ast, value)));
+            braced(parts)
+        },
+        Expr::If { keyword: _, condition, then_branch, else_branch } => {
+            let mut text = format!("if ({}) {}", expr(ast, *condition), expr(ast, *then_branch));
+            if let Some(else_branch) = else_branch {
+                text.push_str(&format!(" else {}", expr(ast, *else_branch)));
+            }
+            text
+        },
+        Expr::Lambda { params, arrow: _, body } => {
+            let params: Vec<&str> = params.iter().map(|param| &*param.lexeme).collect();
+            format!("({}) => {}", params.join(", "), expr(ast, *body))
+        },
+        Expr::Error { token: _ } => unreachable!("formatted source parsed without errors"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let source = "if (true) {} else {}\nvar a=1;\nif(a>0){print a*2;}else if (a==0) print \"zero\"; else {a=-a;}\n\n\n\nfor(var i=0;i<3;i=i+1){ }\nfor(;;){break;}\nwhile (a) {\n{ a = f(1,2)(); }}\n";
+
+        assert_eq!(format_source(source).unwrap(), [
+            "if (true) {} else {}",
+            "var a = 1;",
+            "if (a > 0) {",
+            "    print a * 2;",
+            "} else if (a == 0) print \"zero\";",
+            "else {",
+            "    a = -a;",
+            "}",
+            "",
+            "for (var i = 0; i < 3; i = i + 1) {}",
+            "for (;;) {",
+            "    break;",
+            "}",
+            "while (a) {",
+            "    {",
+            "        a = f(1, 2)();",
+            "    }",
+            "}",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn comments() {
+        let source = "// header\nvar a = 1; // one\n\n{ // block\n  // inside\n  print a;\n  // last\n}\n{\n  // only\n}\n// footer\n";
+
+        assert_eq!(format_source(source).unwrap(), [
+            "// header",
+            "var a = 1; // one",
+            "",
+            "{ // block",
+            "    // inside",
+            "    print a;",
+            "    // last",
+            "}",
+            "{",
+            "    // only",
+            "}",
+            "// footer",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn block_expressions() {
+        let source = "var x=if(a){1}else if (b) {} else{ var c=2;while(c)c=c-1;c};\n";
+        assert_eq!(format_source(source).unwrap(), "var x = if (a) { 1 } else if (b) {} else { var c = 2; while (c) c = c - 1; c };\n");
+    }
+
+    #[test]
+    fn arrow_functions() {
+        let source = "var add=(a,b)=>a+b;\nvar f=( )=>{print 1;add(1,2)};\n";
+        assert_eq!(format_source(source).unwrap(), "var add = (a, b) => a + b;\nvar f = () => { print 1; add(1, 2) };\n");
+    }
+
+    #[test]
+    fn idempotent() {
+        let source = "var a = 1;\nif (a > 0) {\n    print a;\n} else print -a;\n\nfor (; a < 3;) a = a + 1; // step\n";
+        assert_eq!(format_source(source).unwrap(), source);
+    }
+
+    #[test]
+    fn long_chains() {
+        // Thousands of levels deep to the left, which the formatter walks in a loop.
+        let source = format!("print {};\nprint a or b and c == 1 + 2 * 3;\n", vec!["1"; 20_000].join(" + "));
+        assert_eq!(format_source(&source).unwrap(), source);
+    }
+}
@@ -0,0 +1,47 @@
+//! Functions written in Lox. An arrow function is a value that keeps the scope it was
+//! made in, so calling it later still sees the variables around it.
+
+use crate::environment::Environment;
+use crate::globals::Resolution;
+use crate::scanner::Token;
+use crate::shared::Shared;
+use crate::syntax::{Ast, ExprId};
+
+use std::fmt;
+
+/// An arrow function together with what it needs to run after the program that made it
+/// has finished: the tree its body lives in, where that tree's globals are, and the
+/// scope around it.
+pub struct Function {
+    pub(crate) params: Vec<Token>,
+    pub(crate) body: ExprId,
+    pub(crate) ast: Shared<Ast>,
+    pub(crate) resolution: Shared<Resolution>,
+    pub(crate) closure: Shared<Environment>,
+    // Calls so far and the native code once the function is hot.
+    #[cfg(feature = "jit")]
+    pub(crate) jit: crate::jit::Cache,
+}
+
+impl Function {
+    /// Arrow functions have no name of their own.
+    pub fn name(&self) -> &str {
+        "lambda"
+    }
+
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Function({}/{})", self.name(), self.arity())
+    }
+}
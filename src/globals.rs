@@ -0,0 +1,312 @@
+//! The global variables, kept in a flat table owned by the interpreter rather than in the
+//! chain of scopes. Before a program runs, `resolve` gives every global it names a slot,
+//! so reading or assigning a global inside a loop is an index into a vector instead of a
+//! walk up the scopes and a hash of the name on every iteration.
+
+use crate::codes::ErrorCode;
+use crate::error::RuntimeError;
+use crate::intern::Symbol;
+use crate::interpreter::Value;
+use crate::scanner::Token;
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct GlobalId(u32);
+
+#[derive(Clone, Default)]
+pub(crate) struct Globals {
+    names: Vec<Symbol>,
+    // `None` until the variable is defined.
+    values: Vec<Option<Value>>,
+    // Set for variables from a prelude, which can be declared again but not assigned.
+    read_only: Vec<bool>,
+    ids: HashMap<Symbol, GlobalId>,
+}
+
+impl Globals {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of `prelude` whose variables cannot be assigned.
+    pub(crate) fn from_prelude(prelude: &Globals) -> Self {
+        Self { read_only: vec![true; prelude.names.len()], ..prelude.clone() }
+    }
+
+    /// The slot for `name`, made the first time the name comes up.
+    pub(crate) fn id(&mut self, name: &Symbol) -> GlobalId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+
+        let id = GlobalId(self.names.len() as u32);
+        self.names.push(Symbol::clone(name));
+        self.values.push(None);
+        self.read_only.push(false);
+        self.ids.insert(Symbol::clone(name), id);
+        id
+    }
+
+    pub(crate) fn get(&self, id: GlobalId) -> Option<Value> {
+        self.values[id.0 as usize].clone()
+    }
+
+    pub(crate) fn get_name(&self, name: &str) -> Option<Value> {
+        self.ids.get(name).and_then(|&id| self.get(id))
+    }
+
+    /// Defines the variable in slot `id`, returning the value it had before, if any.
+    pub(crate) fn define(&mut self, id: GlobalId, value: Value) -> Option<Value> {
+        self.read_only[id.0 as usize] = false;
+        self.values[id.0 as usize].replace(value)
+    }
+
+    pub(crate) fn define_name(&mut self, name: &str, value: Value) -> Option<Value> {
+        let id = self.id(&Symbol::from(name));
+        self.define(id, value)
+    }
+
+    pub(crate) fn assign(&mut self, id: GlobalId, name: &Token, value: Value) -> Result<Value, RuntimeError> {
+        let index = id.0 as usize;
+        if self.values[index].is_none() {
+            return Err(RuntimeError::new(ErrorCode::UndefinedVariable, name, &format!("Variable '{}' does not exist.", name.lexeme)));
+        }
+        if self.read_only[index] {
+            return Err(RuntimeError::new(ErrorCode::PreludeAssignment, name, &format!("Cannot assign to '{}', it belongs to the prelude.", name.lexeme)));
+        }
+
+        self.values[index] = Some(value.clone());
+        Ok(value)
+    }
+
+    pub(crate) fn assign_name(&mut self, name: &Token, value: Value) -> Result<Value, RuntimeError> {
+        let id = self.id(&name.lexeme);
+        self.assign(id, name, value)
+    }
+
+    fn defined(&self) -> impl Iterator<Item = (usize, &Symbol, &Value)> {
+        self.names.iter().zip(&self.values).enumerate()
+            .filter_map(|(index, (name, value))| value.as_ref().map(|value| (index, name, value)))
+    }
+
+    /// The defined globals, sorted by name.
+    pub(crate) fn values(&self) -> Vec<(String, Value)> {
+        let mut values: Vec<(String, Value)> = self.defined().map(|(_, name, value)| (name.to_string(), value.clone())).collect();
+        values.sort_by(|left, right| left.0.cmp(&right.0));
+        values
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.defined().map(|(_, name, _)| name.to_string()).collect()
+    }
+
+    /// Like `Environment::heap_size`, which leaves out what the prelude holds.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.defined().filter(|&(index, _, _)| !self.read_only[index]).map(|(_, _, value)| value.heap_size()).sum()
+    }
+}
+
+/// Which names in a program refer to globals, and to which slots.
+#[derive(Default)]
+pub(crate) struct Resolution {
+    // Indexed by expression, for variables and assignments.
+    exprs: Vec<Option<GlobalId>>,
+    // Indexed by statement, for variable declarations.
+    stmts: Vec<Option<GlobalId>>,
+}
+
+impl Resolution {
+    pub(crate) fn expr(&self, expr: ExprId) -> Option<GlobalId> {
+        self.exprs.get(expr.index()).copied().flatten()
+    }
+
+    pub(crate) fn stmt(&self, stmt: StmtId) -> Option<GlobalId> {
+        self.stmts.get(stmt.index()).copied().flatten()
+    }
+}
+
+/// Finds the globals `ast` uses, giving new ones a slot. A name is global unless a block
+/// around it declared it earlier, which is exactly when the scopes at run time end up
+/// looking past every local one for it.
+pub(crate) fn resolve(ast: &Ast, globals: &mut Globals) -> Resolution {
+    let mut resolver = Resolver {
+        ast,
+        globals,
+        scopes: Vec::new(),
+        resolution: Resolution { exprs: vec![None; ast.expr_count()], stmts: vec![None; ast.stmt_count()] },
+    };
+    for &statement in &ast.statements {
+        resolver.stmt(statement);
+    }
+    resolver.resolution
+}
+
+struct Resolver<'a> {
+    ast: &'a Ast,
+    globals: &'a mut Globals,
+    scopes: Vec<HashSet<Symbol>>,
+    resolution: Resolution,
+}
+
+impl Resolver<'_> {
+    fn global(&mut self, name: &Token) -> Option<GlobalId> {
+        if self.scopes.iter().any(|scope| scope.contains(&name.lexeme)) {
+            None
+        } else {
+            Some(self.globals.id(&name.lexeme))
+        }
+    }
+
+    fn stmt(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        match &ast[stmt] {
+            Stmt::Block { statements } => {
+                self.scopes.push(HashSet::new());
+                for &statement in statements {
+                    self.stmt(statement);
+                }
+                self.scopes.pop();
+            },
+            Stmt::Expression { expression } | Stmt::Print { keyword: _, expression } => self.expr(*expression),
+            Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+                self.expr(*condition);
+                self.stmt(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(*else_branch);
+                }
+            },
+            Stmt::Variable { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expr(*initializer);
+                }
+                match self.scopes.last_mut() {
+                    Some(scope) => { scope.insert(Symbol::clone(&name.lexeme)); },
+                    None => self.resolution.stmts[stmt.index()] = Some(self.globals.id(&name.lexeme)),
+                }
+            },
+            Stmt::While { keyword: _, condition, body } => {
+                self.expr(*condition);
+                self.stmt(*body);
+            },
+            Stmt::For { keyword: _, initializer, condition, increment, body } => {
+                self.scopes.push(HashSet::new());
+                if let Some(initializer) = initializer {
+                    self.stmt(*initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expr(*condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(*increment);
+                }
+                self.stmt(*body);
+                self.scopes.pop();
+            },
+            Stmt::Break {} | Stmt::Import { keyword: _, module: _ } | Stmt::Debugger { keyword: _ } | Stmt::Error { tokens: _ } => {},
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        match &ast[expr] {
+            Expr::Assign { name, value } => {
+                self.expr(*value);
+                self.resolution.exprs[expr.index()] = self.global(name);
+            },
+            Expr::Variable { name } => self.resolution.exprs[expr.index()] = self.global(name),
+            Expr::Binary { .. } | Expr::Logical { .. } => {
+                for operand in ast.chain_operands(expr) {
+                    self.expr(operand);
+                }
+            },
+            Expr::Call { callee, paren: _, arguments } => {
+                self.expr(*callee);
+                for &argument in arguments {
+                    self.expr(argument);
+                }
+            },
+            Expr::Grouping { expression } => self.expr(*expression),
+            Expr::Unary { operator: _, right } => self.expr(*right),
+            Expr::Block { statements, value } => {
+                self.scopes.push(HashSet::new());
+                for &statement in statements {
+                    self.stmt(statement);
+                }
+                if let Some(value) = value {
+                    self.expr(*value);
+                }
+                self.scopes.pop();
+            },
+            Expr::If { keyword: _, condition, then_branch, else_branch } => {
+                self.expr(*condition);
+                self.expr(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expr(*else_branch);
+                }
+            },
+            Expr::Lambda { params, arrow: _, body } => {
+                self.scopes.push(params.iter().map(|param| Symbol::clone(&param.lexeme)).collect());
+                self.expr(*body);
+                self.scopes.pop();
+            },
+            Expr::Literal { value: _ } | Expr::Error { token: _ } => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    // Whether each `print` in the program prints a global, in order.
+    fn printed_globals(source: &str) -> Vec<bool> {
+        fn prints(ast: &Ast, statements: &[StmtId], found: &mut Vec<ExprId>) {
+            for &statement in statements {
+                match &ast[statement] {
+                    Stmt::Print { expression, .. } => found.push(*expression),
+                    Stmt::Block { statements } => prints(ast, statements, found),
+                    Stmt::For { body, .. } => prints(ast, &[*body], found),
+                    _ => {},
+                }
+            }
+        }
+
+        let ast = parse_source(source).unwrap();
+        let resolution = resolve(&ast, &mut Globals::new());
+        let mut found: Vec<ExprId> = Vec::new();
+        prints(&ast, &ast.statements, &mut found);
+        found.into_iter().map(|expr| resolution.expr(expr).is_some()).collect()
+    }
+
+    #[test]
+    fn resolve_globals() {
+        assert_eq!(printed_globals("var a = 1;\n{ print a; var a = 2; print a; { print a; } }\nprint a;"), vec![true, false, false, true]);
+        assert_eq!(printed_globals("for (var i = 0; i < 1; i = i + 1) print i;\nprint i;"), vec![false, true]);
+
+        let ast = parse_source("var a = 1; { var b = a; }").unwrap();
+        let mut globals = Globals::new();
+        let resolution = resolve(&ast, &mut globals);
+        assert!(resolution.stmt(ast.statements[0]).is_some());
+        assert!(resolution.stmt(ast.statements[1]).is_none());
+        // Slots are made up front, but nothing is defined until the program runs.
+        assert!(globals.names().is_empty());
+    }
+
+    #[test]
+    fn prelude_is_read_only() {
+        let mut prelude = Globals::new();
+        prelude.define_name("greeting", Value::from("hi"));
+        let mut globals = Globals::from_prelude(&prelude);
+        let name = crate::scanner::scan_tokens("greeting").unwrap().remove(0);
+
+        assert!(globals.assign_name(&name, Value::Nil).is_err());
+        assert_eq!(globals.heap_size(), 0);
+
+        globals.define_name("greeting", Value::Nil);
+        assert!(globals.assign_name(&name, Value::from("bye")).is_ok());
+        assert_eq!(globals.get_name("greeting"), Some(Value::from("bye")));
+    }
+}
@@ -0,0 +1,143 @@
+//! Snapshots of everything a running program keeps alive, written as JSON for looking into
+//! leaks and what holds on to memory. `jlox --heap-snapshot-on-exit=<file>` writes one once
+//! the program ends, and also lets the script write its own with `dumpHeap(path)` from
+//! `import native "debug";`.
+//!
+//! The snapshot lists the scopes each call frame is in as `roots`, and every scope,
+//! function, string builder and channel reachable from them as `objects`, each with an
+//! `id`. Values that point at an object hold its id in `ref`; numbers, strings and the
+//! other plain values are written out in place, as `print` shows them.
+
+use crate::environment::Environment;
+use crate::function::Function;
+use crate::interpreter::Value;
+use crate::native::StringBuilder;
+use crate::shared::Shared;
+use crate::stack::CallStack;
+use crate::threads::Channel;
+
+use serde_json::{json, Map, Value as Json};
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+enum Object {
+    Scope(Shared<Environment>),
+    Function(Shared<Function>),
+    Builder(Shared<StringBuilder>),
+    Channel(Channel),
+}
+
+impl Object {
+    // What identifies the object, shared by every copy of a value pointing at it.
+    fn address(&self) -> usize {
+        match self {
+            Object::Scope(scope) => Shared::as_ptr(scope) as usize,
+            Object::Function(function) => Shared::as_ptr(function) as usize,
+            Object::Builder(builder) => Shared::as_ptr(builder) as usize,
+            Object::Channel(channel) => channel.address(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Snapshot {
+    ids: HashMap<usize, usize>,
+    // Objects that have an id but haven't been written out yet.
+    pending: Vec<(usize, Object)>,
+    objects: Vec<Json>,
+}
+
+impl Snapshot {
+    fn id(&mut self, object: Object) -> usize {
+        let next = self.ids.len() + 1;
+        let id = *self.ids.entry(object.address()).or_insert(next);
+        if id == next {
+            self.pending.push((id, object));
+        }
+        id
+    }
+
+    fn value(&mut self, value: &Value) -> Json {
+        let object = match value {
+            Value::Function(function) => Object::Function(Shared::clone(function)),
+            Value::Builder(builder) => Object::Builder(Shared::clone(builder)),
+            Value::Channel(channel) => Object::Channel(channel.clone()),
+            _ => return json!({ "type": value.type_name(), "value": value.to_string() }),
+        };
+        json!({ "type": value.type_name(), "ref": self.id(object) })
+    }
+
+    fn scope(&mut self, id: usize, scope: &Environment) -> Json {
+        let mut variables = Map::new();
+        for (name, value) in scope.values() {
+            variables.insert(name, self.value(&value));
+        }
+        let enclosing = scope.enclosing.as_ref().map(|enclosing| self.id(Object::Scope(Shared::clone(enclosing))));
+        json!({ "id": id, "type": "scope", "variables": variables, "enclosing": enclosing })
+    }
+
+    fn object(&mut self, id: usize, object: Object) -> Json {
+        match object {
+            Object::Scope(scope) => self.scope(id, &scope),
+            Object::Function(function) => {
+                let closure = self.id(Object::Scope(Shared::clone(&function.closure)));
+                json!({ "id": id, "type": "function", "name": function.name(), "arity": function.arity(), "closure": closure })
+            },
+            Object::Builder(builder) => json!({ "id": id, "type": "string builder", "bytes": builder.capacity() }),
+            Object::Channel(_) => json!({ "id": id, "type": "channel" }),
+        }
+    }
+}
+
+/// Writes a snapshot of everything reachable from the frames on `stack`.
+pub(crate) fn write_snapshot(stack: &CallStack, writer: impl Write) -> io::Result<()> {
+    // Copied out first, so the stack isn't locked while the scopes are walked.
+    let frames: Vec<(String, Option<Shared<Environment>>)> = stack.0.borrow().iter()
+        .map(|frame| (frame.function.clone(), frame.environment.clone()))
+        .collect();
+
+    let mut snapshot = Snapshot::default();
+    let roots: Vec<Json> = frames.into_iter()
+        .map(|(function, scope)| {
+            let scope = scope.map(|scope| snapshot.id(Object::Scope(scope)));
+            json!({ "frame": function, "scope": scope })
+        })
+        .collect();
+    while let Some((id, object)) = snapshot.pending.pop() {
+        let object = snapshot.object(id, object);
+        snapshot.objects.push(object);
+    }
+    snapshot.objects.sort_by_key(|object| object["id"].as_u64());
+
+    serde_json::to_writer(writer, &json!({ "roots": roots, "objects": snapshot.objects }))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+
+    use serde_json::Value as Json;
+
+    #[test]
+    fn snapshot() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.eval("import native \"strings\"; var counter = { var text = StringBuilder(); () => text }; var n = 1;").unwrap();
+        let mut file: Vec<u8> = Vec::new();
+        interpreter.write_heap_snapshot(&mut file).unwrap();
+        let snapshot: Json = serde_json::from_slice(&file).unwrap();
+
+        let objects = snapshot["objects"].as_array().unwrap();
+        let object = |id: &Json| objects.iter().find(|object| object["id"] == *id).unwrap();
+        let globals = object(&snapshot["roots"][0]["scope"]);
+        assert_eq!(globals["variables"]["n"], serde_json::json!({ "type": "number", "value": "1" }));
+
+        // The function keeps the block's scope alive, and the builder with it.
+        let function = object(&globals["variables"]["counter"]["ref"]);
+        assert_eq!(function["type"], "function");
+        let closure = object(&function["closure"]);
+        assert_eq!(object(&closure["variables"]["text"]["ref"])["type"], "string builder");
+        assert_eq!(closure["enclosing"], snapshot["roots"][0]["scope"]);
+    }
+}
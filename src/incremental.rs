@@ -0,0 +1,277 @@
+//! Parsing a document again after an edit without starting over, for editors that want
+//! diagnostics on every keystroke in large files.
+//!
+//! A `Document` remembers where each top-level statement starts and ends and how far the
+//! parser read ahead while parsing it. An edit only parses again from the first statement
+//! whose parse looked at the changed text, and stops as soon as a statement ends where an
+//! old one did, past the edit. The statements after that keep their errors, moved to
+//! where the edit shifted them.
+
+use crate::error::{CompileError, ParseError, ScanError};
+use crate::parser::Parser;
+use crate::scanner::{Dialect, Scanner, Token};
+
+use std::ops::Range;
+
+/// A top-level statement and the space before it, and the errors in them. It ends where
+/// the next one starts.
+#[derive(Debug, Clone)]
+struct Chunk {
+    start: usize,
+    // Past the statement's end when parsing the statement needed to look at the tokens after it.
+    read_until: usize,
+    errors: Vec<CompileError>,
+}
+
+/// A line and a column as errors count them, from 1 and in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    line: i32,
+    column: usize,
+}
+
+impl Position {
+    fn of(text: &str, offset: usize) -> Self {
+        let before = &text[..offset];
+        let line = before.matches('\n').count() as i32 + 1;
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Position { line, column: before[line_start..].chars().count() + 1 }
+    }
+
+    // From a position in a piece of the text starting at `origin` to one in the whole text.
+    fn in_text(self, origin: Position) -> Self {
+        if self.line == 1 {
+            Position { line: origin.line, column: origin.column + self.column - 1 }
+        } else {
+            Position { line: origin.line + self.line - 1, column: self.column }
+        }
+    }
+}
+
+/// A Lox source file being edited, with its parse errors kept up to date.
+pub struct Document {
+    text: String,
+    dialect: Dialect,
+    chunks: Vec<Chunk>,
+}
+
+impl Document {
+    pub fn new(text: &str) -> Self {
+        Self::with_dialect(text, Dialect::default())
+    }
+
+    pub fn with_dialect(text: &str, dialect: Dialect) -> Self {
+        let mut document = Self { text: text.to_string(), dialect, chunks: Vec::new() };
+        document.parse_from(0, 0, Vec::new());
+        document
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The scan and parse errors in the document, as `parser::parse_source_lossy` reports them.
+    pub fn diagnostics(&self) -> Vec<CompileError> {
+        self.chunks.iter().flat_map(|chunk| chunk.errors.iter().cloned()).collect()
+    }
+
+    /// Replaces the bytes in `range` with `replacement` and parses what that changed again.
+    /// Returns how many statements were parsed again.
+    ///
+    /// Panics if `range` is out of bounds or doesn't start and end on character boundaries,
+    /// like `String::replace_range`.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) -> usize {
+        let old_end = Position::of(&self.text, range.end);
+        self.text.replace_range(range.clone(), replacement);
+        let new_end = range.start + replacement.len();
+
+        // Statements whose parse never looked as far as the edit are left alone.
+        let first = self.chunks.iter()
+            .position(|chunk| chunk.read_until >= range.start)
+            .unwrap_or(self.chunks.len().saturating_sub(1));
+        let start = self.chunks.get(first).map_or(0, |chunk| chunk.start);
+
+        // The old statements after the edit, where parsing can stop.
+        let mut old = self.chunks.split_off(first);
+        old.retain(|chunk| chunk.start >= range.end);
+        let shift = Shift {
+            bytes: new_end as isize - range.end as isize,
+            old_end,
+            new_end: Position::of(&self.text, new_end),
+        };
+        for chunk in &mut old {
+            shift.chunk(chunk);
+        }
+
+        self.parse_from(start, new_end, old)
+    }
+
+    // Parses the statements from `start` to the end, or until one ends where a statement of
+    // `old` starts, and keeps the rest of `old` from there.
+    fn parse_from(&mut self, start: usize, changed_until: usize, mut old: Vec<Chunk>) -> usize {
+        let origin = Position::of(&self.text, start);
+        let mut parser = Parser::from_scanner(Scanner::new(&self.text[start..]).with_dialect(self.dialect));
+        // Errors scanned while reading ahead, which belong to a later statement.
+        let mut scan_errors: Vec<ScanError> = Vec::new();
+        let mut parsed = 0;
+        let mut chunk_start = start;
+
+        while parser.parse_next().is_some() {
+            parsed += 1;
+            let (last, furthest) = parser.last_tokens();
+            let end = start + last.span.end;
+            let read_until = start + furthest.span.end;
+            let last = Position { line: last.line, column: last.column };
+
+            scan_errors.extend(parser.take_scan_errors());
+            let (mine, later): (Vec<ScanError>, Vec<ScanError>) = scan_errors.into_iter()
+                .partition(|error| Position { line: error.line, column: error.column } < last);
+            scan_errors = later;
+
+            let mut errors: Vec<CompileError> = mine.into_iter().map(CompileError::Scan)
+                .chain(parser.take_errors().into_iter().map(CompileError::Parse))
+                .collect();
+            sort(&mut errors);
+            for error in &mut errors {
+                move_error(error, origin, start as isize);
+            }
+            self.chunks.push(Chunk { start: chunk_start, read_until, errors });
+            chunk_start = end;
+
+            if end >= changed_until {
+                if let Some(index) = old.iter().position(|chunk| chunk.start >= end) {
+                    if old[index].start == end {
+                        self.chunks.extend(old.drain(index..));
+                        return parsed;
+                    }
+                }
+            }
+        }
+
+        // What is left after the last statement goes with it.
+        scan_errors.extend(parser.take_scan_errors());
+        let mut errors: Vec<CompileError> = scan_errors.into_iter().map(CompileError::Scan).collect();
+        for error in &mut errors {
+            move_error(error, origin, start as isize);
+        }
+        match self.chunks.last_mut() {
+            Some(chunk) => {
+                chunk.errors.extend(errors);
+                sort(&mut chunk.errors);
+                chunk.read_until = self.text.len();
+            },
+            None => self.chunks.push(Chunk { start, read_until: self.text.len(), errors }),
+        }
+        parsed
+    }
+}
+
+// In the order `parser::parse_source_lossy` gives: scan errors before parse errors at the
+// same place.
+fn sort(errors: &mut [CompileError]) {
+    errors.sort_by_key(|error| (error.line(), error.column(), matches!(error, CompileError::Parse(_))));
+}
+
+// How an edit moved the text after it.
+struct Shift {
+    bytes: isize,
+    old_end: Position,
+    new_end: Position,
+}
+
+impl Shift {
+    fn position(&self, position: Position) -> Position {
+        if position.line == self.old_end.line {
+            Position { line: self.new_end.line, column: position.column + self.new_end.column - self.old_end.column }
+        } else {
+            Position { line: position.line + self.new_end.line - self.old_end.line, column: position.column }
+        }
+    }
+
+    fn chunk(&self, chunk: &mut Chunk) {
+        chunk.start = chunk.start.wrapping_add_signed(self.bytes);
+        chunk.read_until = chunk.read_until.wrapping_add_signed(self.bytes);
+        for error in &mut chunk.errors {
+            let position = self.position(Position { line: error.line(), column: error.column() });
+            set_position(error, position, self.bytes);
+        }
+    }
+}
+
+// From a position in the piece parsed from `origin`, `bytes` into the text, to one in the text.
+fn move_error(error: &mut CompileError, origin: Position, bytes: isize) {
+    let position = Position { line: error.line(), column: error.column() }.in_text(origin);
+    set_position(error, position, bytes);
+}
+
+fn set_position(error: &mut CompileError, position: Position, bytes: isize) {
+    match error {
+        CompileError::Scan(ScanError { line, column, .. }) => {
+            *line = position.line;
+            *column = position.column;
+        },
+        CompileError::Parse(ParseError { token, .. }) => move_token(token, position, bytes),
+        CompileError::Lint(warning) => move_token(&mut warning.token, position, bytes),
+    }
+}
+
+fn move_token(token: &mut Token, position: Position, bytes: isize) {
+    token.line = position.line;
+    token.column = position.column;
+    token.span.start = token.span.start.wrapping_add_signed(bytes);
+    token.span.end = token.span.end.wrapping_add_signed(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn assert_up_to_date(document: &Document) {
+        let (_, expected) = parser::parse_source_lossy(document.text());
+        assert_eq!(document.diagnostics(), expected, "for {:?}", document.text());
+    }
+
+    #[test]
+    fn reparses_only_the_edited_statement() {
+        let source: String = (0..100).map(|i| format!("var a{i} = {i} + 1;\n")).collect();
+        let mut document = Document::new(&source);
+        assert!(document.diagnostics().is_empty());
+
+        let offset = source.find("var a50").unwrap();
+        assert_eq!(document.edit(offset + 10..offset + 12, "@ +"), 1);
+        assert_up_to_date(&document);
+        assert_eq!(document.diagnostics()[0].to_string(), "[line 51] Error: unknown character.");
+
+        // A statement can swallow the ones after it, until it ends where an old one did.
+        let offset = document.text().find("var a20").unwrap();
+        assert_eq!(document.edit(offset + 16..offset + 17, ""), 1);
+        assert_up_to_date(&document);
+        assert_eq!(document.edit(offset + 16..offset + 16, ";"), 2);
+        assert_up_to_date(&document);
+    }
+
+    #[test]
+    fn random_edits() {
+        const PIECES: [&str; 12] = ["", ";", "\n", "var ", "x", " = ", "(a, b) => a", "{ ", " }", "\"", "print 1", "@"];
+        let source = "var x = 1;\nprint x + 2;\n{ var y = (a) => { a }; }\nif (x) { print \"é\"; }\nprint (1;\n";
+        let mut document = Document::new(source);
+        assert_up_to_date(&document);
+
+        // The same edits every run, from a fixed seed.
+        let mut state: u64 = 7;
+        let mut next = |bound: usize| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize % bound
+        };
+        for _ in 0..500 {
+            let boundaries: Vec<usize> = document.text().char_indices().map(|(offset, _)| offset)
+                .chain(std::iter::once(document.text().len()))
+                .collect();
+            let start = boundaries[next(boundaries.len())];
+            let end = boundaries.iter().copied().filter(|&offset| offset >= start).nth(next(4)).unwrap_or(start);
+            document.edit(start..end, PIECES[next(PIECES.len())]);
+            assert_up_to_date(&document);
+        }
+    }
+}
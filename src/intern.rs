@@ -0,0 +1,68 @@
+//! Interned strings for identifiers and other lexemes. A program names the same few
+//! variables over and over, so the scanner keeps one copy of every distinct lexeme and
+//! tokens share it. Environments key their variables by the same strings, which makes
+//! defining or assigning a variable a reference count bump instead of an allocation.
+
+use crate::shared::Shared;
+
+use std::collections::HashSet;
+
+/// A shared, immutable string. Cloning one is cheap.
+pub type Symbol = Shared<str>;
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The one copy of `text`, allocated the first time it is seen.
+    pub fn intern(&mut self, text: &str) -> Symbol {
+        match self.strings.get(text) {
+            Some(symbol) => Shared::clone(symbol),
+            None => {
+                let symbol: Symbol = Shared::from(text);
+                self.strings.insert(Shared::clone(&symbol));
+                symbol
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_copies() {
+        let mut interner = Interner::new();
+        let first = interner.intern("count");
+        let second = interner.intern("count");
+        let other = interner.intern("total");
+
+        assert!(Shared::ptr_eq(&first, &second));
+        assert!(!Shared::ptr_eq(&first, &other));
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn scanner_interns_lexemes() {
+        let tokens = crate::scanner::scan_tokens("var a = 1; a = a + 1;").unwrap();
+        let names: Vec<&Symbol> = tokens.iter().filter(|token| &*token.lexeme == "a").map(|token| &token.lexeme).collect();
+
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().all(|name| Shared::ptr_eq(name, names[0])));
+    }
+}
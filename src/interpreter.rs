@@ -1,369 +1,1315 @@
-use crate::scanner::{Literal, TokenType};
-use crate::syntax::{Expr, Stmt};
+use crate::codes::ErrorCode;
+use crate::error::{ConversionError, LoxError, RuntimeError};
+use crate::parser;
+use crate::scanner::{Dialect, Literal, Token, TokenType};
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
 use crate::environment::Environment;
+use crate::function::Function;
+use crate::globals::{self, Globals, Resolution};
+use crate::heap;
+#[cfg(feature = "jit")]
+use crate::jit::JitMode;
+use crate::native::{self, Clock, NativeBody, NativeFunction, NativeModule, Rng, StringBuilder, TimeModule};
+use crate::observer::BoxedObserver;
+use crate::replay::Recording;
+use crate::stack::{CallStack, Frame, FrameView};
+use crate::state;
+use crate::stats::Stats;
+use crate::suggest;
+use crate::threads::Channel;
 
-use std::rc::Rc;
+use crate::shared::{Lock, Output, Shared, Weak};
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const LAST_RESULT: &str = "_";
+
+/// How many calls may be in progress at once before the program is stopped. Every call
+/// costs stack frames in the interpreter, so this turns runaway recursion into a runtime
+/// error instead of a crash.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 128;
+
+// Ways of leaving a statement early that have to travel up through `execute`.
+enum Unwind {
+    Break,
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
 
 pub struct Interpreter {
-    environment: Rc<Environment>,
+    globals: Shared<Lock<Globals>>,
+    // The outermost scope, which holds the globals.
+    global_scope: Shared<Environment>,
+    environment: Shared<Environment>,
+    // Where the globals of the program being run are in `globals`.
+    resolution: Shared<Resolution>,
+    // A copy of the program being run, made for the first function it creates so the
+    // function can outlive the run.
+    running: Option<Shared<Ast>>,
     is_repl: bool,
+    dialect: Dialect,
+    // Where `print` and the REPL echo go.
+    output: Output,
+    // Where `eprint` goes.
+    error_output: Output,
+    // What was printed so far, when output is captured.
+    captured: Option<Shared<Lock<Vec<u8>>>>,
+    // Steps left before the program is stopped, or `None` to let it run forever.
+    fuel: Option<u64>,
+    // Bytes that variables may hold on the heap, or `None` for no limit.
+    memory_limit: Option<usize>,
+    // A running count of those bytes, kept up on define, assign and scope exit while there
+    // is a limit, so checking it doesn't walk every scope.
+    memory_used: Cell<usize>,
+    max_call_depth: usize,
+    // How deeply the programs given to `eval` may nest.
+    max_depth: usize,
+    // What the `random` natives draw from.
+    rng: Rng,
+    // What `clock` reads.
+    clock: Clock,
+    // Raised from outside, e.g. by a Ctrl-C handler, to stop the running program.
+    interrupt: Option<Arc<AtomicBool>>,
+    // Native modules that scripts can import, by name.
+    modules: HashMap<String, Shared<dyn NativeModule>>,
+    observers: Vec<BoxedObserver>,
+    stack: CallStack,
+    // What running the program cost so far, once enabled.
+    stats: Option<Stats>,
+    // Every scope opened so far and the line it opened on, once leak checking is enabled.
+    scopes: Option<Vec<(Weak<Environment>, i32)>>,
+    #[cfg(feature = "jit")]
+    jit: JitMode,
+}
+
+// What a worker thread started with `spawn` takes over from the interpreter that started it.
+pub(crate) struct Limits {
+    memory_limit: Option<usize>,
+    max_call_depth: usize,
+    interrupt: Option<Arc<AtomicBool>>,
+}
+
+/// Globals and native modules set up once and shared, read-only, by any number of
+/// interpreters. Each interpreter made from it starts from a copy of its globals, so
+/// scripts stay isolated from each other while skipping the setup. Cloning is cheap.
+#[derive(Clone)]
+pub struct Prelude {
+    globals: Shared<Globals>,
+    modules: HashMap<String, Shared<dyn NativeModule>>,
 }
 
 impl Interpreter {
     pub fn new(is_repl: bool) -> Self {
+        let mut interpreter = Self::with_globals(Globals::new(), HashMap::new(), is_repl);
+        for module in native::builtin_modules() {
+            interpreter.register_module(module);
+        }
+        interpreter
+    }
+
+    /// Creates an interpreter that sees everything in `prelude` but defines its own
+    /// globals separately. Assigning to a prelude variable is a runtime error.
+    pub fn with_prelude(prelude: &Prelude, is_repl: bool) -> Self {
+        Self::with_globals(Globals::from_prelude(&prelude.globals), prelude.modules.clone(), is_repl)
+    }
+
+    /// Freezes the globals and modules of this interpreter into a `Prelude`.
+    pub fn into_prelude(mut self) -> Prelude {
+        Prelude { globals: Shared::new(self.globals.borrow().clone()), modules: std::mem::take(&mut self.modules) }
+    }
+
+    fn with_globals(globals: Globals, modules: HashMap<String, Shared<dyn NativeModule>>, is_repl: bool) -> Self {
+        let globals = Shared::new(Lock::new(globals));
+        let global_scope = Shared::new(Environment::global(Shared::clone(&globals)));
+
         Self { 
-            environment: Rc::new(Environment::new()),
-            is_repl: is_repl,
+            environment: Shared::clone(&global_scope),
+            stack: CallStack::new(Shared::clone(&global_scope)),
+            globals,
+            global_scope,
+            resolution: Shared::new(Resolution::default()),
+            running: None,
+            is_repl,
+            dialect: Dialect::default(),
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+            captured: None,
+            fuel: None,
+            memory_limit: None,
+            memory_used: Cell::new(0),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_depth: parser::DEFAULT_MAX_DEPTH,
+            rng: Rng::from_clock(),
+            clock: Clock::new(),
+            interrupt: None,
+            modules,
+            observers: Vec::new(),
+            stats: None,
+            scopes: None,
+            #[cfg(feature = "jit")]
+            jit: JitMode::default(),
+        }
+    }
+
+    /// Makes `module` available to `import native "name";`, replacing any module that was
+    /// registered under the same name.
+    pub fn register_module(&mut self, module: Box<dyn NativeModule>) {
+        self.modules.insert(module.name().to_string(), Shared::from(module));
+    }
+
+    /// Sends everything the program prints to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+        self.captured = None;
+    }
+
+    /// Sends what the program writes with `eprint` to `error_output` instead of stderr.
+    pub fn set_error_output(&mut self, error_output: Output) {
+        self.error_output = error_output;
+    }
+
+    /// Records everything the program prints so it can be read back with `captured_output`.
+    pub fn capture_output(&mut self) {
+        let captured = Shared::new(Lock::new(Vec::new()));
+        self.output = Box::new(Capture(Shared::clone(&captured)));
+        self.captured = Some(captured);
+    }
+
+    /// The lines printed since `capture_output` was called.
+    pub fn captured_output(&self) -> Vec<String> {
+        match &self.captured {
+            Some(captured) => String::from_utf8_lossy(&captured.borrow()).lines().map(String::from).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Limits how long untrusted programs may run. Every statement and expression burns one
+    /// unit of fuel, and the program stops with a runtime error once none is left. `None`,
+    /// the default, means no limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Caps the approximate heap memory, in bytes, held by the program's variables. Storing
+    /// a value or building a string that would go over the cap is a runtime error. `None`,
+    /// the default, means no limit.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+        self.memory_used.set(self.environment.heap_size());
+    }
+
+    /// Runs programs written in `dialect` from now on. With `print_function`, this defines
+    /// the `print` and `println` natives, and with `jlox` the `clock` global that the book's
+    /// interpreter has, since `import` is an ordinary name there.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+        let mut functions = Vec::new();
+        if dialect.print_function {
+            functions.extend(native::print_functions());
+        }
+        if dialect.jlox {
+            functions.extend(TimeModule.functions());
+        }
+        for function in functions {
+            let name = function.name.clone();
+            self.set_global(&name, Value::Native(Shared::new(function)));
+        }
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Overrides `DEFAULT_MAX_CALL_DEPTH` for this interpreter.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Overrides `parser::DEFAULT_MAX_DEPTH` for the programs this interpreter parses.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Seeds the generator the `random` natives draw from, so runs with the same seed draw
+    /// the same numbers. Without a seed it is seeded from the clock.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Writes a snapshot of every scope, function, builder and channel the program keeps
+    /// alive, as described in `heap`.
+    pub fn write_heap_snapshot(&self, writer: impl Write) -> io::Result<()> {
+        heap::write_snapshot(&self.stack, writer)
+    }
+
+    /// Notes down what the program reads from outside from now on, for `recording`.
+    pub fn start_recording(&mut self) {
+        self.clock.record();
+    }
+
+    /// The seed and the clock readings since `start_recording`.
+    pub fn recording(&self) -> Recording {
+        Recording { seed: self.rng.seed(), clock: self.clock.recorded() }
+    }
+
+    /// Makes the program read what it read in the recorded run. Reading the clock more
+    /// often than that run did is a runtime error.
+    pub fn replay(&mut self, recording: &Recording) {
+        self.set_seed(recording.seed);
+        self.clock.replay(recording.clock.clone());
+    }
+
+    /// Decides when functions are compiled to native code. It is never done while fuel is
+    /// limited or an interrupt is set, since compiled code neither burns fuel nor polls the
+    /// flag, or in the `jlox` dialect.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, mode: JitMode) {
+        self.jit = mode;
+    }
+
+    /// Lets another thread or a signal handler stop the running program by setting `interrupt`.
+    /// The flag is polled on every loop iteration and function call, and cleared once the
+    /// program has stopped with an "Interrupted." runtime error.
+    pub fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) {
+        self.interrupt = Some(interrupt);
+    }
+
+    // Whether the interrupt was raised, clearing it if so.
+    pub(crate) fn take_interrupt(&self) -> bool {
+        self.interrupt.as_ref().is_some_and(|interrupt| interrupt.swap(false, Ordering::Relaxed))
+    }
+
+    pub(crate) fn limits(&self) -> Limits {
+        Limits { memory_limit: self.memory_limit, max_call_depth: self.max_call_depth, interrupt: self.interrupt.clone() }
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: Limits) {
+        self.memory_limit = limits.memory_limit;
+        self.max_call_depth = limits.max_call_depth;
+        self.interrupt = limits.interrupt;
+    }
+
+    // Calls a function without parameters whose body is `body` in `ast`, made at the top
+    // level of this interpreter. The tree's globals are looked up again here, since this
+    // interpreter numbers them on its own.
+    #[cfg(feature = "sync")]
+    pub(crate) fn call_body(&mut self, ast: Shared<Ast>, body: ExprId) -> Result<Value, RuntimeError> {
+        let resolution = globals::resolve(&ast, &mut self.globals.borrow_mut());
+        let span = ast.expr_span(body);
+        let paren = Token { token_type: TokenType::RightParen, lexeme: ")".into(), literal: None, line: span.line, column: 0, span: crate::scanner::Span::new(span.start, span.end) };
+        let function = Function {
+            params: Vec::new(),
+            body,
+            ast,
+            resolution: Shared::new(resolution),
+            closure: Shared::clone(&self.global_scope),
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
+        };
+        self.call_function(&function, &paren, &[])
+    }
+
+    /// Calls `observer` around every statement and native function call from now on.
+    pub fn add_observer(&mut self, observer: BoxedObserver) {
+        self.observers.push(observer);
+    }
+
+    /// A handle on the call stack that stays valid while the interpreter runs, so
+    /// observers can inspect the stack from their callbacks.
+    pub fn call_stack(&self) -> CallStack {
+        self.stack.clone()
+    }
+
+    /// The frames of the call stack, innermost first. Between runs only the script frame
+    /// is left, holding the globals.
+    pub fn stack_frames(&self) -> Vec<FrameView> {
+        self.stack.frames()
+    }
+
+    /// Starts counting statements, scopes, values and call depth, at a small cost to speed.
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(Stats { environments: 1, peak_call_depth: self.stack.depth(), ..Stats::default() });
+    }
+
+    /// What the program cost to run so far, if `enable_stats` was called.
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+
+    /// Starts keeping track of every scope the program opens, so `leaked_scopes` can tell
+    /// which of them are never freed.
+    pub fn enable_leak_check(&mut self) {
+        self.scopes = Some(Vec::new());
+    }
+
+    /// Drops the interpreter and returns the line each scope that outlives it opened on,
+    /// in the order they opened. Unless the host still holds a value from the program,
+    /// such a scope is kept alive by a reference cycle: a function stored in a scope it
+    /// closes over. Empty if `enable_leak_check` was never called.
+    pub fn leaked_scopes(mut self) -> Vec<i32> {
+        let scopes = self.scopes.take().unwrap_or_default();
+        drop(self);
+        scopes.into_iter().filter(|(scope, _)| scope.strong_count() > 0).map(|(_, line)| line).collect()
+    }
+
+    /// Defines or overwrites the global `name`, so a script can read input from the host.
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        self.globals.borrow_mut().define_name(name, value.into());
+    }
+
+    /// The current value of the global `name`, if the script or host defined it.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().get_name(name)
+    }
+
+    /// Every global defined so far, by the script, the host or a prelude, sorted by name.
+    /// Values are cheap to clone, so this hands out copies rather than holding the
+    /// globals locked while the host looks through them.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        self.globals.borrow().values()
+    }
+
+    /// The globals whose values are of `kind`, such as the functions a plugin script
+    /// defines as its entry points.
+    pub fn globals_of(&self, kind: ValueKind) -> Vec<(String, Value)> {
+        self.globals().into_iter().filter(|(_, value)| value.kind() == kind).collect()
+    }
+
+    /// Writes the globals that are plain data to `writer` as JSON, to be read back by
+    /// `load_state` in a later run. Functions are left out; running their source again
+    /// brings them back.
+    pub fn dump_state(&self, writer: impl Write) -> io::Result<()> {
+        state::dump(&self.globals(), writer)
+    }
+
+    /// Defines the globals written by `dump_state`, overwriting any of the same name. A
+    /// reader that isn't such a checkpoint is an `InvalidData` error and defines nothing.
+    pub fn load_state(&mut self, reader: impl Read) -> io::Result<()> {
+        let state = state::load(reader)?;
+        let mut globals = self.globals.borrow_mut();
+        for (name, value) in state {
+            globals.define_name(&name, value);
         }
+        Ok(())
+    }
+
+    pub fn interpret(&mut self, ast: &Ast) -> Result<(), RuntimeError> {
+        self.run(ast).map(|_| ())
+    }
+
+    /// Scans, parses and runs `source`, returning the value of its last top-level
+    /// expression statement, or `Value::Nil` if it has none. Globals defined by earlier
+    /// calls stay visible, so a host can feed a program to the interpreter piece by piece.
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let ast = parser::parse_source_with_max_depth(source, self.dialect, self.max_depth)?;
+        Ok(self.run(&ast)?)
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Stmt>) -> Result<(), ()> {
-        for statement in statements {
-            match self.execute(&statement) {
-                Ok(()) => {},
-                Err(e) => {
-                    println!("Failed to interpret statement.");
-                    println!("{}", e);
-                    return Err(());
+    fn run(&mut self, ast: &Ast) -> Result<Value, RuntimeError> {
+        // In the REPL a failing input must not leave half of its declarations behind,
+        // so the globals are rolled back to how they were before the input ran.
+        let snapshot = if self.is_repl { Some(self.globals.borrow().clone()) } else { None };
+        let mut last_value = Value::Nil;
+        let resolution = tracing::debug_span!("resolve").in_scope(|| globals::resolve(ast, &mut self.globals.borrow_mut()));
+        self.resolution = Shared::new(resolution);
+        self.running = None;
+        if self.memory_limit.is_some() {
+            self.memory_used.set(self.environment.heap_size());
+        }
+
+        let _execute = tracing::debug_span!("execute", statements = ast.statements.len()).entered();
+
+        for &statement in &ast.statements {
+            let result = match &ast[statement] {
+                Stmt::Expression { expression } => {
+                    self.burn_fuel(ast.stmt_span(statement).line)?;
+                    self.begin_statement(ast, statement);
+                    self.observe(ast, statement, |interpreter| interpreter.expression_statement(ast, *expression))
+                        .map(|value| last_value = value)
+                        .map_err(Unwind::from)
+                },
+                _ => self.execute(ast, statement),
+            };
+
+            match result {
+                // The parser rejects 'break' outside of loops, so it never reaches the top level.
+                Ok(()) | Err(Unwind::Break) => {},
+                Err(Unwind::Error(e)) => {
+                    if let Some(snapshot) = snapshot {
+                        *self.globals.borrow_mut() = snapshot;
+                        self.set_environment(Shared::clone(&self.global_scope));
+                        self.memory_used.set(self.environment.heap_size());
+                    }
+                    return Err(e);
                 }
             }
         }
 
-        Ok(())
+        Ok(last_value)
+    }
+
+    fn execute(&mut self, ast: &Ast, stmt: StmtId) -> Result<(), Unwind> {
+        self.observe(ast, stmt, |interpreter| interpreter.execute_statement(ast, stmt))
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), String> {
-        match stmt {
+    // Runs `run`, which executes `stmt`, between the observers' statement callbacks.
+    fn observe<T>(&mut self, ast: &Ast, stmt: StmtId, run: impl FnOnce(&mut Self) -> T) -> T {
+        if self.observers.is_empty() {
+            return run(self);
+        }
+
+        let line = Some(ast.stmt_span(stmt).line);
+        for observer in &mut self.observers {
+            observer.before_statement(ast, stmt, line, &self.environment);
+        }
+        let result = run(self);
+        for observer in &mut self.observers {
+            observer.after_statement(ast, stmt, line, &self.environment);
+        }
+        result
+    }
+
+    // Bookkeeping for every statement that runs, before it does.
+    fn begin_statement(&mut self, ast: &Ast, stmt: StmtId) {
+        if let Some(stats) = &mut self.stats {
+            stats.statements += 1;
+        }
+        self.stack.set_line(ast.stmt_span(stmt).line);
+    }
+
+    fn execute_statement(&mut self, ast: &Ast, stmt: StmtId) -> Result<(), Unwind> {
+        self.burn_fuel(ast.stmt_span(stmt).line)?;
+        self.begin_statement(ast, stmt);
+
+        match &ast[stmt] {
             Stmt::Expression { expression } => {
-                match self.evaluate(expression) {
-                    Ok(value) => { 
-                        if self.is_repl {
-                            println!("{}", self.stringify(&value));
-                        }
-                        Ok(())
-                    },
-                    Err(e) => { return Err(e); }
-                }
+                self.expression_statement(ast, *expression)?;
+                Ok(())
             },
-            Stmt::Print { expression } => {
-                let value = self.evaluate(expression);
-                match value {
-                    Ok(value) => {
-                        println!("{}", self.stringify(&value));
-                        Ok(())
-                    },
-                    Err(e) => {
-                        return Err(e);
-                    }
-                }
+            Stmt::Print { keyword: _, expression } => {
+                let value = self.evaluate(ast, *expression)?;
+                let text = self.stringify(&value);
+                self.print_line(&text);
+
+                Ok(())
             },
             Stmt::Variable { name, initializer } => {
-                match initializer {
-                    Some(expr) => {
-                        let value = match self.evaluate(expr) {
-                            Ok(value) => value,
-                            Err(e) => { return Err(e); }
-                        };
-                        self.environment.define(name, value);
-                    },
-                    None => {}
+                if let Some(expr) = initializer {
+                    let value = self.evaluate(ast, *expr)?;
+                    self.check_memory(name, value.heap_size())?;
+                    self.notify_assign(name, &value);
+                    let size = value.heap_size();
+                    let replaced = match self.resolution.stmt(stmt) {
+                        Some(global) => self.globals.borrow_mut().define(global, value),
+                        None => self.environment.define(name, value),
+                    };
+                    self.track_memory(replaced.as_ref(), size);
                 }
 
                 Ok(())
             },
             Stmt::Block { statements } => {
-                self.environment = Rc::new(Environment::from(Rc::clone(&self.environment)));
-                for statement in statements {
-                    match self.execute(statement) {
-                        Ok(_) => {},
-                        Err(e) => { 
-                            self.environment = match &self.environment.enclosing {
-                                Some(enclosing) => Rc::clone(&enclosing),
-                                None => { return Err(format!("{}\n{}", "Enclosing environment not found.", e)); }
-                            };
-
-                            return Err(e);
-                        }
-                    }
-                }
+                let previous = Shared::clone(&self.environment);
+                self.enter_scope(ast.stmt_span(stmt).line);
 
-                self.environment = match &self.environment.enclosing {
-                    Some(enclosing) => Rc::clone(&enclosing),
-                    None => { return Err(String::from("Enclosing environment not found.")); }
-                };
+                let result = statements.iter().try_for_each(|&statement| self.execute(ast, statement));
 
-                Ok(())
+                self.close_scope(previous);
+                result
             },
-            Stmt::If { condition, then_branch, else_branch } => {
-                if is_truthy(&self.evaluate(condition)?) {
-                    self.execute(then_branch)
-                } else if else_branch.is_some() {
-                    // TODO: why?
-                    match else_branch {
-                        Some(statement) => self.execute(statement),
-                        None => Err("This can literally never hit.".to_string())
-                    }
+            Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+                if is_truthy(&self.evaluate(ast, *condition)?) {
+                    self.execute(ast, *then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(ast, *else_branch)
                 } else {
                     Ok(())
                 }
             },
-            Stmt::While { condition, body } => {
-                while is_truthy(&self.evaluate(condition)?) {
-                    match self.execute(body) {
-                        Ok(_) => {},
-                        Err(e) => {
-                            if e == "break".to_string() {
-                                return Ok(());
-                            } else {
-                                return Err(e);
-                            }
-                        }
-                    }
+            Stmt::While { keyword, condition, body } => {
+                self.run_loop(ast, keyword, Some(*condition), None, *body)
+            },
+            Stmt::For { keyword, initializer, condition, increment, body } => {
+                // The loop variable lives in a scope of its own, around the loop.
+                let previous = Shared::clone(&self.environment);
+                self.enter_scope(ast.stmt_span(stmt).line);
+
+                let result = match initializer {
+                    Some(initializer) => self.execute(ast, *initializer),
+                    None => Ok(()),
+                };
+                let result = result.and_then(|_| self.run_loop(ast, keyword, *condition, *increment, *body));
+
+                self.close_scope(previous);
+                result
+            },
+            Stmt::Break {  } => {
+                Err(Unwind::Break)
+            },
+            Stmt::Debugger { keyword } => {
+                for observer in &mut self.observers {
+                    observer.breakpoint(keyword.line, &self.environment);
+                }
+                Ok(())
+            },
+            Stmt::Import { keyword: _, module } => {
+                let name = match &module.literal {
+                    Some(Literal::Str(name)) => name,
+                    _ => { return Err(self.generate_error(ErrorCode::UnknownModule, module, "Module name must be a string.").into()); }
+                };
+                let functions = match self.modules.get(&**name) {
+                    Some(native_module) => native_module.functions(),
+                    None => { return Err(self.generate_error(ErrorCode::UnknownModule, module, &format!("Unknown native module '{name}'.")).into()); }
+                };
+
+                for function in functions {
+                    let name = function.name.clone();
+                    self.environment.define_name(&name, Value::Native(Shared::new(function)));
                 }
 
                 Ok(())
             },
-            Stmt::Break {  } => {
-                // TODO: this is super ghetto
-                Err("break".to_string())
+            Stmt::Error { tokens } => {
+                match tokens.first() {
+                    Some(token) => Err(self.generate_error(ErrorCode::ParseErrorReached, token, "Cannot execute a statement that failed to parse.").into()),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn expression_statement(&mut self, ast: &Ast, expression: ExprId) -> Result<Value, RuntimeError> {
+        let value = self.evaluate(ast, expression)?;
+        if self.is_repl {
+            let text = self.stringify(&value);
+            self.print_line(&text);
+            let replaced = self.globals.borrow_mut().define_name(LAST_RESULT, value.clone());
+            self.track_memory(replaced.as_ref(), value.heap_size());
+        }
+
+        Ok(value)
+    }
+
+    fn evaluate(&mut self, ast: &Ast, expr: ExprId) -> Result<Value, RuntimeError> {
+        let value = self.evaluate_expression(ast, expr)?;
+
+        // Variables, groupings, assignments, logical operators, blocks and ifs hand on a
+        // value that already exists.
+        if let Some(stats) = &mut self.stats {
+            if matches!(ast[expr], Expr::Literal { .. } | Expr::Unary { .. } | Expr::Binary { .. } | Expr::Call { .. }) {
+                stats.count_value(&value);
             }
         }
+        Ok(value)
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> Result<Value, String> {
-        match expr {
+    // Every nested expression and call pays for this function's stack frame, so arms that
+    // do more than recurse live in methods of their own.
+    fn evaluate_expression(&mut self, ast: &Ast, expr: ExprId) -> Result<Value, RuntimeError> {
+        self.burn_fuel(ast.expr_span(expr).line)?;
+
+        match &ast[expr] {
             Expr::Literal { value } => {
                 self.literal_to_value(value)
             },
             Expr::Grouping { expression } => {
-                self.evaluate(expression)
+                self.evaluate(ast, *expression)
             },
-            Expr::Variable { name } => {
-                match self.environment.get(name) {
-                    Some(value) => Ok(value.clone()),
-                    None => Err(format!("Variable '{}' is undefined.", name.lexeme))
-                }
+            Expr::Block { statements, value } => self.block(ast, expr, statements, *value),
+            Expr::If { keyword: _, condition, then_branch, else_branch } => self.if_expression(ast, *condition, *then_branch, *else_branch),
+            Expr::Variable { name } => self.variable(expr, name),
+            Expr::Assign { name, value } => self.assign(ast, expr, name, *value),
+            Expr::Logical { .. } => self.logical(ast, expr),
+            Expr::Unary { operator, right } => self.unary(ast, operator, *right),
+            Expr::Binary { .. } => self.binary(ast, expr),
+            Expr::Call { callee, paren, arguments } => self.call(ast, *callee, paren, arguments),
+            Expr::Lambda { params, arrow: _, body } => Ok(self.lambda(ast, params, *body)),
+            Expr::Error { token } => {
+                Err(self.generate_error(ErrorCode::ParseErrorReached, token, "Cannot evaluate an expression that failed to parse."))
+            }
+        }
+    }
+
+    fn variable(&mut self, expr: ExprId, name: &Token) -> Result<Value, RuntimeError> {
+        let value = match self.resolution.expr(expr) {
+            Some(global) => self.globals.borrow().get(global),
+            None => self.environment.get(name),
+        };
+        match value {
+            Some(value) => Ok(value),
+            None if self.dialect.jlox => Err(self.generate_error(ErrorCode::UndefinedVariable, name, &format!("Undefined variable '{}'.", name.lexeme))),
+            None => {
+                let error = self.generate_error(ErrorCode::UndefinedVariable, name, &format!("Variable '{}' is undefined.", name.lexeme));
+                Err(self.suggest_name(error, name))
+            }
+        }
+    }
+
+    fn block(&mut self, ast: &Ast, expr: ExprId, statements: &[StmtId], value: Option<ExprId>) -> Result<Value, RuntimeError> {
+        let previous = Shared::clone(&self.environment);
+        self.enter_scope(ast.expr_span(expr).line);
+
+        let result = self.block_value(ast, statements, value);
+
+        self.close_scope(previous);
+        result
+    }
+
+    fn if_expression(&mut self, ast: &Ast, condition: ExprId, then_branch: ExprId, else_branch: Option<ExprId>) -> Result<Value, RuntimeError> {
+        if is_truthy(&self.evaluate(ast, condition)?) {
+            self.evaluate(ast, then_branch)
+        } else if let Some(else_branch) = else_branch {
+            self.evaluate(ast, else_branch)
+        } else {
+            Ok(Value::Nil)
+        }
+    }
+
+    fn assign(&mut self, ast: &Ast, expr: ExprId, name: &Token, value: ExprId) -> Result<Value, RuntimeError> {
+        let new_value = self.evaluate(ast, value)?;
+        let size = new_value.heap_size();
+        self.check_memory(name, size)?;
+        // Only looked up for the running count of memory, which is kept when there is a limit.
+        let old_value = match self.memory_limit {
+            Some(_) => match self.resolution.expr(expr) {
+                Some(global) => self.globals.borrow().get(global),
+                None => self.environment.get(name),
             },
-            Expr::Assign { name, value } => {
-                let new_value = self.evaluate(value)?;
-                self.environment.assign(name, new_value)
+            None => None,
+        };
+        let assigned = match self.resolution.expr(expr) {
+            Some(global) => self.globals.borrow_mut().assign(global, name, new_value),
+            None => self.environment.assign(name, new_value),
+        };
+        let new_value = match assigned {
+            Ok(new_value) => new_value,
+            Err(_) if self.dialect.jlox => {
+                return Err(self.generate_error(ErrorCode::UndefinedVariable, name, &format!("Undefined variable '{}'.", name.lexeme)));
             },
-            Expr::Logical { 
-                left, 
-                operator, 
-                right 
-            } => {
-                let left_object = self.evaluate(left)?;
+            Err(e) => { return Err(self.suggest_name(e, name)); }
+        };
+        self.track_memory(old_value.as_ref(), size);
+        self.notify_assign(name, &new_value);
+        Ok(new_value)
+    }
 
-                if operator.token_type == TokenType::Or {
-                    if is_truthy(&left_object) {
-                        return Ok(left_object);
-                    }
-                } else {
-                    if !is_truthy(&left_object) {
-                        return Ok(left_object);
-                    }
+    // Chains like `a or b or c` nest to the left, so the operations below `expr` are
+    // followed in a loop rather than by recursion, which would overflow the stack on long ones.
+    fn logical(&mut self, ast: &Ast, expr: ExprId) -> Result<Value, RuntimeError> {
+        let mut left = expr;
+        let mut chain = Vec::new();
+        while let Expr::Logical { left: inner, operator, right } = &ast[left] {
+            // Evaluating `expr` already paid for it, but not for the operations inside it.
+            if left != expr {
+                self.burn_fuel(ast.expr_span(left).line)?;
+            }
+            chain.push((operator, *right));
+            left = *inner;
+        }
+
+        let mut value = self.evaluate(ast, left)?;
+        for (operator, right) in chain.into_iter().rev() {
+            let short_circuits = if operator.token_type == TokenType::Or { is_truthy(&value) } else { !is_truthy(&value) };
+            if !short_circuits {
+                value = self.evaluate(ast, right)?;
+            }
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self, ast: &Ast, operator: &Token, right: ExprId) -> Result<Value, RuntimeError> {
+        let right_object = self.evaluate(ast, right)?;
+        if self.dialect.jlox {
+            return self.jlox_unary(operator, right_object);
+        }
+
+        match operator.token_type {
+            TokenType::Minus => {
+                match right_object {
+                    Value::Number(value) => {
+                        Ok(Value::Number(-value))
+                    },
+                    _ => { Err(self.generate_error(ErrorCode::OperandNotNumber, operator, "cannot apply '-' operator on a non-number.")) }
                 }
+            },
+            TokenType::Bang => {
+                match right_object {
+                    Value::Bool(value) => {
+                        Ok(Value::Bool(!value))
+                    },
+                    _ => { Err(self.generate_error(ErrorCode::OperandNotNumber, operator, "cannot apply '!' operator on a non-number.")) }
+                }
+            }
+            _ => { Err(self.generate_error(ErrorCode::Internal, operator, "unary operator must be '-' or '!'.")) }
+        }
+    }
 
-                self.evaluate(right)
-            },
-            Expr::Unary { 
-                operator, 
-                right 
-            } => {
-                let right_object = self.evaluate(right)?;
-
-                match operator.token_type {
-                    TokenType::Minus => {
-                        match right_object {
-                            Value::Number(value) => {
-                                return Ok(Value::Number(-value));
-                            },
-                            _ => { return Err(self.generate_error(operator.line, "cannot apply '-' operator on a non-number.")); }
-                        }
+    fn call(&mut self, ast: &Ast, callee: ExprId, paren: &Token, arguments: &[ExprId]) -> Result<Value, RuntimeError> {
+        let callee_value = self.evaluate(ast, callee)?;
+
+        let mut arguments_value: Vec<Value> = Vec::new();
+        for &argument in arguments {
+            arguments_value.push(self.evaluate(ast, argument)?);
+        }
+
+        match callee_value {
+            Value::Native(function) => self.call_native(&function, paren, &arguments_value),
+            Value::Function(function) => self.call_function(&function, paren, &arguments_value),
+            _ => Err(self.generate_error(ErrorCode::NotCallable, paren, "Can only call functions and classes.")),
+        }
+    }
+
+    fn lambda(&mut self, ast: &Ast, params: &[Token], body: ExprId) -> Value {
+        let ast = Shared::clone(self.running.get_or_insert_with(|| Shared::new(ast.clone())));
+        Value::Function(Shared::new(Function {
+            params: params.to_vec(),
+            body,
+            ast,
+            resolution: Shared::clone(&self.resolution),
+            closure: Shared::clone(&self.environment),
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
+        }))
+    }
+
+    // Chains like `1 + 2 + 3` nest to the left, so the operations below `expr` are applied
+    // in a loop rather than by recursion, which would overflow the stack on long ones.
+    fn binary(&mut self, ast: &Ast, expr: ExprId) -> Result<Value, RuntimeError> {
+        let mut left = expr;
+        let mut chain = Vec::new();
+        while let Expr::Binary { left: inner, operator, right } = &ast[left] {
+            // Evaluating `expr` already paid for it, but not for the operations inside it.
+            if left != expr {
+                self.burn_fuel(ast.expr_span(left).line)?;
+            }
+            chain.push((left, operator, *right));
+            left = *inner;
+        }
+
+        let mut value = self.evaluate(ast, left)?;
+        for (operation, operator, right) in chain.into_iter().rev() {
+            let right_object = self.evaluate(ast, right)?;
+            value = self.binary_operation(operator, value, right_object)?;
+            // `evaluate` counts the value of `expr` itself.
+            if let Some(stats) = self.stats.as_mut().filter(|_| operation != expr) {
+                stats.count_value(&value);
+            }
+        }
+        Ok(value)
+    }
+
+    fn binary_operation(&mut self, operator: &Token, left_object: Value, right_object: Value) -> Result<Value, RuntimeError> {
+        if self.dialect.jlox {
+            return self.jlox_binary(operator, left_object, right_object);
+        }
+
+        match operator.token_type {
+            TokenType::Minus => { 
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        Ok(Value::Number(left_value - right_value))
                     },
-                    TokenType::Bang => {
-                        match right_object {
-                            Value::Bool(value) => {
-                                return Ok(Value::Bool(!value));
-                            },
-                            _ => { return Err(self.generate_error(operator.line, "cannot apply '!' operator on a non-number.")); }
-                        }
-                    }
-                    _ => { return Err(self.generate_error(operator.line, "unary operator must be '-' or '!'.")); }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "cannot apply '-' on non-numbers.")) }
                 }
             },
-            Expr::Binary { 
-                left, 
-                operator, 
-                right 
-            } => {
-                let left_object = self.evaluate(left)?;
-                let right_object = self.evaluate(right)?;
-
-                match operator.token_type {
-                    TokenType::Minus => { 
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                return Ok(Value::Number(left_value - right_value));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "cannot apply '-' on non-numbers.")); }
-                        }
+            TokenType::Plus => {
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        Ok(Value::Number(left_value + right_value))
                     },
-                    TokenType::Plus => {
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                return Ok(Value::Number(left_value + right_value));
-                            },
-                            (Value::Str(left_value), Value::Str(right_value)) => 
-                            {
-                                return Ok(Value::Str(format!("{}{}", left_value, right_value)));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "'+' operator must be applied on numbers or strings.")); }
-                        }
+                    (Value::Str(left_value), Value::Str(right_value)) => 
+                    {
+                        self.check_memory(operator, left_value.len() + right_value.len())?;
+                        let mut text = String::with_capacity(left_value.len() + right_value.len());
+                        text.push_str(&left_value);
+                        text.push_str(&right_value);
+                        Ok(Value::Str(text.into()))
                     },
-                    TokenType::Slash => {
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                if right_value == 0.0 {
-                                    return Err(self.generate_error(operator.line, "cannot divide by 0."));
-                                }
-                                return Ok(Value::Number(left_value / right_value));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "'/' operator must be applied on numbers.")); }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'+' operator must be applied on numbers or strings.")) }
+                }
+            },
+            TokenType::Slash => {
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        if right_value == 0.0 {
+                            return Err(self.generate_error(ErrorCode::DivisionByZero, operator, "cannot divide by 0."));
                         }
+                        Ok(Value::Number(left_value / right_value))
                     },
-                    TokenType::Star => {
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                return Ok(Value::Number(left_value * right_value));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "'*' operator must be applied on numbers.")); }
-                        }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'/' operator must be applied on numbers.")) }
+                }
+            },
+            TokenType::Star => {
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        Ok(Value::Number(left_value * right_value))
                     },
-                    TokenType::Greater => {
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                return Ok(Value::Bool(left_value > right_value));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "'>' operator must be applied on numbers.")); }
-                        }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'*' operator must be applied on numbers.")) }
+                }
+            },
+            TokenType::Greater => {
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        Ok(Value::Bool(left_value > right_value))
                     },
-                    TokenType::GreaterEqual => {
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                return Ok(Value::Bool(left_value >= right_value));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "'>=' operator must be applied on numbers.")); }
-                        }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'>' operator must be applied on numbers.")) }
+                }
+            },
+            TokenType::GreaterEqual => {
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        Ok(Value::Bool(left_value >= right_value))
                     },
-                    TokenType::Less => {
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                return Ok(Value::Bool(left_value < right_value));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "'<' operator must be applied on numbers.")); }
-                        }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'>=' operator must be applied on numbers.")) }
+                }
+            },
+            TokenType::Less => {
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        Ok(Value::Bool(left_value < right_value))
                     },
-                    TokenType::LessEqual => {
-                        match (left_object, right_object) {
-                            (Value::Number(left_value), Value::Number(right_value)) => 
-                            {
-                                return Ok(Value::Bool(left_value <= right_value));
-                            },
-                            (_, _) => { return Err(self.generate_error(operator.line, "'<=' operator must be applied on numbers.")); }
-                        }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'<' operator must be applied on numbers.")) }
+                }
+            },
+            TokenType::LessEqual => {
+                match (left_object, right_object) {
+                    (Value::Number(left_value), Value::Number(right_value)) => 
+                    {
+                        Ok(Value::Bool(left_value <= right_value))
                     },
-                    TokenType::BangEqual => {
-                        match self.is_equal(&left_object, &right_object) {
-                            Some(result) => { return Ok(Value::Bool(!result)); },
-                            // TODO: error should be reported in is_equal
-                            None => { return Err(self.generate_error(operator.line, "'!=' operator must be applied on the same types.")); }
-                        }
-                    }
-                    TokenType::EqualEqual => {
-                        match self.is_equal(&left_object, &right_object) {
-                            Some(result) => { return Ok(Value::Bool(result)); },
-                            // TODO: error should be reported in is_equal
-                            None => { return Err(self.generate_error(operator.line, "'==' operator must be applied on the same types.")); }
-                        }
-                    }
-                    _ => { return Err(self.generate_error(operator.line, "unknown token found while parsing binary expression.")); }
+                    (_, _) => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'<=' operator must be applied on numbers.")) }
                 }
             },
-            Expr::Call { 
-                callee, 
-                paren, 
-                arguments 
-            } => {
-                let callee_result = self.evaluate(callee)?;
-
-                let mut arguments_value: Vec<Value> = Vec::new();
-                for argument in arguments {
-                    arguments_value.push(self.evaluate(argument)?);
+            TokenType::BangEqual => {
+                match self.is_equal(&left_object, &right_object) {
+                    Some(result) => { Ok(Value::Bool(!result)) },
+                    // TODO: error should be reported in is_equal
+                    None => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'!=' operator must be applied on the same types.")) }
+                }
+            }
+            TokenType::EqualEqual => {
+                match self.is_equal(&left_object, &right_object) {
+                    Some(result) => { Ok(Value::Bool(result)) },
+                    // TODO: error should be reported in is_equal
+                    None => { Err(self.generate_error(ErrorCode::OperandTypes, operator, "'==' operator must be applied on the same types.")) }
                 }
-
-                Err("TODO: impl".to_string())
             }
+            _ => { Err(self.generate_error(ErrorCode::Internal, operator, "unknown token found while parsing binary expression.")) }
         }
     }
 
-    fn literal_to_value(&mut self, literal: &Literal) -> Result<Value, String> {
-        match literal {
-            Literal::Identifier(text) => { Ok(Value::Identifier(text.clone())) },
-            Literal::Str(text) => { Ok(Value::Str(text.clone())) },
-            Literal::Number(number) => { Ok(Value::Number(number.clone())) },
-            Literal::Bool(value) => { Ok(Value::Bool(value.clone())) },
-            Literal::Nil => { Ok(Value::Nil) },
+    // jlox's `visitUnaryExpr`, where `!` takes any value.
+    fn jlox_unary(&mut self, operator: &Token, right: Value) -> Result<Value, RuntimeError> {
+        match (operator.token_type, right) {
+            (TokenType::Bang, right) => Ok(Value::Bool(!is_truthy(&right))),
+            (TokenType::Minus, Value::Number(value)) => Ok(Value::Number(-value)),
+            (TokenType::Minus, _) => Err(self.generate_error(ErrorCode::OperandNotNumber, operator, "Operand must be a number.")),
+            _ => Err(self.generate_error(ErrorCode::Internal, operator, "unary operator must be '-' or '!'.")),
         }
     }
 
-    fn is_equal(&mut self, left: &Value, right: &Value) -> Option<bool> {
-        match (left, right) {
-            (Value::Identifier(left_value), Value::Identifier(right_value)) => 
+    // jlox's `visitBinaryExpr`. Equality never fails and division follows IEEE 754.
+    fn jlox_binary(&mut self, operator: &Token, left: Value, right: Value) -> Result<Value, RuntimeError> {
+        match operator.token_type {
+            TokenType::EqualEqual | TokenType::BangEqual => {
+                let equal = match (&left, &right) {
+                    // Java's `Double.equals`: NaN equals itself and 0 doesn't equal -0.
+                    (Value::Number(left), Value::Number(right)) => (left.is_nan() && right.is_nan()) || left.to_bits() == right.to_bits(),
+                    _ => self.is_equal(&left, &right).unwrap_or(false),
+                };
+                return Ok(Value::Bool(equal == (operator.token_type == TokenType::EqualEqual)));
+            },
+            TokenType::Plus => {
+                return match (left, right) {
+                    (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+                    (Value::Str(left), Value::Str(right)) => {
+                        self.check_memory(operator, left.len() + right.len())?;
+                        Ok(Value::from(format!("{left}{right}")))
+                    },
+                    _ => Err(self.generate_error(ErrorCode::OperandTypes, operator, "Operands must be two numbers or two strings.")),
+                };
+            },
+            _ => {},
+        }
+
+        let (left, right) = match (left, right) {
+            (Value::Number(left), Value::Number(right)) => (left, right),
+            _ => { return Err(self.generate_error(ErrorCode::OperandTypes, operator, "Operands must be numbers.")); }
+        };
+        match operator.token_type {
+            TokenType::Minus => Ok(Value::Number(left - right)),
+            TokenType::Slash => Ok(Value::Number(left / right)),
+            TokenType::Star => Ok(Value::Number(left * right)),
+            TokenType::Greater => Ok(Value::Bool(left > right)),
+            TokenType::GreaterEqual => Ok(Value::Bool(left >= right)),
+            TokenType::Less => Ok(Value::Bool(left < right)),
+            TokenType::LessEqual => Ok(Value::Bool(left <= right)),
+            _ => Err(self.generate_error(ErrorCode::Internal, operator, "unknown token found while parsing binary expression.")),
+        }
+    }
+
+    // How `print` and the REPL show a value.
+    fn stringify(&self, value: &Value) -> String {
+        match value {
+            Value::Number(number) if self.dialect.jlox => java_number(*number),
+            _ => value.to_string(),
+        }
+    }
+
+    fn literal_to_value(&mut self, literal: &Literal) -> Result<Value, RuntimeError> {
+        match literal {
+            Literal::Identifier(text) => { Ok(Value::Identifier(text.clone())) },
+            Literal::Str(text) => { Ok(Value::Str(text.clone())) },
+            Literal::Number(number) => { Ok(Value::Number(*number)) },
+            Literal::Bool(value) => { Ok(Value::Bool(*value)) },
+            Literal::Nil => { Ok(Value::Nil) },
+        }
+    }
+
+    fn is_equal(&mut self, left: &Value, right: &Value) -> Option<bool> {
+        match (left, right) {
+            (Value::Identifier(left_value), Value::Identifier(right_value)) => 
             {
-                return Some(left_value == right_value);
+                Some(left_value == right_value)
             },
             (Value::Str(left_value), Value::Str(right_value)) => 
             {
-                return Some(left_value == right_value);
+                Some(left_value == right_value)
             },
             (Value::Number(left_value), Value::Number(right_value)) => 
             {
-                return Some(left_value == right_value);
+                Some(left_value == right_value)
             },
             (Value::Bool(left_value), Value::Bool(right_value)) => 
             {
-                return Some(left_value == right_value);
+                Some(left_value == right_value)
             },
             (Value::Nil, Value::Nil) => 
             {
-                return Some(true);
+                Some(true)
+            },
+            (Value::Native(left_value), Value::Native(right_value)) => 
+            {
+                Some(Shared::ptr_eq(left_value, right_value))
+            },
+            (Value::Builder(left_value), Value::Builder(right_value)) => 
+            {
+                Some(Shared::ptr_eq(left_value, right_value))
+            },
+            (Value::Function(left_value), Value::Function(right_value)) => 
+            {
+                Some(Shared::ptr_eq(left_value, right_value))
+            },
+            (Value::Channel(left_value), Value::Channel(right_value)) => 
+            {
+                Some(left_value == right_value)
             },
-            (_, _) => { return None; }
+            (_, _) => { None}
         }
     }
 
-    fn stringify(&mut self, value: &Value) -> String {
+
+    // Runs the statements of a block expression, in the scope it opened, and then works
+    // out its value.
+    fn block_value(&mut self, ast: &Ast, statements: &[StmtId], value: Option<ExprId>) -> Result<Value, RuntimeError> {
+        for &statement in statements {
+            match self.execute(ast, statement) {
+                // The parser rejects a 'break' that would leave the block.
+                Ok(()) | Err(Unwind::Break) => {},
+                Err(Unwind::Error(e)) => { return Err(e); }
+            }
+        }
+
         match value {
-            Value::Identifier(val) => { val.clone() },
-            Value::Str(val) => { val.clone() },
-            Value::Number(val) => { val.to_string() },
-            Value::Bool(val) => { val.to_string() },
-            Value::Nil => { String::from("nil") },
+            Some(value) => self.evaluate(ast, value),
+            None => Ok(Value::Nil),
         }
     }
 
-    fn generate_error(&mut self, line: i32, message: &str) -> String {
-        format!("[line {line}] Error: {message}")
+    // Runs `body`, then `increment`, for as long as `condition` holds. Loops are one of
+    // the ways a program can run for long, so interrupts are checked here.
+    fn run_loop(&mut self, ast: &Ast, keyword: &Token, condition: Option<ExprId>, increment: Option<ExprId>, body: StmtId) -> Result<(), Unwind> {
+        loop {
+            if self.take_interrupt() {
+                return Err(self.generate_error(ErrorCode::Interrupted, keyword, "Interrupted.").into());
+            }
+            if let Some(condition) = condition {
+                if !is_truthy(&self.evaluate(ast, condition)?) {
+                    return Ok(());
+                }
+            }
+
+            match self.execute(ast, body) {
+                Ok(_) => {},
+                Err(Unwind::Break) => { return Ok(()); },
+                Err(e) => { return Err(e); }
+            }
+            if let Some(increment) = increment {
+                self.evaluate(ast, increment)?;
+            }
+        }
+    }
+
+    fn call_native(&mut self, function: &NativeFunction, paren: &Token, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        let _call = tracing::trace_span!("call", function = %function.name, line = paren.line).entered();
+        self.begin_call(&function.name, function.arity, paren, arguments)?;
+
+        let result = match function.function {
+            NativeBody::Pure(body) => body(arguments),
+            NativeBody::Output(body) => body(&mut *self.output, arguments),
+            NativeBody::ErrorOutput(body) => body(&mut *self.error_output, arguments),
+            NativeBody::Random(body) => body(&mut self.rng, arguments),
+            NativeBody::Clock(body) => body(&mut self.clock, arguments),
+            NativeBody::Interpreter(body) => body(self, arguments),
+        };
+        let result = result.map_err(|message| self.generate_error(ErrorCode::NativeFunction, paren, &message));
+
+        self.end_call(&function.name, paren, result)
+    }
+
+    fn call_function(&mut self, function: &Function, paren: &Token, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        let _call = tracing::trace_span!("call", function = function.name(), line = paren.line).entered();
+        self.begin_call(function.name(), function.arity(), paren, arguments)?;
+
+        #[cfg(feature = "jit")]
+        if let Some(result) = self.run_compiled(function, arguments) {
+            return self.end_call(function.name(), paren, Ok(result));
+        }
+
+        // The body runs in a scope of its own inside the one the function was made in,
+        // and may come from a different program than the one calling it.
+        let previous = Shared::clone(&self.environment);
+        let environment = Environment::from(Shared::clone(&function.closure));
+        for (param, argument) in function.params.iter().zip(arguments) {
+            environment.define(param, argument.clone());
+        }
+        if self.memory_limit.is_some() {
+            self.track_memory(None, environment.scope_heap_size());
+        }
+        self.open_scope(environment, function.ast.expr_span(function.body).line);
+        let resolution = std::mem::replace(&mut self.resolution, Shared::clone(&function.resolution));
+        let running = self.running.replace(Shared::clone(&function.ast));
+
+        let result = self.evaluate(&function.ast, function.body);
+
+        self.running = running;
+        self.resolution = resolution;
+        self.close_scope(previous);
+        self.end_call(function.name(), paren, result)
+    }
+
+    // Runs `function` as native code if it is hot and only gets numbers. `None` leaves the
+    // call to the interpreter, which also reports any error the native code gave up on.
+    #[cfg(feature = "jit")]
+    fn run_compiled(&self, function: &Function, arguments: &[Value]) -> Option<Value> {
+        if self.jit == JitMode::Off || self.fuel.is_some() || self.interrupt.is_some() || self.dialect.jlox {
+            return None;
+        }
+
+        let numbers: Vec<f64> = arguments.iter().map(Value::as_number).collect::<Option<_>>()?;
+        function.jit.get(function, self.jit)?.call(&numbers)
+    }
+
+    // Checks a call to `name` and pushes its frame. `end_call` pops it again.
+    fn begin_call(&mut self, name: &str, arity: usize, paren: &Token, arguments: &[Value]) -> Result<(), RuntimeError> {
+        if arguments.len() != arity {
+            let message = format!("Expected {} arguments but got {}.", arity, arguments.len());
+            return Err(self.generate_error(ErrorCode::WrongArgumentCount, paren, &message));
+        }
+
+        // Recursion runs for long without looping, so calls are checked like loop iterations.
+        if self.take_interrupt() {
+            return Err(self.generate_error(ErrorCode::Interrupted, paren, "Interrupted."));
+        }
+        self.burn_fuel(paren.line)?;
+
+        // The bottom frame is the script, which isn't a call.
+        if self.stack.depth() > self.max_call_depth {
+            let message = format!("Stack overflow (max call depth {} exceeded).", self.max_call_depth);
+            return Err(self.generate_error(ErrorCode::StackOverflow, paren, &message));
+        }
+
+        self.stack.set_line(paren.line);
+        self.stack.push(Frame { function: name.to_string(), line: None, environment: None });
+        if let Some(stats) = &mut self.stats {
+            stats.peak_call_depth = stats.peak_call_depth.max(self.stack.depth());
+        }
+
+        for observer in &mut self.observers {
+            observer.enter_function(name, arguments, paren.line, &self.environment);
+        }
+        Ok(())
+    }
+
+    fn end_call(&mut self, name: &str, paren: &Token, result: Result<Value, RuntimeError>) -> Result<Value, RuntimeError> {
+        for observer in &mut self.observers {
+            observer.exit_function(name, result.as_ref().ok(), paren.line, &self.environment);
+        }
+        self.stack.pop();
+        result
+    }
+
+    // Opens a scope inside the current one. The caller goes back to the previous environment
+    // with `close_scope`.
+    fn enter_scope(&mut self, line: i32) {
+        self.open_scope(Environment::from(Shared::clone(&self.environment)), line);
     }
+
+    fn open_scope(&mut self, environment: Environment, line: i32) {
+        if let Some(stats) = &mut self.stats {
+            stats.environments += 1;
+        }
+
+        let environment = Shared::new(environment);
+        if let Some(scopes) = &mut self.scopes {
+            // Loops open a scope per iteration, so the ones already freed are dropped
+            // whenever the list would have to grow.
+            if scopes.len() == scopes.capacity() {
+                scopes.retain(|(scope, _)| scope.strong_count() > 0);
+            }
+            scopes.push((Shared::downgrade(&environment), line));
+        }
+        self.set_environment(environment);
+    }
+
+    // Leaves the current scope for `previous`, no longer counting what its variables hold.
+    fn close_scope(&mut self, previous: Shared<Environment>) {
+        if self.memory_limit.is_some() {
+            self.memory_used.set(self.memory_used.get().saturating_sub(self.environment.scope_heap_size()));
+        }
+        self.set_environment(previous);
+    }
+
+    fn set_environment(&mut self, environment: Shared<Environment>) {
+        self.stack.set_environment(&environment);
+        self.environment = environment;
+    }
+
+    fn notify_assign(&mut self, name: &Token, value: &Value) {
+        for observer in &mut self.observers {
+            observer.assign(&name.lexeme, value, name.line);
+        }
+    }
+
+    // Fails if `extra` more bytes would take the variables over the memory limit.
+    fn check_memory(&mut self, token: &Token, extra: usize) -> Result<(), RuntimeError> {
+        if self.over_memory_limit(extra) {
+            return Err(self.generate_error(ErrorCode::MemoryLimit, token, "Memory limit exceeded."));
+        }
+        Ok(())
+    }
+
+    // Whether `extra` more bytes would take the variables over the memory limit, for
+    // natives that grow values in place.
+    pub(crate) fn over_memory_limit(&self, extra: usize) -> bool {
+        self.memory_limit.is_some_and(|limit| self.memory_used.get() + extra > limit)
+    }
+
+    // Counts `size` more bytes held by variables in place of what `replaced` held.
+    pub(crate) fn track_memory(&self, replaced: Option<&Value>, size: usize) {
+        let replaced = replaced.map_or(0, Value::heap_size);
+        self.memory_used.set(self.memory_used.get().saturating_sub(replaced) + size);
+    }
+
+    // Fails once the fuel is used up, at `line`, the statement or expression that needed more.
+    fn burn_fuel(&mut self, line: i32) -> Result<(), RuntimeError> {
+        match &mut self.fuel {
+            Some(0) => Err(RuntimeError::at_line(ErrorCode::FuelExhausted, line, "Fuel exhausted.")),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn print_line(&mut self, text: &str) {
+        // Like println!, a broken output is not something the program can recover from.
+        writeln!(self.output, "{text}").expect("failed writing the program's output");
+    }
+
+    fn suggest_name(&self, mut error: RuntimeError, name: &Token) -> RuntimeError {
+        let names = self.environment.names();
+        if let Some(suggestion) = suggest::did_you_mean(&name.lexeme, names.iter().map(String::as_str)) {
+            error.message.push_str(&format!(" Did you mean '{suggestion}'?"));
+        }
+        error
+    }
+
+    fn generate_error(&mut self, code: ErrorCode, token: &Token, message: &str) -> RuntimeError {
+        RuntimeError::new(code, token, message)
+    }
+}
+
+// A global function closes over the global scope, which holds the globals, so those two
+// would keep each other alive. Emptying the globals breaks every such cycle.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        *self.globals.borrow_mut() = Globals::new();
+    }
+}
+
+// The shortest text that reads back as the same number. Very large and very small
+// numbers switch to an exponent, as in JavaScript, rather than spelling out every zero.
+fn format_number(number: f64) -> String {
+    if number.is_nan() {
+        return String::from("NaN");
+    }
+    if number.is_infinite() {
+        return String::from(if number > 0.0 { "Infinity" } else { "-Infinity" });
+    }
+
+    let magnitude = number.abs();
+    if magnitude != 0.0 && !(1e-7..1e21).contains(&magnitude) {
+        format!("{number:e}")
+    } else {
+        number.to_string()
+    }
+}
+
+// A number the way jlox prints it: Java's `Double.toString`, minus the ".0" jlox strips
+// from whole numbers. Java switches to scientific notation outside [0.001, 10^7).
+fn java_number(number: f64) -> String {
+    if number.is_nan() {
+        return String::from("NaN");
+    }
+    if number.is_infinite() {
+        return String::from(if number > 0.0 { "Infinity" } else { "-Infinity" });
+    }
+
+    let magnitude = number.abs();
+    if magnitude != 0.0 && !(1e-3..1e7).contains(&magnitude) {
+        let text = format!("{number:e}");
+        let (mantissa, exponent) = text.split_once('e').expect("{:e} always writes an exponent");
+        let point = if mantissa.contains('.') { "" } else { ".0" };
+        return format!("{mantissa}{point}E{exponent}");
+    }
+    number.to_string()
 }
 
 fn is_truthy(value: &Value) -> bool {
@@ -374,11 +1320,630 @@ fn is_truthy(value: &Value) -> bool {
     }
 }
 
-#[derive(Clone, PartialEq)]
+struct Capture(Shared<Lock<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A Lox value at runtime, as handed back to the host by `Interpreter::eval`. With the
+/// `serde` feature it also implements `Serialize` and `Deserialize`.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
-    Identifier(String),
-    Str(String),
+    /// The name of an identifier used as a value.
+    Identifier(Shared<str>),
+    /// Strings are immutable, so copies of a value share the same text.
+    Str(Shared<str>),
+    /// Lox has a single number type, a double.
     Number(f64),
     Bool(bool),
+    Native(Shared<NativeFunction>),
+    /// A string being built with the `strings` module. Copies of the value share it, so
+    /// appending through one is seen by all of them.
+    Builder(Shared<StringBuilder>),
+    /// A function written in Lox, such as `(a, b) => a + b`.
+    Function(Shared<Function>),
+    /// A channel from the `threads` module, shared by every thread that has a copy.
+    Channel(Channel),
     Nil
-}
\ No newline at end of file
+}
+
+/// What a value can be used for, coarser than its type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// Native and Lox functions, which can be called.
+    Function,
+    /// Everything else.
+    Data,
+}
+
+impl Value {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::Native(_) | Value::Function(_) => ValueKind::Function,
+            _ => ValueKind::Data,
+        }
+    }
+
+    /// The name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Identifier(_) => "identifier",
+            Value::Str(_) => "string",
+            Value::Number(_) => "number",
+            Value::Bool(_) => "boolean",
+            Value::Native(_) => "native function",
+            Value::Builder(_) => "string builder",
+            Value::Function(_) => "function",
+            Value::Channel(_) => "channel",
+            Value::Nil => "nil",
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        *self == Value::Nil
+    }
+
+    /// Roughly how many bytes the value keeps on the heap.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::Identifier(text) | Value::Str(text) => text.len(),
+            Value::Builder(builder) => builder.capacity(),
+            Value::Number(_) | Value::Bool(_) | Value::Native(_) | Value::Function(_) | Value::Channel(_) | Value::Nil => 0,
+        }
+    }
+}
+
+/// Formats the value the way `print` shows it.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Identifier(val) => write!(f, "{val}"),
+            Value::Str(val) => write!(f, "{val}"),
+            Value::Number(val) => write!(f, "{}", format_number(*val)),
+            Value::Bool(val) => write!(f, "{val}"),
+            Value::Native(function) => write!(f, "<native fn {}>", function.name),
+            Value::Builder(builder) => write!(f, "{}", builder.text()),
+            Value::Function(function) => write!(f, "<fn {}>", function.name()),
+            Value::Channel(_) => write!(f, "<channel>"),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(number: f64) -> Self {
+        Value::Number(number)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Str(Shared::from(text))
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Str(Shared::from(text))
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.as_number().ok_or(ConversionError { expected: "number", value })
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Str(text) => Ok(text.to_string()),
+            _ => Err(ConversionError { expected: "string", value }),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.as_bool().ok_or(ConversionError { expected: "boolean", value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval() {
+        let mut interpreter = Interpreter::new(false);
+
+        assert_eq!(interpreter.eval("1 + 2;").unwrap(), Value::Number(3.0));
+        assert_eq!(interpreter.eval("var a = \"lox\"; a + a; print a;").unwrap(), Value::from("loxlox"));
+        assert_eq!(interpreter.eval("var b = a;").unwrap(), Value::Nil);
+        assert_eq!(interpreter.eval("b == a;").unwrap(), Value::Bool(true));
+        match (interpreter.get_global("a"), interpreter.get_global("b")) {
+            (Some(Value::Str(a)), Some(Value::Str(b))) => assert!(Shared::ptr_eq(&a, &b)),
+            values => panic!("expected two strings, got {values:?}"),
+        }
+
+        assert!(matches!(interpreter.eval("1 +;"), Err(LoxError::Compile(_))));
+        assert!(matches!(interpreter.eval("-\"a\";"), Err(LoxError::Runtime(_))));
+    }
+
+    #[test]
+    fn long_chains() {
+        // Chains nest thousands of levels deep to the left, but are evaluated in a loop.
+        let mut interpreter = Interpreter::new(false);
+        let source = format!("{};", vec!["1"; 5000].join(" + "));
+        assert_eq!(interpreter.eval(&source).unwrap(), Value::Number(5000.0));
+        let source = format!("{} or true;", vec!["false"; 5000].join(" or "));
+        assert_eq!(interpreter.eval(&source).unwrap(), Value::Bool(true));
+        assert_eq!(interpreter.eval("1 - 2 - 3 < 0 and nil or 4;").unwrap(), Value::Number(4.0));
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output() {
+        let buffer = SharedBuffer::default();
+        let mut interpreter = Interpreter::new(true);
+        interpreter.set_output(Box::new(buffer.clone()));
+
+        interpreter.eval("print \"hi\"; 1 + 1;").unwrap();
+
+        assert_eq!(String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(), "hi\n2\n");
+    }
+
+    #[test]
+    fn error_output() {
+        let errors = SharedBuffer::default();
+        let mut interpreter = Interpreter::new(false);
+        interpreter.capture_output();
+        interpreter.set_error_output(Box::new(errors.clone()));
+
+        interpreter.eval("import native \"io\"; print 1; eprint(\"warning: \"); eprintln(2);").unwrap();
+
+        assert_eq!(interpreter.captured_output(), vec!["1"]);
+        assert_eq!(String::from_utf8(errors.0.lock().unwrap().clone()).unwrap(), "warning: 2\n");
+    }
+
+    #[test]
+    fn capture_output() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.capture_output();
+
+        interpreter.eval("var i = 0; while (i < 3) { print i; i = i + 1; }").unwrap();
+
+        assert_eq!(interpreter.captured_output(), vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn conversions() {
+        let mut interpreter = Interpreter::new(false);
+
+        let number: f64 = interpreter.eval("1 + 2;").unwrap().try_into().unwrap();
+        assert_eq!(number, 3.0);
+        assert_eq!(Value::from("lox").as_str(), Some("lox"));
+        assert_eq!(bool::try_from(Value::from(true)), Ok(true));
+        assert!(Value::Nil.is_nil());
+
+        let error = String::try_from(Value::from(1.0)).unwrap_err();
+        assert_eq!(error.to_string(), "Expected a string, found a number.");
+    }
+
+    #[test]
+    fn globals() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_global("config", 2.0);
+
+        interpreter.eval("var result = config * 21; { var local = 1; }").unwrap();
+
+        assert_eq!(interpreter.get_global("result"), Some(Value::Number(42.0)));
+        assert_eq!(interpreter.get_global("local"), None);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Interpreter>();
+
+        let mut interpreter = Interpreter::new(false);
+        interpreter.eval("var a = 1;").unwrap();
+        let worker = std::thread::spawn(move || interpreter.eval("a + 1;").unwrap());
+        assert_eq!(worker.join().unwrap(), Value::Number(2.0));
+    }
+
+    #[test]
+    fn fuel() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_fuel(Some(1000));
+
+        let error = interpreter.eval("var i = 0;\nwhile (true) i = i + 1;").unwrap_err();
+        assert_eq!(error.to_string(), "[line 2] Error: Fuel exhausted.");
+        assert_eq!(interpreter.remaining_fuel(), Some(0));
+
+        interpreter.set_fuel(Some(1000));
+        interpreter.eval("for (var j = 0; j < 10; j = j + 1) {}").unwrap();
+
+        // Recursion runs out too, and so does a program that is one expression statement.
+        interpreter.set_fuel(Some(1000));
+        let source = "var f = (n) => if (n > 0) { f(n - 1) + f(n - 1) } else { 0 };\nf(22);";
+        let error = interpreter.eval(source).unwrap_err();
+        assert_eq!(error.to_string(), "[line 1] Error: Fuel exhausted.");
+        interpreter.set_fuel(Some(0));
+        assert_eq!(interpreter.eval("1;").unwrap_err().to_string(), "[line 1] Error: Fuel exhausted.");
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_memory_limit(Some(1000));
+
+        let error = interpreter.eval("var s = \"ab\";\nwhile (true) s = s + s;").unwrap_err();
+        assert_eq!(error.to_string(), "[line 2] Error: Memory limit exceeded.");
+        assert!(interpreter.get_global("s").unwrap().heap_size() <= 1000);
+
+        interpreter.eval("var t = s;").unwrap_err();
+        interpreter.eval("s = nil; var t = \"fits\";").unwrap();
+
+        // A builder grows without any assignment.
+        let source = "import native \"strings\"; var b = StringBuilder();\nwhile (true) append(b, \"0123456789\");";
+        let error = interpreter.eval(source).unwrap_err();
+        assert_eq!(error.to_string(), "[line 2] Error: Memory limit exceeded.");
+
+        // Variables stop counting once their scope ends, so locals made over and over fit.
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_memory_limit(Some(1500));
+        let source = "var big = \"0123456789\";\nfor (var i = 0; i < 5; i = i + 1) big = big + big;
+            var f = (s) => { var copy = s; copy };
+            for (var i = 0; i < 100; i = i + 1) { var local = big; local = f(local); }
+            var last = big;";
+        interpreter.eval(source).unwrap();
+        interpreter.eval("var again = big + big + big;").unwrap_err();
+    }
+
+    #[test]
+    fn print_function() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.capture_output();
+        interpreter.set_dialect(Dialect { print_function: true, ..Dialect::default() });
+
+        interpreter.eval("print(\"a\"); println(1 + 1); var print = 3; println(print);").unwrap();
+
+        assert_eq!(interpreter.captured_output(), vec!["a2", "3"]);
+        assert!(matches!(interpreter.eval("print 1;"), Err(LoxError::Compile(_))));
+    }
+
+    #[test]
+    fn jlox_dialect() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.capture_output();
+        interpreter.set_dialect(Dialect { jlox: true, ..Dialect::default() });
+
+        interpreter.eval("print 1 / 0; print 20000000; print 0.00025; print 0.5; print !nil; print 1 == \"1\"; print (0/0) == (0/0);").unwrap();
+        assert_eq!(interpreter.captured_output(), vec!["Infinity", "2.0E7", "2.5E-4", "0.5", "true", "false", "true"]);
+
+        // The extensions are ordinary names, and blocks aren't expressions.
+        assert_eq!(interpreter.eval("var break = 1; break;").unwrap(), Value::Number(1.0));
+        assert!(matches!(interpreter.eval("var a = { 1 };"), Err(LoxError::Compile(_))));
+
+        assert_eq!(interpreter.eval("-\"a\";").unwrap_err().to_string(), "[line 1] Error: Operand must be a number.");
+        assert_eq!(interpreter.eval("1 + nil;").unwrap_err().to_string(), "[line 1] Error: Operands must be two numbers or two strings.");
+        assert_eq!(interpreter.eval("breaks = 1;").unwrap_err().to_string(), "[line 1] Error: Undefined variable 'breaks'.");
+
+        // `clock` is a global, as in the book.
+        assert_eq!(interpreter.eval("clock() > 0;").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn max_call_depth() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.eval("import native \"time\";").unwrap();
+        interpreter.set_max_call_depth(1);
+        assert!(interpreter.eval("clock();").is_ok());
+
+        interpreter.set_max_call_depth(0);
+        let error = interpreter.eval("clock();").unwrap_err();
+        assert_eq!(error.to_string(), "[line 1] Error: Stack overflow (max call depth 0 exceeded).");
+        assert_eq!(interpreter.call_stack().depth(), 1);
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut interpreter = Interpreter::new(false);
+        let source = format!("{}1{};", "(".repeat(200), ")".repeat(200));
+        assert!(matches!(interpreter.eval(&source), Err(LoxError::Compile(_))));
+
+        interpreter.set_max_depth(300);
+        assert!(interpreter.eval(&source).is_ok());
+        interpreter.set_max_depth(2);
+        assert!(matches!(interpreter.eval("((1));"), Err(LoxError::Compile(_))));
+    }
+
+    #[test]
+    fn arrow_functions() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.eval("var offset = 10; var add = (a) => a + offset;").unwrap();
+
+        // The function outlives the program that made it and still finds its globals.
+        assert_eq!(interpreter.eval("offset = 20; add(1);").unwrap(), Value::Number(21.0));
+        assert_eq!(interpreter.eval("add;").unwrap().type_name(), "function");
+
+        let error = interpreter.eval("add(1, 2);").unwrap_err();
+        assert_eq!(error.to_string(), "[line 1] Error: Expected 1 arguments but got 2.");
+
+        // Recursing up to the limit must not overflow the test thread's stack first.
+        let error = interpreter.eval("var down = (n) => if (n > 0) { down(n - 1) } else { 0 };\ndown(1000);").unwrap_err();
+        assert_eq!(error.to_string(), "[line 1] Error: Stack overflow (max call depth 128 exceeded).");
+        assert_eq!(interpreter.call_stack().depth(), 1);
+        assert_eq!(interpreter.eval("down(100);").unwrap(), Value::Number(0.0));
+    }
+
+    #[test]
+    fn enumerate_globals() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_global("host", 1.0);
+        interpreter.eval("import native \"time\"; var name = \"plugin\"; var run = () => name;").unwrap();
+
+        let names = |globals: Vec<(String, Value)>| globals.into_iter().map(|(name, _)| name).collect::<Vec<String>>();
+        assert_eq!(names(interpreter.globals()), vec!["clock", "host", "name", "run"]);
+        assert_eq!(names(interpreter.globals_of(ValueKind::Function)), vec!["clock", "run"]);
+        assert_eq!(names(interpreter.globals_of(ValueKind::Data)), vec!["host", "name"]);
+    }
+
+    #[test]
+    fn seeded_random() {
+        let draw = |seed: u64| {
+            let mut interpreter = Interpreter::new(false);
+            interpreter.set_seed(seed);
+            interpreter.eval("import native \"random\"; var a = random(); var b = randomInt(1, 7); b;").unwrap();
+            (interpreter.get_global("a"), interpreter.get_global("b"))
+        };
+
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42).0, draw(43).0);
+        let (Some(Value::Number(a)), Some(Value::Number(b))) = draw(7) else { panic!("random natives should return numbers") };
+        assert!((0.0..1.0).contains(&a));
+        assert!([1.0, 2.0, 3.0, 4.0, 5.0, 6.0].contains(&b));
+    }
+
+    #[test]
+    fn checkpoint() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.eval("var runs = 1; var last = \"a\"; var step = () => runs;").unwrap();
+        let mut checkpoint: Vec<u8> = Vec::new();
+        interpreter.dump_state(&mut checkpoint).unwrap();
+
+        let mut resumed = Interpreter::new(false);
+        resumed.load_state(checkpoint.as_slice()).unwrap();
+        assert_eq!(resumed.eval("runs = runs + 1; last;").unwrap(), Value::from("a"));
+        assert_eq!(resumed.get_global("runs"), Some(Value::Number(2.0)));
+        assert_eq!(resumed.get_global("step"), None);
+    }
+
+    #[test]
+    fn leak_check() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.enable_leak_check();
+        interpreter.eval("var f = () => f;\n{ var g = (() => 1)(); }\n{\nvar h = () => h; }").unwrap();
+
+        // Only the scope that stores a function closing over it is never freed.
+        assert_eq!(interpreter.leaked_scopes(), vec![3]);
+    }
+
+    #[test]
+    fn interrupt() {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_interrupt(Arc::clone(&interrupt));
+
+        interrupt.store(true, Ordering::Relaxed);
+        let error = interpreter.eval("while (true) {}").unwrap_err();
+
+        assert_eq!(error.to_string(), "[line 1] Error: Interrupted.");
+        assert!(!interrupt.load(Ordering::Relaxed));
+
+        // Recursion that would take far too long to finish stops as well.
+        let setter = Arc::clone(&interrupt);
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            setter.store(true, Ordering::Relaxed);
+        });
+        let source = "var f = (n) => if (n > 0) { f(n - 1) + f(n - 1) } else { 0 };\nf(40);";
+        let error = interpreter.eval(source).unwrap_err();
+        thread.join().unwrap();
+        assert_eq!(error.to_string(), "[line 1] Error: Interrupted.");
+    }
+
+    struct MathModule;
+
+    impl NativeModule for MathModule {
+        fn name(&self) -> &str {
+            "math"
+        }
+
+        fn functions(&self) -> Vec<NativeFunction> {
+            vec![NativeFunction::new("sqrt", 1, |arguments| match arguments[0] {
+                Value::Number(number) if number >= 0.0 => Ok(Value::Number(number.sqrt())),
+                _ => Err(String::from("sqrt expects a non-negative number.")),
+            })]
+        }
+    }
+
+    #[test]
+    fn native_modules() {
+        let mut interpreter = Interpreter::new(false);
+        interpreter.register_module(Box::new(MathModule));
+
+        assert_eq!(interpreter.eval("import native \"math\"; sqrt(16);").unwrap(), Value::Number(4.0));
+        assert_eq!(interpreter.eval("sqrt;").unwrap().to_string(), "<native fn sqrt>");
+        assert_eq!(interpreter.eval("sqrt(1, 2);").unwrap_err().to_string(), "[line 1] Error: Expected 1 arguments but got 2.");
+        assert_eq!(interpreter.eval("sqrt(-1);").unwrap_err().to_string(), "[line 1] Error: sqrt expects a non-negative number.");
+        assert_eq!(interpreter.eval("import native \"sql\";").unwrap_err().to_string(), "[line 1] Error: Unknown native module 'sql'.");
+
+        assert!(interpreter.eval("import native \"time\"; clock() > 0;").unwrap().as_bool().unwrap());
+    }
+
+    struct Tracer(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl crate::observer::Observer for Tracer {
+        fn before_statement(&mut self, _ast: &Ast, _stmt: StmtId, line: Option<i32>, _environment: &Environment) {
+            self.0.lock().unwrap().push(format!("statement {line:?}"));
+        }
+
+        fn enter_function(&mut self, name: &str, _arguments: &[Value], line: i32, _environment: &Environment) {
+            self.0.lock().unwrap().push(format!("enter {name} {line}"));
+        }
+
+        fn exit_function(&mut self, name: &str, _result: Option<&Value>, _line: i32, environment: &Environment) {
+            let a = environment.get_name("a").unwrap();
+            self.0.lock().unwrap().push(format!("exit {name} a={a}"));
+        }
+
+        fn breakpoint(&mut self, line: i32, environment: &Environment) {
+            let a = environment.get_name("a").unwrap();
+            self.0.lock().unwrap().push(format!("breakpoint {line} a={a}"));
+        }
+    }
+
+    #[test]
+    fn observers() {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut interpreter = Interpreter::new(false);
+        interpreter.add_observer(Box::new(Tracer(std::sync::Arc::clone(&events))));
+
+        interpreter.eval("import native \"time\";\nvar a = 1;\n\nclock();").unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec!["statement Some(1)", "statement Some(2)", "statement Some(4)", "enter clock 4", "exit clock a=1"]);
+
+        events.lock().unwrap().clear();
+        interpreter.eval("a = 2;\ndebugger;").unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["statement Some(1)", "statement Some(2)", "breakpoint 2 a=2"]);
+    }
+
+    struct StackRecorder(CallStack, Shared<Lock<Vec<Vec<FrameView>>>>);
+
+    impl crate::observer::Observer for StackRecorder {
+        fn enter_function(&mut self, _name: &str, _arguments: &[Value], _line: i32, _environment: &Environment) {
+            self.1.borrow_mut().push(self.0.frames());
+        }
+
+        fn breakpoint(&mut self, _line: i32, _environment: &Environment) {
+            self.1.borrow_mut().push(self.0.frames());
+        }
+    }
+
+    #[test]
+    fn stack_frames() {
+        let stacks = Shared::new(Lock::new(Vec::new()));
+        let mut interpreter = Interpreter::new(false);
+        interpreter.add_observer(Box::new(StackRecorder(interpreter.call_stack(), Shared::clone(&stacks))));
+
+        interpreter.eval("import native \"time\";\nvar a = 1;\n{\n  var b = 2;\n  debugger;\n  clock();\n}").unwrap();
+
+        let stacks = stacks.borrow();
+        let scopes = |frame: &FrameView| -> Vec<Vec<String>> {
+            frame.scopes.iter().map(|scope| scope.iter().map(|(name, value)| format!("{name}={value}")).collect()).collect()
+        };
+
+        assert_eq!(stacks[0].len(), 1);
+        assert_eq!((stacks[0][0].function.as_str(), stacks[0][0].line), (crate::stack::SCRIPT, Some(5)));
+        assert_eq!(scopes(&stacks[0][0]), vec![vec!["b=2"], vec!["a=1", "clock=<native fn clock>"]]);
+
+        let functions: Vec<(&str, Option<i32>)> = stacks[1].iter().map(|frame| (frame.function.as_str(), frame.line)).collect();
+        assert_eq!(functions, vec![("clock", None), (crate::stack::SCRIPT, Some(6))]);
+        assert!(stacks[1][0].scopes.is_empty());
+
+        // Once the program is done only the globals are left.
+        let frames = interpreter.stack_frames();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(scopes(&frames[0]), vec![vec!["a=1", "clock=<native fn clock>"]]);
+    }
+
+    #[test]
+    fn stats() {
+        let mut interpreter = Interpreter::new(false);
+        assert!(interpreter.stats().is_none());
+        interpreter.enable_stats();
+
+        interpreter.eval("import native \"time\";\nvar a = \"x\";\nfor (var i = 0; i < 2; i = i + 1) {\n  a = a + \"y\";\n}\nclock();").unwrap();
+
+        let stats = interpreter.stats().unwrap();
+        assert_eq!((stats.statements, stats.environments, stats.peak_call_depth), (9, 4, 2));
+        assert_eq!(stats.values.get("string"), Some(&5));
+        assert_eq!(stats.values.get("boolean"), Some(&3));
+        assert_eq!(stats.values.get("number"), Some(&9));
+    }
+
+    #[test]
+    fn prelude() {
+        let mut setup = Interpreter::new(false);
+        setup.register_module(Box::new(MathModule));
+        setup.eval("import native \"math\"; var greeting = \"hi\";").unwrap();
+        let prelude = setup.into_prelude();
+
+        let mut first = Interpreter::with_prelude(&prelude, false);
+        let mut second = Interpreter::with_prelude(&prelude, false);
+
+        assert_eq!(first.eval("var a = sqrt(4); greeting;").unwrap(), Value::from("hi"));
+        assert_eq!(first.get_global("a"), Some(Value::Number(2.0)));
+        assert_eq!(second.get_global("a"), None);
+
+        let error = second.eval("greeting = \"bye\";").unwrap_err();
+        assert_eq!(error.to_string(), "[line 1] Error: Cannot assign to 'greeting', it belongs to the prelude.");
+        assert_eq!(second.eval("import native \"math\"; var greeting = \"bye\"; greeting;").unwrap(), Value::from("bye"));
+        assert_eq!(first.eval("greeting;").unwrap(), Value::from("hi"));
+    }
+}
@@ -0,0 +1,349 @@
+//! Compiles hot functions to native code with Cranelift, behind the `jit` feature. Only
+//! functions that do arithmetic on numbers are compiled: their parameters, literals,
+//! arithmetic, comparisons, `!`, `and`, `or` and `if` with an `else`. Anything else, like
+//! strings, calls or a variable from around the function, leaves it to the interpreter.
+//!
+//! Compiled code only runs when every argument is a number, and it has no side effects.
+//! When it hits something it would have to report, like dividing by 0, it gives up and
+//! the call is interpreted from the start, so errors read the same either way.
+
+use crate::function::Function;
+use crate::interpreter::Value;
+use crate::scanner::{Literal, Token, TokenType};
+use crate::syntax::{Expr, ExprId};
+
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, MemFlags, Type, Value as Ir};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+
+/// How many calls with only numbers as arguments make a function hot.
+pub const JIT_THRESHOLD: u32 = 100;
+
+/// When functions are compiled, set with `Interpreter::set_jit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JitMode {
+    /// Everything is interpreted.
+    Off,
+    /// Functions are compiled once they have been called `JIT_THRESHOLD` times.
+    #[default]
+    Auto,
+    /// Functions are compiled on their first call, for benchmarking the compiled code.
+    Always,
+}
+
+// Compiled code takes the arguments and where to write the result, and returns 0 when it
+// wrote one or 1 when the call has to be interpreted.
+type Code = extern "C" fn(*const f64, *mut f64) -> u8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Number,
+    // A boolean is an I8 that holds 0 or 1.
+    Bool,
+}
+
+pub(crate) struct Compiled {
+    code: Code,
+    returns: Kind,
+    // Owns the memory `code` lives in.
+    module: Option<JITModule>,
+}
+
+// SAFETY: the module is only touched again to free it, and the code it holds reads nothing
+// but its arguments, so calling it from any thread is fine.
+unsafe impl Send for Compiled {}
+unsafe impl Sync for Compiled {}
+
+impl Compiled {
+    /// Runs the code with one number for every parameter.
+    pub(crate) fn call(&self, arguments: &[f64]) -> Option<Value> {
+        let mut result = 0.0;
+        if (self.code)(arguments.as_ptr(), &mut result) != 0 {
+            return None;
+        }
+
+        Some(match self.returns {
+            Kind::Number => Value::Number(result),
+            Kind::Bool => Value::Bool(result != 0.0),
+        })
+    }
+}
+
+impl Drop for Compiled {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: `code` goes away with `self`, so nothing can call into the memory anymore.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// Counts the calls to a function and holds its code once it is hot.
+#[derive(Default)]
+pub(crate) struct Cache {
+    calls: AtomicU32,
+    // `None` once compiling was tried and the function turned out to be unsupported.
+    compiled: OnceLock<Option<Compiled>>,
+}
+
+impl Cache {
+    /// Counts a call to `function`, compiling it if that made it hot.
+    pub(crate) fn get(&self, function: &Function, mode: JitMode) -> Option<&Compiled> {
+        if let Some(compiled) = self.compiled.get() {
+            return compiled.as_ref();
+        }
+
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        if mode == JitMode::Always || calls >= JIT_THRESHOLD {
+            self.compiled.get_or_init(|| compile(function)).as_ref()
+        } else {
+            None
+        }
+    }
+}
+
+/// Compiles `function`, or returns `None` if it does something the compiler doesn't support.
+pub(crate) fn compile(function: &Function) -> Option<Compiled> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder().ok()?.finish(settings::Flags::new(flags)).ok()?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let mut context = module.make_context();
+    let pointer = module.target_config().pointer_type();
+    context.func.signature.params.push(AbiParam::new(pointer));
+    context.func.signature.params.push(AbiParam::new(pointer));
+    context.func.signature.returns.push(AbiParam::new(types::I8));
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    builder.seal_block(entry);
+    let (arguments, output) = (builder.block_params(entry)[0], builder.block_params(entry)[1]);
+
+    let bail = builder.create_block();
+    let params: Vec<Ir> = (0..function.params.len())
+        .map(|index| builder.ins().load(types::F64, MemFlags::trusted(), arguments, (index * 8) as i32))
+        .collect();
+
+    let mut translator = Translator { function, builder, params, bail };
+    let (result, returns) = translator.expr(function.body)?;
+    let mut builder = translator.builder;
+
+    let result = match returns {
+        Kind::Number => result,
+        Kind::Bool => {
+            let wide = builder.ins().uextend(types::I32, result);
+            builder.ins().fcvt_from_uint(types::F64, wide)
+        },
+    };
+    builder.ins().store(MemFlags::trusted(), result, output, 0);
+    let done = builder.ins().iconst(types::I8, 0);
+    builder.ins().return_(&[done]);
+
+    builder.switch_to_block(bail);
+    builder.seal_block(bail);
+    let failed = builder.ins().iconst(types::I8, 1);
+    builder.ins().return_(&[failed]);
+    builder.finalize();
+
+    let id = module.declare_anonymous_function(&context.func.signature).ok()?;
+    module.define_function(id, &mut context).ok()?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+
+    // SAFETY: the function was built with exactly the signature of `Code`.
+    let code = unsafe { std::mem::transmute::<*const u8, Code>(module.get_finalized_function(id)) };
+    Some(Compiled { code, returns, module: Some(module) })
+}
+
+struct Translator<'a> {
+    function: &'a Function,
+    builder: FunctionBuilder<'a>,
+    params: Vec<Ir>,
+    // Where to go when the call has to be interpreted instead.
+    bail: Block,
+}
+
+impl Translator<'_> {
+    fn expr(&mut self, expr: ExprId) -> Option<(Ir, Kind)> {
+        let function = self.function;
+        match &function.ast[expr] {
+            Expr::Literal { value: Literal::Number(number) } => Some((self.builder.ins().f64const(*number), Kind::Number)),
+            Expr::Literal { value: Literal::Bool(value) } => Some((self.builder.ins().iconst(types::I8, i64::from(*value)), Kind::Bool)),
+            Expr::Grouping { expression } => self.expr(*expression),
+            // Like the branches of an `if`, which are blocks.
+            Expr::Block { statements, value: Some(value) } if statements.is_empty() => self.expr(*value),
+            Expr::Variable { name } => {
+                if function.resolution.expr(expr).is_some() {
+                    return None;
+                }
+                // With nothing but parameters in scope, a local is the last one of its name.
+                let index = function.params.iter().rposition(|param| param.lexeme == name.lexeme)?;
+                Some((self.params[index], Kind::Number))
+            },
+            Expr::Unary { operator, right } => {
+                let (right, kind) = self.expr(*right)?;
+                match (operator.token_type, kind) {
+                    (TokenType::Minus, Kind::Number) => Some((self.builder.ins().fneg(right), Kind::Number)),
+                    (TokenType::Bang, Kind::Bool) => Some((self.builder.ins().bxor_imm(right, 1), Kind::Bool)),
+                    _ => None,
+                }
+            },
+            Expr::Binary { .. } | Expr::Logical { .. } => {
+                // Chains nest to the left, so they are translated in a loop.
+                let (first, links) = function.ast.chain(expr);
+                let mut value = self.expr(first)?;
+                for (operator, operand) in links {
+                    value = self.operation(value, operator, operand)?;
+                }
+                Some(value)
+            },
+            Expr::If { keyword: _, condition, then_branch, else_branch: Some(else_branch) } => {
+                let (condition, Kind::Bool) = self.expr(*condition)? else { return None; };
+                self.branch(condition, |this| this.expr(*then_branch), |this| this.expr(*else_branch))
+            },
+            _ => None,
+        }
+    }
+
+    // Applies `operator` to `left`, which is translated already, and to `right`.
+    fn operation(&mut self, left: (Ir, Kind), operator: &Token, right: ExprId) -> Option<(Ir, Kind)> {
+        match (operator.token_type, left) {
+            // `a and b` is `if (a) b else false`, and `a or b` is `if (a) true else b`.
+            (TokenType::And, (condition, Kind::Bool)) => {
+                self.branch(condition, |this| this.expr(right), |_| Some((condition, Kind::Bool)))
+            },
+            (TokenType::Or, (condition, Kind::Bool)) => {
+                self.branch(condition, |_| Some((condition, Kind::Bool)), |this| this.expr(right))
+            },
+            (TokenType::And | TokenType::Or, _) => None,
+            _ => self.binary(left, operator, right),
+        }
+    }
+
+    fn binary(&mut self, (left, left_kind): (Ir, Kind), operator: &Token, right: ExprId) -> Option<(Ir, Kind)> {
+        let (right, right_kind) = self.expr(right)?;
+
+        match (left_kind, right_kind, operator.token_type) {
+            (Kind::Number, Kind::Number, TokenType::Slash) => {
+                // Dividing by 0 is a runtime error for the interpreter to report.
+                let zero = self.builder.ins().f64const(0.0);
+                let is_zero = self.builder.ins().fcmp(FloatCC::Equal, right, zero);
+                let divide = self.builder.create_block();
+                self.builder.ins().brif(is_zero, self.bail, &[], divide, &[]);
+                self.builder.switch_to_block(divide);
+                self.builder.seal_block(divide);
+                Some((self.builder.ins().fdiv(left, right), Kind::Number))
+            },
+            (Kind::Number, Kind::Number, token_type) => {
+                let condition = match token_type {
+                    TokenType::Plus => return Some((self.builder.ins().fadd(left, right), Kind::Number)),
+                    TokenType::Minus => return Some((self.builder.ins().fsub(left, right), Kind::Number)),
+                    TokenType::Star => return Some((self.builder.ins().fmul(left, right), Kind::Number)),
+                    TokenType::Greater => FloatCC::GreaterThan,
+                    TokenType::GreaterEqual => FloatCC::GreaterThanOrEqual,
+                    TokenType::Less => FloatCC::LessThan,
+                    TokenType::LessEqual => FloatCC::LessThanOrEqual,
+                    TokenType::EqualEqual => FloatCC::Equal,
+                    // Unordered too, so `NaN != NaN` like in Rust.
+                    TokenType::BangEqual => FloatCC::NotEqual,
+                    _ => return None,
+                };
+                Some((self.builder.ins().fcmp(condition, left, right), Kind::Bool))
+            },
+            (Kind::Bool, Kind::Bool, TokenType::EqualEqual) => Some((self.builder.ins().icmp(IntCC::Equal, left, right), Kind::Bool)),
+            (Kind::Bool, Kind::Bool, TokenType::BangEqual) => Some((self.builder.ins().icmp(IntCC::NotEqual, left, right), Kind::Bool)),
+            _ => None,
+        }
+    }
+
+    // Evaluates one of two branches depending on `condition`. Both have to be of the same kind.
+    fn branch(
+        &mut self,
+        condition: Ir,
+        then_branch: impl FnOnce(&mut Self) -> Option<(Ir, Kind)>,
+        else_branch: impl FnOnce(&mut Self) -> Option<(Ir, Kind)>,
+    ) -> Option<(Ir, Kind)> {
+        let then_block = self.builder.create_block();
+        let else_block = self.builder.create_block();
+        let merge = self.builder.create_block();
+        self.builder.ins().brif(condition, then_block, &[], else_block, &[]);
+
+        self.builder.switch_to_block(then_block);
+        self.builder.seal_block(then_block);
+        let (then_value, then_kind) = then_branch(self)?;
+        self.builder.ins().jump(merge, &[then_value]);
+
+        self.builder.switch_to_block(else_block);
+        self.builder.seal_block(else_block);
+        let (else_value, else_kind) = else_branch(self)?;
+        if else_kind != then_kind {
+            return None;
+        }
+        self.builder.ins().jump(merge, &[else_value]);
+
+        let value_type: Type = if then_kind == Kind::Number { types::F64 } else { types::I8 };
+        self.builder.append_block_param(merge, value_type);
+        self.builder.switch_to_block(merge);
+        self.builder.seal_block(merge);
+        Some((self.builder.block_params(merge)[0], then_kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn function(source: &str) -> Value {
+        Interpreter::new(false).eval(source).unwrap()
+    }
+
+    fn compiles(source: &str) -> bool {
+        match function(source) {
+            Value::Function(function) => compile(&function).is_some(),
+            value => panic!("{value} is not a function"),
+        }
+    }
+
+    #[test]
+    fn supported() {
+        assert!(compiles("(x, y) => if (x < y and !(x == 0)) { x * 2 } else { -y / 4 };"));
+        assert!(compiles("(a, b) => a != b or true;"));
+        assert!(!compiles("(x) => x + \"a\";"));
+        assert!(!compiles("(x) => if (x > 0) { x };"));
+        assert!(!compiles("(x) => if (x > 0) { x } else { false };"));
+        assert!(!compiles("(x) => { var y = x; y; };"));
+        assert!(!compiles("(x) => x + clock();"));
+    }
+
+    #[test]
+    fn same_results() {
+        let cases: [(&str, &str); 6] = [
+            ("(x, y) => if (x < y) { x * 2 } else { -y / 4 };", "f(1, 2); f(3, 2);"),
+            ("(x) => x / 0;", "f(1);"),
+            ("(x) => false and x / 0 == 1;", "f(1);"),
+            ("(x) => x == 2 or x != x;", "f(2); f(3);"),
+            ("(x, x) => x;", "f(1, 2);"),
+            ("(x) => x - 1;", "f(\"a\");"),
+        ];
+
+        for (function, calls) in cases {
+            let run = |mode: JitMode| {
+                let mut interpreter = Interpreter::new(false);
+                interpreter.set_jit(mode);
+                interpreter.eval(&format!("var f = {function} {calls}"))
+            };
+            assert_eq!(run(JitMode::Always), run(JitMode::Off), "{function}");
+        }
+    }
+}
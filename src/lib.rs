@@ -0,0 +1,55 @@
+//! A tree-walk interpreter for Lox, following the first half of Crafting Interpreters.
+//!
+//! Source is loaded by `source` and goes through `scanner`, `parser` and then `interpreter`; `error` holds what each
+//! stage can report, `codes` the stable code of each kind of error, and `diagnostics` renders
+//! those reports. The `jlox` binary is a thin
+//! wrapper around these modules.
+//!
+//! Evaluation is synchronous: the interpreter walks the tree recursively and cannot
+//! suspend in the middle of a script. Async hosts should build with the `sync` feature
+//! and run `Interpreter::eval` on a blocking thread, such as tokio's `spawn_blocking`.
+
+pub mod source;
+pub mod scanner;
+pub mod parser;
+pub mod incremental;
+pub mod syntax;
+pub mod cst;
+pub mod interpreter;
+pub mod environment;
+pub mod function;
+pub mod error;
+pub mod codes;
+pub mod diagnostics;
+pub mod lint;
+pub mod native;
+pub mod observer;
+pub mod ast_printer;
+pub mod formatter;
+pub mod doc;
+pub mod conformance;
+pub mod symbols;
+pub mod callgraph;
+pub mod metrics;
+pub mod diff;
+pub mod completion;
+pub mod rename;
+pub mod stack;
+pub mod stats;
+pub mod heap;
+pub mod replay;
+pub mod threads;
+pub mod intern;
+mod globals;
+mod state;
+mod suggest;
+mod shared;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "jit")]
+pub mod jit;
+
+pub use error::{ConversionError, LoxError};
+pub use interpreter::{Interpreter, Prelude, Value, ValueKind};
@@ -0,0 +1,384 @@
+use crate::scanner::{Literal, Token, TokenType};
+use crate::symbols::{Position, SymbolTable, GLOBAL_SCOPE};
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    Unused,
+    Shadow,
+    ConstantCondition,
+    EmptyBlock,
+    Unreachable,
+    SelfAssign,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [Lint::Unused, Lint::Shadow, Lint::ConstantCondition, Lint::EmptyBlock, Lint::Unreachable, Lint::SelfAssign];
+
+    /// The name used on the command line, as in `-Wunused`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::Unused => "unused",
+            Lint::Shadow => "shadow",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::EmptyBlock => "empty-block",
+            Lint::Unreachable => "unreachable",
+            Lint::SelfAssign => "self-assign",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    /// The code `jlox --explain` takes, as in `W0001`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Lint::Unused => "W0001",
+            Lint::Shadow => "W0002",
+            Lint::ConstantCondition => "W0003",
+            Lint::EmptyBlock => "W0004",
+            Lint::Unreachable => "W0005",
+            Lint::SelfAssign => "W0006",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn explanation(self) -> &'static str {
+        match self {
+            Lint::Unused => "\
+A local variable is declared but never read, which is often a leftover or a typo in a
+later use. Globals aren't reported, since later input may still read them. Start the
+name with `_` to keep a variable that is unused on purpose.",
+            Lint::Shadow => "\
+A variable has the same name as one in an enclosing scope, which hides the outer one
+for the rest of the block. Renaming one of them makes clear which is meant.",
+            Lint::ConstantCondition => "\
+The condition of an `if` is a literal, so it always takes the same branch.",
+            Lint::EmptyBlock => "\
+An `if` or `else` has an empty body, which usually means code is missing.",
+            Lint::Unreachable => "\
+Statements after a `break` in the same block can never run.",
+            Lint::SelfAssign => "\
+A variable is assigned to itself, which does nothing. Usually another variable was meant.",
+        }
+    }
+
+    /// A program that gets this warning.
+    pub fn example(self) -> &'static str {
+        match self {
+            Lint::Unused => "{\n    var total = 0;\n}",
+            Lint::Shadow => "var count = 1;\n{\n    var count = 2;\n    print count;\n}",
+            Lint::ConstantCondition => "if (true) print 1;",
+            Lint::EmptyBlock => "var ready = false;\nif (ready) {}",
+            Lint::Unreachable => "while (true) {\n    break;\n    print 1;\n}",
+            Lint::SelfAssign => "var count = 1;\ncount = count;",
+        }
+    }
+
+    pub fn enabled_by_default(self) -> bool {
+        match self {
+            Lint::Unused | Lint::ConstantCondition | Lint::Unreachable | Lint::SelfAssign => true,
+            Lint::Shadow | Lint::EmptyBlock => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub token: Token,
+    pub message: String,
+}
+
+impl Warning {
+    fn new(lint: Lint, token: &Token, message: &str) -> Self {
+        Self { lint, token: token.clone(), message: message.to_string() }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Warning: {} [-W{}]", self.token.line, self.message, self.lint.name())
+    }
+}
+
+/// Runs every lint over the program. Callers decide which of the warnings to report.
+pub fn check(ast: &Ast) -> Vec<Warning> {
+    let mut linter = Linter { ast, warnings: Vec::new() };
+    for &statement in &ast.statements {
+        linter.stmt(statement);
+    }
+
+    let mut warnings = linter.warnings;
+    warnings.extend(check_symbols(&SymbolTable::build(ast)));
+    warnings.sort_by_key(|warning| (warning.token.line, warning.token.column));
+    warnings
+}
+
+// The lints about declarations: unused and shadowing variables.
+fn check_symbols(table: &SymbolTable) -> Vec<Warning> {
+    let mut warnings: Vec<Warning> = Vec::new();
+
+    // Globals are never reported as unused because later input (another REPL line, for
+    // instance) may still read them.
+    for (id, symbol) in table.symbols.iter().enumerate().filter(|(_, symbol)| symbol.scope != GLOBAL_SCOPE) {
+        let name = &symbol.name;
+
+        let parent = table.scopes[symbol.scope].parent.unwrap_or(GLOBAL_SCOPE);
+        if let Some(outer) = table.resolve(parent, &name.lexeme, Position::of(name)) {
+            let message = format!("Variable '{}' shadows the declaration on line {}.", name.lexeme, table.symbols[outer].name.line);
+            warnings.push(Warning::new(Lint::Shadow, name, &message));
+        }
+
+        // A leading underscore marks a variable as intentionally unused.
+        if !table.is_read(id) && !name.lexeme.starts_with('_') {
+            let message = format!("Variable '{}' is never read.", name.lexeme);
+            warnings.push(Warning::new(Lint::Unused, name, &message));
+        }
+    }
+
+    warnings
+}
+
+struct Linter<'a> {
+    ast: &'a Ast,
+    warnings: Vec<Warning>,
+}
+
+impl Linter<'_> {
+    fn stmt(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        match &ast[stmt] {
+            Stmt::Block { statements } => {
+                let mut after_break = false;
+                for &statement in statements {
+                    if after_break {
+                        if let Some(token) = stmt_token(ast, statement) {
+                            self.warnings.push(Warning::new(Lint::Unreachable, token, "Unreachable code after 'break'."));
+                        }
+                        after_break = false;
+                    }
+                    self.stmt(statement);
+                    after_break |= matches!(ast[statement], Stmt::Break {});
+                }
+            },
+            Stmt::Expression { expression } => self.expr(*expression),
+            Stmt::If { keyword, condition, then_branch, else_branch } => {
+                if let Some(value) = constant(ast, *condition) {
+                    self.warnings.push(Warning::new(Lint::ConstantCondition, keyword, &format!("Condition is always {value}.")));
+                }
+                if is_empty_block(ast, *then_branch) {
+                    self.warnings.push(Warning::new(Lint::EmptyBlock, keyword, "Empty 'if' body."));
+                }
+                if else_branch.is_some_and(|else_branch| is_empty_block(ast, else_branch)) {
+                    self.warnings.push(Warning::new(Lint::EmptyBlock, keyword, "Empty 'else' body."));
+                }
+
+                self.expr(*condition);
+                self.stmt(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(*else_branch);
+                }
+            },
+            Stmt::Print { keyword: _, expression } => self.expr(*expression),
+            Stmt::Variable { name: _, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expr(*initializer);
+                }
+            },
+            Stmt::While { keyword, condition, body } => {
+                self.check_loop(keyword, Some(*condition), *body);
+                self.expr(*condition);
+                self.stmt(*body);
+            },
+            Stmt::For { keyword, initializer, condition, increment, body } => {
+                self.check_loop(keyword, *condition, *body);
+                if let Some(initializer) = initializer {
+                    self.stmt(*initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expr(*condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(*increment);
+                }
+                self.stmt(*body);
+            },
+            Stmt::Break {} | Stmt::Import { keyword: _, module: _ } | Stmt::Debugger { keyword: _ } | Stmt::Error { tokens: _ } => {},
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        match &ast[expr] {
+            Expr::Assign { name, value } => {
+                if let Expr::Variable { name: source } = unwrap_groupings(ast, *value) {
+                    if source.lexeme == name.lexeme {
+                        let message = format!("Variable '{}' is assigned to itself.", name.lexeme);
+                        self.warnings.push(Warning::new(Lint::SelfAssign, name, &message));
+                    }
+                }
+                self.expr(*value);
+            },
+            Expr::Binary { .. } | Expr::Logical { .. } => {
+                for operand in ast.chain_operands(expr) {
+                    self.expr(operand);
+                }
+            },
+            Expr::Call { callee, paren: _, arguments } => {
+                self.expr(*callee);
+                for &argument in arguments {
+                    self.expr(argument);
+                }
+            },
+            Expr::Grouping { expression } => self.expr(*expression),
+            Expr::Literal { value: _ } => {},
+            Expr::Unary { operator: _, right } => self.expr(*right),
+            Expr::Variable { name: _ } => {},
+            Expr::Block { statements, value } => {
+                for &statement in statements {
+                    self.stmt(statement);
+                }
+                if let Some(value) = value {
+                    self.expr(*value);
+                }
+            },
+            Expr::If { keyword, condition, then_branch, else_branch } => {
+                if let Some(value) = constant(ast, *condition) {
+                    self.warnings.push(Warning::new(Lint::ConstantCondition, keyword, &format!("Condition is always {value}.")));
+                }
+
+                self.expr(*condition);
+                self.expr(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expr(*else_branch);
+                }
+            },
+            Expr::Lambda { params: _, arrow: _, body } => self.expr(*body),
+            Expr::Error { token: _ } => {},
+        }
+    }
+
+    // `while (true)` is how Lox spells an endless loop, so only a loop that never runs is
+    // reported.
+    fn check_loop(&mut self, keyword: &Token, condition: Option<ExprId>, body: StmtId) {
+        if condition.and_then(|condition| constant(self.ast, condition)) == Some(false) {
+            self.warnings.push(Warning::new(Lint::ConstantCondition, keyword, "Condition is always false, so the loop never runs."));
+        }
+        if is_empty_block(self.ast, body) {
+            self.warnings.push(Warning::new(Lint::EmptyBlock, keyword, "Empty loop body."));
+        }
+    }
+}
+
+fn unwrap_groupings(ast: &Ast, expr: ExprId) -> &Expr {
+    match &ast[expr] {
+        Expr::Grouping { expression } => unwrap_groupings(ast, *expression),
+        expr => expr,
+    }
+}
+
+/// The truthiness of a condition made of literals alone.
+fn constant(ast: &Ast, expr: ExprId) -> Option<bool> {
+    match unwrap_groupings(ast, expr) {
+        Expr::Literal { value: Literal::Bool(value) } => Some(*value),
+        Expr::Literal { value: Literal::Nil } => Some(false),
+        Expr::Literal { value: _ } => Some(true),
+        Expr::Unary { operator, right } if operator.token_type == TokenType::Bang => constant(ast, *right).map(|value| !value),
+        _ => None,
+    }
+}
+
+fn is_empty_block(ast: &Ast, stmt: StmtId) -> bool {
+    matches!(&ast[stmt], Stmt::Block { statements } if statements.is_empty())
+}
+
+// The first token of a statement, to point a warning at. Statements made only of
+// literals, and 'break', have none.
+fn stmt_token(ast: &Ast, stmt: StmtId) -> Option<&Token> {
+    match &ast[stmt] {
+        Stmt::Block { statements } => statements.iter().find_map(|&statement| stmt_token(ast, statement)),
+        Stmt::Expression { expression } => expr_token(ast, *expression),
+        Stmt::Print { keyword, .. } | Stmt::If { keyword, .. } | Stmt::While { keyword, .. } | Stmt::For { keyword, .. } => Some(keyword),
+        Stmt::Import { keyword, .. } | Stmt::Debugger { keyword } => Some(keyword),
+        Stmt::Variable { name, .. } => Some(name),
+        Stmt::Error { tokens } => tokens.first(),
+        Stmt::Break {} => None,
+    }
+}
+
+fn expr_token(ast: &Ast, expr: ExprId) -> Option<&Token> {
+    match &ast[expr] {
+        Expr::Assign { name, value: _ } | Expr::Variable { name } => Some(name),
+        Expr::Binary { left, operator, right: _ } | Expr::Logical { left, operator, right: _ } => {
+            // Chains nest to the left, so their innermost operation is found in a loop.
+            let (mut left, mut operator) = (*left, operator);
+            while let Expr::Binary { left: inner, operator: inner_operator, right: _ } | Expr::Logical { left: inner, operator: inner_operator, right: _ } = &ast[left] {
+                (left, operator) = (*inner, inner_operator);
+            }
+            expr_token(ast, left).or(Some(operator))
+        },
+        Expr::Call { callee, paren, arguments: _ } => expr_token(ast, *callee).or(Some(paren)),
+        Expr::Grouping { expression } => expr_token(ast, *expression),
+        Expr::Literal { value: _ } => None,
+        Expr::Unary { operator, right: _ } => Some(operator),
+        Expr::Block { statements, value } => {
+            statements.iter().find_map(|&statement| stmt_token(ast, statement)).or_else(|| value.and_then(|value| expr_token(ast, value)))
+        },
+        Expr::If { keyword, .. } => Some(keyword),
+        Expr::Lambda { params, arrow, body: _ } => params.first().or(Some(arrow)),
+        Expr::Error { token } => Some(token),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    fn lints(source: &str) -> Vec<(Lint, i32)> {
+        let ast = parse_source(source).unwrap();
+        check(&ast).iter().map(|warning| (warning.lint, warning.token.line)).collect()
+    }
+
+    #[test]
+    fn unused_locals() {
+        assert_eq!(lints("var a = 1;"), vec![]);
+        assert_eq!(lints("{\nvar a = 1;\nvar _b = 2;\nvar c = 3;\nprint c;\n}"), vec![(Lint::Unused, 2)]);
+        assert_eq!(lints("{ var a = 1; a = 2; }"), vec![(Lint::Unused, 1)]);
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(lints("var a = 1;\n{\nvar a = a;\nprint a;\n}"), vec![(Lint::Shadow, 3)]);
+        assert_eq!(lints("{ var a = 1; print a; }\n{ var a = 2; print a; }"), vec![]);
+    }
+
+    #[test]
+    fn constant_conditions() {
+        assert_eq!(lints("if (true) print 1;\nif (!nil) print 2;\nvar a = 1;\nif (a) print a;"), vec![(Lint::ConstantCondition, 1), (Lint::ConstantCondition, 2)]);
+        assert_eq!(lints("while (true) break;\nwhile (false) print 1;\nfor (;(nil);) print 2;"), vec![(Lint::ConstantCondition, 2), (Lint::ConstantCondition, 3)]);
+    }
+
+    #[test]
+    fn empty_blocks() {
+        assert_eq!(lints("var a = 1;\nif (a) {} else {}\nwhile (a) {}\n{}"), vec![(Lint::EmptyBlock, 2), (Lint::EmptyBlock, 2), (Lint::EmptyBlock, 3)]);
+    }
+
+    #[test]
+    fn unreachable() {
+        assert_eq!(lints("var a = 1;\nwhile (a) {\nbreak;\nprint a;\na = 2;\n}"), vec![(Lint::Unreachable, 4)]);
+        assert_eq!(lints("var a = 1;\nwhile (a) {\nif (a) break;\nprint a;\n}"), vec![]);
+    }
+
+    #[test]
+    fn self_assignment() {
+        assert_eq!(lints("var a = 1;\na = (a);\na = a + 1;"), vec![(Lint::SelfAssign, 2)]);
+    }
+}
@@ -1,70 +1,834 @@
-pub mod scanner;
-pub mod environment;
-mod syntax;
-mod parser;
-mod interpreter;
+mod dap;
+mod repl;
 
-use interpreter::Interpreter;
+use crafting_interpreters_rust::callgraph::CallGraph;
+use crafting_interpreters_rust::codes;
+use crafting_interpreters_rust::conformance::{self, Chapter};
+use crafting_interpreters_rust::diff::{self, DiffError};
+use crafting_interpreters_rust::diagnostics::{ColorChoice, Diagnostics, Level};
+use crafting_interpreters_rust::doc;
+use crafting_interpreters_rust::error::{CompileError, LoxError};
+use crafting_interpreters_rust::formatter;
+use crafting_interpreters_rust::interpreter::Interpreter;
+#[cfg(feature = "jit")]
+use crafting_interpreters_rust::jit::JitMode;
+use crafting_interpreters_rust::lint::{self, Lint};
+use crafting_interpreters_rust::metrics;
+use crafting_interpreters_rust::native::DebugModule;
+use crafting_interpreters_rust::observer::Trace;
+use crafting_interpreters_rust::parser;
+use crafting_interpreters_rust::rename::{self, RenameError};
+use crafting_interpreters_rust::replay::Recording;
+use crafting_interpreters_rust::scanner::Dialect;
+use crafting_interpreters_rust::source::read_source;
+use crafting_interpreters_rust::symbols::Position;
+use crafting_interpreters_rust::threads::ThreadsModule;
+
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
 
 use std::{env, process::exit};
 use std::fs;
-use std::io::stdin;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
 
-fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), ()> {
-    let tokens = match scanner::scan_tokens(contents) {
-        Ok(tokens) => tokens,
-        Err(_) => { return Err(()); }
-    };
+/// Runs `contents` and reports any warnings and errors through `diagnostics`.
+fn run(interpreter: &mut Interpreter, contents: &str, diagnostics: &Diagnostics) -> Result<(), LoxError> {
+    run_checked(interpreter, contents, diagnostics).inspect_err(|e| diagnostics.emit_error(e, contents))
+}
 
-    let statements = match parser::parse_tokens(tokens) {
-        Ok(statements) => statements,
-        Err(_) => { return Err(()); }
-    };
+fn run_checked(interpreter: &mut Interpreter, contents: &str, diagnostics: &Diagnostics) -> Result<(), LoxError> {
+    let ast = parser::parse_source_with_max_depth(contents, interpreter.dialect(), interpreter.max_depth())?;
+
+    // The book's jlox has no lints, so neither does its dialect.
+    let warnings = if interpreter.dialect().jlox { Vec::new() } else { diagnostics.filter(lint::check(&ast)) };
+    if diagnostics.deny_warnings() && !warnings.is_empty() {
+        return Err(LoxError::Compile(warnings.into_iter().map(CompileError::Lint).collect()));
+    }
+    for warning in &warnings {
+        diagnostics.emit_warning(warning, contents);
+    }
+
+    interpreter.interpret(&ast)?;
+
+    Ok(())
+}
+
+const USAGE: &str = "Usage: jlox [--color=always|never|auto] [-W<lint>] [-A<lint>] [--deny-warnings] [--no-init] [--trace] [--stats] [--leak-check] [--heap-snapshot-on-exit=<file>] [--max-call-depth=<n>] [--max-depth=<n>] [--seed=<n>] [--record=<file>] [--replay=<file>] [--print-function] [--compat=jlox] [--jit=off|auto|always] [-v|-vv] [script]\n       jlox lint [-W<lint>] [-A<lint>] <file>...\n       jlox fmt [--check] <file>...\n       jlox doc [--html] <file>\n       jlox callgraph [--format=text|dot] <file>\n       jlox diff <before> <after>\n       jlox metrics [--format=table|json] <file>\n       jlox rename [--write] <file> <old> <new> --line <n> --col <n>\n       jlox conformance [--chapter=<n>] <test directory>\n       jlox dap\n       jlox --explain <code>";
+
+struct Options {
+    color: ColorChoice,
+    lint_levels: Vec<(Lint, Level)>,
+    deny_warnings: bool,
+    // Skip running the REPL's init file.
+    no_init: bool,
+    // Log every statement, assignment and call to stderr.
+    trace: bool,
+    // Report what running the script cost once it is done.
+    stats: bool,
+    leak_check: bool,
+    // Where to write a snapshot of what the script keeps alive once it ends.
+    heap_snapshot: Option<String>,
+    max_call_depth: Option<usize>,
+    // How deeply the script may nest, in place of `parser::DEFAULT_MAX_DEPTH`.
+    max_depth: Option<usize>,
+    // Makes the `random` natives draw the same numbers every run.
+    seed: Option<u64>,
+    // Where to write what the script read from outside, and the recording to replay.
+    record: Option<String>,
+    replay: Option<String>,
+    // Run the script with `print` as a native function rather than a statement.
+    print_function: bool,
+    jlox: bool,
+    #[cfg(feature = "jit")]
+    jit: Option<JitMode>,
+    // How much of the interpreter's own spans to log: 1 for its phases, 2 for every call too.
+    verbosity: u8,
+    files: Vec<String>,
+}
+
+impl Options {
+    #[cfg(feature = "jit")]
+    fn jit_requested(&self) -> bool {
+        self.jit.is_some()
+    }
+
+    // Without the jit feature, --jit is refused while parsing.
+    #[cfg(not(feature = "jit"))]
+    fn jit_requested(&self) -> bool {
+        false
+    }
+}
+
+fn parse_lint(name: &str) -> Result<Lint, String> {
+    Lint::from_name(name).ok_or_else(|| format!("Unknown lint '{name}'."))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { color: ColorChoice::Auto, lint_levels: Vec::new(), deny_warnings: false, no_init: false, trace: false, stats: false, leak_check: false, heap_snapshot: None, max_call_depth: None, max_depth: None, seed: None, record: None, replay: None, print_function: false, jlox: false, #[cfg(feature = "jit")] jit: None, verbosity: 0, files: Vec::new() };
+
+    for arg in args {
+        if let Some(name) = arg.strip_prefix("-W") {
+            options.lint_levels.push((parse_lint(name)?, Level::Warn));
+        } else if let Some(name) = arg.strip_prefix("-A") {
+            options.lint_levels.push((parse_lint(name)?, Level::Allow));
+        } else if arg == "--deny-warnings" {
+            options.deny_warnings = true;
+        } else if arg == "--no-init" {
+            options.no_init = true;
+        } else if arg == "--trace" {
+            options.trace = true;
+        } else if arg == "--stats" {
+            options.stats = true;
+        } else if arg == "--leak-check" {
+            options.leak_check = true;
+        } else if arg == "-v" {
+            options.verbosity = options.verbosity.saturating_add(1);
+        } else if arg == "-vv" {
+            options.verbosity = options.verbosity.saturating_add(2);
+        } else if arg == "--print-function" {
+            options.print_function = true;
+        } else if let Some(value) = arg.strip_prefix("--max-call-depth=") {
+            options.max_call_depth = match value.parse() {
+                Ok(depth) => Some(depth),
+                Err(_) => { return Err(format!("Invalid value '{value}' for --max-call-depth.")); }
+            };
+        } else if let Some(value) = arg.strip_prefix("--max-depth=") {
+            options.max_depth = match value.parse() {
+                Ok(depth) => Some(depth),
+                Err(_) => { return Err(format!("Invalid value '{value}' for --max-depth.")); }
+            };
+        } else if let Some(path) = arg.strip_prefix("--heap-snapshot-on-exit=") {
+            options.heap_snapshot = Some(path.to_string());
+        } else if let Some(path) = arg.strip_prefix("--record=") {
+            options.record = Some(path.to_string());
+        } else if let Some(path) = arg.strip_prefix("--replay=") {
+            options.replay = Some(path.to_string());
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            options.seed = match value.parse() {
+                Ok(seed) => Some(seed),
+                Err(_) => { return Err(format!("Invalid value '{value}' for --seed.")); }
+            };
+        } else if let Some(value) = arg.strip_prefix("--compat=") {
+            options.jlox = match value {
+                "jlox" => true,
+                _ => { return Err(format!("Invalid value '{value}' for --compat.")); }
+            };
+        } else if let Some(value) = arg.strip_prefix("--jit=") {
+            set_jit(&mut options, value)?;
+        } else if let Some(value) = arg.strip_prefix("--color=") {
+            options.color = match ColorChoice::parse(value) {
+                Some(color) => color,
+                None => { return Err(format!("Invalid value '{value}' for --color.")); }
+            };
+        } else if arg.starts_with('-') {
+            return Err(format!("Unknown option '{arg}'."));
+        } else {
+            options.files.push(arg.clone());
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(feature = "jit")]
+fn set_jit(options: &mut Options, value: &str) -> Result<(), String> {
+    options.jit = Some(match value {
+        "off" => JitMode::Off,
+        "auto" => JitMode::Auto,
+        "always" => JitMode::Always,
+        _ => { return Err(format!("Invalid value '{value}' for --jit.")); }
+    });
+    Ok(())
+}
 
-    interpreter.interpret(&statements)
+#[cfg(not(feature = "jit"))]
+fn set_jit(_options: &mut Options, _value: &str) -> Result<(), String> {
+    Err(String::from("--jit needs jlox to be built with the jit feature."))
 }
 
-fn run_file(filename: &str) -> Result<(), ()> {
-    println!("Running file {filename}");
+/// Logs the interpreter's spans to stderr. `-v` shows the scan, parse, resolve and execute
+/// phases with their timings and `-vv` every call as well. Without either, `LOX_LOG` takes
+/// a filter like `debug` or `crafting_interpreters_rust::parser=debug`.
+fn init_logging(verbosity: u8) {
+    let filter = match verbosity {
+        0 => EnvFilter::try_from_env("LOX_LOG").unwrap_or_else(|_| EnvFilter::new("off")),
+        1 => EnvFilter::new("debug"),
+        _ => EnvFilter::new("trace"),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+}
+
+fn run_file(filename: &str, contents: &str, diagnostics: &Diagnostics, options: &Options, replay: Option<&Recording>) -> Result<(), LoxError> {
+    // The book's jlox prints nothing but the script's own output.
+    if !options.jlox {
+        println!("Running file {filename}");
+    }
 
     let mut interpreter = Interpreter::new(false);
+    interpreter.register_module(Box::new(ThreadsModule));
+    interpreter.register_module(Box::new(DebugModule));
+    if options.trace {
+        interpreter.add_observer(Box::new(Trace::stderr()));
+    }
+    if options.stats {
+        interpreter.enable_stats();
+    }
+    if options.leak_check {
+        interpreter.enable_leak_check();
+    }
+    if let Some(max_call_depth) = options.max_call_depth {
+        interpreter.set_max_call_depth(max_call_depth);
+    }
+    if let Some(max_depth) = options.max_depth {
+        interpreter.set_max_depth(max_depth);
+    }
+    if let Some(seed) = options.seed {
+        interpreter.set_seed(seed);
+    }
+    if let Some(recording) = replay {
+        interpreter.replay(recording);
+    }
+    if options.record.is_some() {
+        interpreter.start_recording();
+    }
+    interpreter.set_dialect(Dialect { print_function: options.print_function, jlox: options.jlox });
+    #[cfg(feature = "jit")]
+    if let Some(mode) = options.jit {
+        interpreter.set_jit(mode);
+    }
+
+    let result = run(&mut interpreter, contents, diagnostics);
+
+    // A failed run still did work worth reporting.
+    if let Some(stats) = interpreter.stats() {
+        eprintln!("{stats}");
+    }
+    if let Some(path) = &options.heap_snapshot {
+        let written = fs::File::create(path).and_then(|file| interpreter.write_heap_snapshot(io::BufWriter::new(file)));
+        if let Err(e) = written {
+            eprintln!("Could not write the heap snapshot to '{path}': {e}");
+        }
+    }
+    if let Some(path) = &options.record {
+        let written = fs::File::create(path).and_then(|file| interpreter.recording().write(io::BufWriter::new(file)));
+        if let Err(e) = written {
+            eprintln!("Could not write the recording to '{path}': {e}");
+        }
+    }
+    if options.leak_check {
+        report_leaks(interpreter.leaked_scopes());
+    }
+    result
+}
 
-    let contents = fs::read_to_string(filename).expect("Someting went wrong reading the file");
-    run(&mut interpreter, &contents)
+// Groups the leaked scopes by the line they opened on, since a leak in a loop repeats.
+fn report_leaks(lines: Vec<i32>) {
+    let mut counts: Vec<(i32, usize)> = Vec::new();
+    for line in lines {
+        match counts.iter_mut().find(|(counted, _)| *counted == line) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((line, 1)),
+        }
+    }
+
+    for (line, count) in counts {
+        if count == 1 {
+            eprintln!("Leak check: a scope opened at line {line} is kept alive by a reference cycle.");
+        } else {
+            eprintln!("Leak check: {count} scopes opened at line {line} are kept alive by reference cycles.");
+        }
+    }
 }
 
-fn run_prompt() {
-    println!("Running prompt");
+fn diagnostics_for(options: &Options) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new(options.color.use_color());
+    for &(lint, level) in &options.lint_levels {
+        diagnostics.set_level(lint, level);
+    }
+    diagnostics.set_deny_warnings(options.deny_warnings);
+    diagnostics.set_jlox(options.jlox);
+    diagnostics
+}
 
-    let mut interpreter = Interpreter::new(true);
+/// Reports the enabled lints for every file without running them. The exit code is 1 if
+/// there were warnings and 65 if a file doesn't parse.
+fn lint_files(options: &Options) -> i32 {
+    if options.files.is_empty() {
+        eprintln!("{USAGE}");
+        return 64;
+    }
+
+    let diagnostics = diagnostics_for(options);
+    let mut status = 0;
+
+    for file in &options.files {
+        let contents = match read_source(file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("Could not read '{file}': {e}");
+                status = 66;
+                continue;
+            }
+        };
 
-    loop {
-        let mut buffer = String::new();
-        stdin().read_line(&mut buffer).expect("Failed to read line.");
-        match buffer.as_str() {
-            "\n" | "\r\n" => {
-                println!("Exiting interactive prompt.");
-                exit(0)
+        match parser::parse_source(&contents) {
+            Ok(ast) => {
+                let warnings = diagnostics.filter(lint::check(&ast));
+                for warning in &warnings {
+                    diagnostics.emit_warning(warning, &contents);
+                }
+                if !warnings.is_empty() {
+                    status = status.max(1);
+                }
             },
-            _ => { run(&mut interpreter, &buffer).ok(); }
+            Err(errors) => {
+                diagnostics.emit_error(&LoxError::from(errors), &contents);
+                status = 65;
+            }
         }
     }
+
+    status
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// Formats the given files in place. With `--check` they are only compared against their
+/// formatted version, for CI, and the exit code tells whether any of them would change.
+fn format_files(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if files.is_empty() {
+        eprintln!("{USAGE}");
+        return 64;
+    }
 
-    match args.len() {
-        1 => run_prompt(),
-        2 => {
-            match run_file(&args[1]) {
-                Ok(_) => {},
-                Err(_) => { exit(1); }
+    let diagnostics = Diagnostics::new(ColorChoice::Auto.use_color());
+    let mut status = 0;
+
+    for file in files {
+        let contents = match read_source(file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("Could not read '{file}': {e}");
+                status = 66;
+                continue;
+            }
+        };
+
+        let formatted = match formatter::format_source(&contents) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                diagnostics.emit_error(&LoxError::from(errors), &contents);
+                status = 65;
+                continue;
             }
+        };
+
+        if formatted == contents {
+            continue;
+        }
+        if check {
+            println!("Would reformat {file}");
+            status = status.max(1);
+        } else if let Err(e) = fs::write(file, formatted) {
+            eprintln!("Could not write '{file}': {e}");
+            status = 73;
+        }
+    }
+
+    status
+}
+
+/// Prints the documentation of a script's top-level declarations, as Markdown by default.
+fn document_file(args: &[String]) -> i32 {
+    let html = args.iter().any(|arg| arg == "--html");
+    let file = match args.iter().filter(|arg| *arg != "--html").collect::<Vec<&String>>()[..] {
+        [file] => file,
+        _ => {
+            eprintln!("{USAGE}");
+            return 64;
+        }
+    };
+
+    let contents = match read_source(file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Could not read '{file}': {e}");
+            return 66;
+        }
+    };
+
+    match doc::extract(&contents) {
+        Ok(items) if html => print!("{}", doc::to_html(file, &items)),
+        Ok(items) => print!("{}", doc::to_markdown(file, &items)),
+        Err(errors) => {
+            Diagnostics::new(ColorChoice::Auto.use_color()).emit_error(&LoxError::from(errors), &contents);
+            return 65;
+        }
+    }
+
+    0
+}
+
+/// Prints which functions of a script call which, as text or as a Graphviz graph. Calls of
+/// names the script never declares or imports are also reported on stderr.
+fn print_call_graph(args: &[String]) -> i32 {
+    let mut dot = false;
+    let mut files: Vec<&String> = Vec::new();
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--format=") {
+            dot = match value {
+                "text" => false,
+                "dot" => true,
+                _ => {
+                    eprintln!("Invalid value '{value}' for --format, which can be text or dot.");
+                    return 64;
+                }
+            };
+        } else {
+            files.push(arg);
+        }
+    }
+    let file = match files[..] {
+        [file] => file,
+        _ => {
+            eprintln!("{USAGE}");
+            return 64;
+        }
+    };
+
+    let contents = match read_source(file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Could not read '{file}': {e}");
+            return 66;
+        }
+    };
+
+    let graph = match CallGraph::build(&contents) {
+        Ok(graph) => graph,
+        Err(errors) => {
+            Diagnostics::new(ColorChoice::Auto.use_color()).emit_error(&LoxError::from(errors), &contents);
+            return 65;
+        }
+    };
+
+    for (name, call) in graph.unknown_calls() {
+        eprintln!("{file}:{}:{}: call to unknown name '{name}'", call.line, call.column);
+    }
+    if dot {
+        print!("{}", graph.to_dot());
+    } else {
+        print!("{}", graph.to_text());
+    }
+
+    0
+}
+
+/// Prints how the statements of one version of a script differ from another's, ignoring
+/// layout. Like `diff`, the exit code is 1 if there are differences.
+fn diff_files(args: &[String]) -> i32 {
+    let (before, after) = match args {
+        [before, after] => (before, after),
+        _ => {
+            eprintln!("{USAGE}");
+            return 64;
+        }
+    };
+
+    let mut contents: Vec<String> = Vec::new();
+    for file in [before, after] {
+        match read_source(file) {
+            Ok(source) => contents.push(source),
+            Err(e) => {
+                eprintln!("Could not read '{file}': {e}");
+                return 66;
+            }
+        }
+    }
+
+    let changes = match diff::diff(&contents[0], &contents[1]) {
+        Ok(changes) => changes,
+        Err(error) => {
+            let (errors, source) = match error {
+                DiffError::Before(errors) => (errors, &contents[0]),
+                DiffError::After(errors) => (errors, &contents[1]),
+            };
+            Diagnostics::new(ColorChoice::Auto.use_color()).emit_error(&LoxError::from(errors), source);
+            return 65;
+        }
+    };
+
+    for change in &changes {
+        println!("{change}");
+    }
+    if changes.is_empty() { 0 } else { 1 }
+}
+
+/// Prints the size and complexity of each function of a script, as a table or as JSON.
+fn print_metrics(args: &[String]) -> i32 {
+    let mut json = false;
+    let mut files: Vec<&String> = Vec::new();
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--format=") {
+            json = match value {
+                "table" => false,
+                "json" => true,
+                _ => {
+                    eprintln!("Invalid value '{value}' for --format, which can be table or json.");
+                    return 64;
+                }
+            };
+        } else {
+            files.push(arg);
+        }
+    }
+    let file = match files[..] {
+        [file] => file,
+        _ => {
+            eprintln!("{USAGE}");
+            return 64;
+        }
+    };
+
+    let contents = match read_source(file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Could not read '{file}': {e}");
+            return 66;
+        }
+    };
+
+    match metrics::measure(&contents) {
+        Ok(functions) if json => println!("{:#}", metrics::to_json(&functions)),
+        Ok(functions) => print!("{}", metrics::to_table(&functions)),
+        Err(errors) => {
+            Diagnostics::new(ColorChoice::Auto.use_color()).emit_error(&LoxError::from(errors), &contents);
+            return 65;
+        }
+    }
+
+    0
+}
+
+/// Renames the variable `old` declared or used at `--line` and `--col` to `new`. The edits
+/// are printed, or made in the file with `--write`.
+fn rename_variable(args: &[String]) -> i32 {
+    let mut write = false;
+    let (mut line, mut column): (Option<i32>, Option<usize>) = (None, None);
+    let mut positional: Vec<&String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--write" => write = true,
+            "--line" | "--col" => {
+                let value = match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{USAGE}");
+                        return 64;
+                    }
+                };
+                let parsed = value.parse().ok().filter(|&number: &usize| number > 0);
+                match (arg.as_str(), parsed) {
+                    ("--line", Some(number)) if i32::try_from(number).is_ok() => line = Some(number as i32),
+                    ("--col", Some(number)) => column = Some(number),
+                    _ => {
+                        eprintln!("Invalid value '{value}' for {arg}, which must be a positive number.");
+                        return 64;
+                    }
+                }
+            },
+            _ => positional.push(arg),
+        }
+    }
+    let (file, old, new, position) = match (&positional[..], line, column) {
+        ([file, old, new], Some(line), Some(column)) => (file, old, new, Position { line, column }),
+        _ => {
+            eprintln!("{USAGE}");
+            return 64;
+        }
+    };
+
+    let contents = match read_source(file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Could not read '{file}': {e}");
+            return 66;
+        }
+    };
+
+    let edits = match rename::rename(&contents, position, old, new) {
+        Ok(edits) => edits,
+        Err(RenameError::Compile(errors)) => {
+            Diagnostics::new(ColorChoice::Auto.use_color()).emit_error(&LoxError::from(errors), &contents);
+            return 65;
         },
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+
+    if !write {
+        for edit in &edits {
+            println!("{file}:{}:{}: {old} -> {}", edit.line, edit.column, edit.text);
+        }
+    } else if let Err(e) = fs::write(file, rename::apply(&contents, &edits)) {
+        eprintln!("Could not write '{file}': {e}");
+        return 73;
+    }
+
+    0
+}
+
+/// Prints the explanation of an error code like `E1004`, or a lint's `W` code.
+fn explain_code(args: &[String]) -> i32 {
+    let code = match args {
+        [code] => code,
         _ => {
-            println!("Usage: jlox [script]");
+            eprintln!("{USAGE}");
+            return 64;
+        }
+    };
+
+    match codes::explain(code) {
+        Some(explanation) => {
+            print!("{explanation}");
+            0
+        },
+        None => {
+            eprintln!("Unknown error code '{code}'.");
+            1
+        }
+    }
+}
+
+/// Runs the book's test suite from `test directory` and reports how much of it passes.
+/// The exit code is 1 if any applicable test failed.
+fn check_conformance(args: &[String]) -> i32 {
+    let mut chapter = Chapter::ControlFlow;
+    let mut directories: Vec<&String> = Vec::new();
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--chapter=") {
+            chapter = match value.parse().ok().and_then(Chapter::from_number) {
+                Some(chapter) => chapter,
+                None => {
+                    eprintln!("Invalid value '{value}' for --chapter, which can be 8 or 9.");
+                    return 64;
+                }
+            };
+        } else {
+            directories.push(arg);
+        }
+    }
+    let directory = match directories[..] {
+        [directory] => Path::new(directory),
+        _ => {
+            eprintln!("{USAGE}");
+            return 64;
+        }
+    };
+
+    let mut tests: Vec<PathBuf> = Vec::new();
+    if let Err(e) = find_tests(directory, &mut tests) {
+        eprintln!("Could not read '{}': {e}", directory.display());
+        return 66;
+    }
+    tests.sort();
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for test in &tests {
+        let name = test.strip_prefix(directory).unwrap_or(test).to_string_lossy().into_owned();
+        let source = match read_source(test) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Could not read '{name}': {e}");
+                return 66;
+            }
+        };
+
+        if !conformance::applies(&name, &source, chapter) {
+            skipped += 1;
+            continue;
+        }
+        match conformance::run(&source) {
+            Ok(()) => passed += 1,
+            Err(failures) => {
+                failed += 1;
+                println!("FAIL {name}");
+                for failure in failures {
+                    println!("    {failure}");
+                }
+            }
+        }
+    }
+
+    let total = passed + failed;
+    let percentage = if total == 0 { 100.0 } else { 100.0 * passed as f64 / total as f64 };
+    println!("Passed {passed} of {total} applicable tests ({percentage:.1}%), skipped {skipped}.");
+    if failed == 0 { 0 } else { 1 }
+}
+
+fn find_tests(directory: &Path, tests: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_tests(&path, tests)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            tests.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args == ["dap"] {
+        dap::serve(Box::new(io::BufReader::new(io::stdin())), Box::new(io::stdout()));
+        return;
+    }
+    if args.first().is_some_and(|arg| arg == "fmt") {
+        exit(format_files(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "doc") {
+        exit(document_file(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "callgraph") {
+        exit(print_call_graph(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "diff") {
+        exit(diff_files(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "metrics") {
+        exit(print_metrics(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "rename") {
+        exit(rename_variable(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "conformance") {
+        exit(check_conformance(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "--explain") {
+        exit(explain_code(&args[1..]));
+    }
+
+    let linting = args.first().is_some_and(|arg| arg == "lint");
+    let options = parse_args(if linting { &args[1..] } else { &args }).and_then(|options| {
+        if !linting && options.files.len() > 1 {
+            Err(String::from("Only one script can be run at a time."))
+        } else if options.stats && (linting || options.files.is_empty()) {
+            Err(String::from("--stats only applies to running a script."))
+        } else if options.leak_check && (linting || options.files.is_empty()) {
+            Err(String::from("--leak-check only applies to running a script."))
+        } else if options.heap_snapshot.is_some() && (linting || options.files.is_empty()) {
+            Err(String::from("--heap-snapshot-on-exit only applies to running a script."))
+        } else if options.max_call_depth.is_some() && (linting || options.files.is_empty()) {
+            Err(String::from("--max-call-depth only applies to running a script."))
+        } else if options.max_depth.is_some() && (linting || options.files.is_empty()) {
+            Err(String::from("--max-depth only applies to running a script."))
+        } else if options.seed.is_some() && (linting || options.files.is_empty()) {
+            Err(String::from("--seed only applies to running a script."))
+        } else if options.record.is_some() && (linting || options.files.is_empty()) {
+            Err(String::from("--record only applies to running a script."))
+        } else if options.replay.is_some() && (linting || options.files.is_empty()) {
+            Err(String::from("--replay only applies to running a script."))
+        } else if options.seed.is_some() && options.replay.is_some() {
+            Err(String::from("--seed cannot be combined with --replay, which uses the recorded seed."))
+        } else if options.print_function && (linting || options.files.is_empty()) {
+            Err(String::from("--print-function only applies to running a script."))
+        } else if options.jlox && (linting || options.files.is_empty()) {
+            Err(String::from("--compat only applies to running a script."))
+        } else if options.jit_requested() && (linting || options.files.is_empty()) {
+            Err(String::from("--jit only applies to running a script."))
+        } else {
+            Ok(options)
+        }
+    });
+    let options = match options {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!("{USAGE}");
             exit(64);
         }
+    };
+    init_logging(options.verbosity);
+    if linting {
+        exit(lint_files(&options));
+    }
+    let diagnostics = diagnostics_for(&options);
+
+    match options.files.first() {
+        None => repl::run_prompt(diagnostics, !options.no_init, options.trace),
+        Some(script) => {
+            let contents = match read_source(script) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    eprintln!("Could not read '{script}': {e}");
+                    exit(66);
+                }
+            };
+            let replay = options.replay.as_ref().map(|path| {
+                match fs::File::open(path).and_then(|file| Recording::read(io::BufReader::new(file))) {
+                    Ok(recording) => recording,
+                    Err(e) => {
+                        eprintln!("Could not read the recording '{path}': {e}");
+                        exit(66);
+                    }
+                }
+            });
+            match run_file(script, &contents, &diagnostics, &options, replay.as_ref()) {
+                Ok(_) => {},
+                Err(LoxError::Compile(_)) => { exit(65); },
+                Err(LoxError::Runtime(_)) => { exit(70); }
+            }
+        },
     }
 }
@@ -0,0 +1,314 @@
+//! Size and complexity measures for each function of a script, as printed by `jlox
+//! metrics`. The script's top level counts as a function too.
+//!
+//! Blocks aren't counted as statements, only what is in them is. A `for` loop's
+//! initializer is counted like any other statement.
+//!
+//! What a function contains stops at the functions nested in it, which are measured on
+//! their own. Nesting depth counts the blocks and `if`, `while` and `for` bodies around a
+//! statement, with a block that is such a body counted once. Cyclomatic complexity is one
+//! more than the number of places the function branches: each `if`, `while`, `for`,
+//! `and` and `or`.
+
+use crate::error::CompileError;
+use crate::parser;
+use crate::scanner::{Token, TokenType};
+use crate::stack::SCRIPT;
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+use serde_json::{json, Value as Json};
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionMetrics {
+    /// The variable the function is declared as or assigned to, or `lambda` like at run
+    /// time when there is none.
+    pub name: String,
+    /// The line of the `=>`, or 0 for the script.
+    pub line: i32,
+    pub parameters: usize,
+    pub statements: usize,
+    pub max_depth: usize,
+    pub complexity: usize,
+}
+
+/// The metrics of the script and then of each function, in source order.
+pub fn measure(source: &str) -> Result<Vec<FunctionMetrics>, Vec<CompileError>> {
+    let ast = parser::parse_source(source)?;
+    let mut builder = Builder { ast: &ast, functions: Vec::new(), current: 0, depth: 0 };
+    builder.functions.push(FunctionMetrics { name: SCRIPT.to_string(), line: 0, parameters: 0, statements: 0, max_depth: 0, complexity: 1 });
+    for &statement in &ast.statements {
+        builder.stmt(statement);
+    }
+    Ok(builder.functions)
+}
+
+/// The metrics as a table with a header row and aligned columns.
+pub fn to_table(functions: &[FunctionMetrics]) -> String {
+    let header = ["function", "line", "params", "statements", "depth", "complexity"];
+    let rows: Vec<[String; 6]> = functions.iter()
+        .map(|function| [
+            function.name.clone(),
+            function.line.to_string(),
+            function.parameters.to_string(),
+            function.statements.to_string(),
+            function.max_depth.to_string(),
+            function.complexity.to_string(),
+        ])
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    let header = header.map(String::from);
+    for row in std::iter::once(&header).chain(&rows) {
+        // Names to the left, numbers to the right.
+        let _ = write!(table, "{:<width$}", row[0], width = widths[0]);
+        for (cell, width) in row.iter().zip(widths).skip(1) {
+            let _ = write!(table, "  {cell:>width$}");
+        }
+        table.push('\n');
+    }
+    table
+}
+
+/// The metrics as a JSON array with an object per function.
+pub fn to_json(functions: &[FunctionMetrics]) -> Json {
+    functions.iter()
+        .map(|function| json!({
+            "name": function.name,
+            "line": function.line,
+            "parameters": function.parameters,
+            "statements": function.statements,
+            "max_depth": function.max_depth,
+            "complexity": function.complexity,
+        }))
+        .collect()
+}
+
+struct Builder<'a> {
+    ast: &'a Ast,
+    functions: Vec<FunctionMetrics>,
+    // The function being measured and how deeply nested the walk is inside it.
+    current: usize,
+    depth: usize,
+}
+
+impl Builder<'_> {
+    fn function(&mut self) -> &mut FunctionMetrics {
+        &mut self.functions[self.current]
+    }
+
+    fn branch(&mut self) {
+        self.function().complexity += 1;
+    }
+
+    // A statement or block nested one level deeper than the current one.
+    fn nested(&mut self, visit: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        let depth = self.depth;
+        let function = self.function();
+        function.max_depth = function.max_depth.max(depth);
+        visit(self);
+        self.depth -= 1;
+    }
+
+    // The body of an `if`, `while` or `for`, which is one level deeper even when it is a block.
+    fn body(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        self.nested(|builder| match &ast[stmt] {
+            Stmt::Block { statements } => {
+                for &statement in statements {
+                    builder.stmt(statement);
+                }
+            },
+            _ => builder.stmt(stmt),
+        });
+    }
+
+    fn expr_body(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        self.nested(|builder| match &ast[expr] {
+            Expr::Block { statements, value } => builder.block(statements, *value),
+            _ => builder.expr(expr),
+        });
+    }
+
+    fn block(&mut self, statements: &[StmtId], value: Option<ExprId>) {
+        for &statement in statements {
+            self.stmt(statement);
+        }
+        if let Some(value) = value {
+            self.expr(value);
+        }
+    }
+
+    fn stmt(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        // A block only groups the statements in it.
+        if !matches!(ast[stmt], Stmt::Block { .. }) {
+            self.function().statements += 1;
+        }
+        match &ast[stmt] {
+            Stmt::Block { statements } => self.nested(|builder| {
+                for &statement in statements {
+                    builder.stmt(statement);
+                }
+            }),
+            Stmt::Expression { expression } | Stmt::Print { keyword: _, expression } => self.expr(*expression),
+            Stmt::If { keyword: _, condition, then_branch, else_branch } => {
+                self.branch();
+                self.expr(*condition);
+                self.body(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.body(*else_branch);
+                }
+            },
+            Stmt::Variable { name, initializer: Some(initializer) } => self.value(name, *initializer),
+            Stmt::While { keyword: _, condition, body } => {
+                self.branch();
+                self.expr(*condition);
+                self.body(*body);
+            },
+            Stmt::For { keyword: _, initializer, condition, increment, body } => {
+                self.branch();
+                if let Some(initializer) = initializer {
+                    self.stmt(*initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expr(*condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(*increment);
+                }
+                self.body(*body);
+            },
+            Stmt::Variable { name: _, initializer: None } | Stmt::Import { keyword: _, module: _ } | Stmt::Break {}
+            | Stmt::Debugger { keyword: _ } | Stmt::Error { tokens: _ } => {},
+        }
+    }
+
+    // A value stored in the variable `name`, which names it if it is a function.
+    fn value(&mut self, name: &Token, value: ExprId) {
+        match &self.ast[value] {
+            Expr::Lambda { params, arrow, body } => self.lambda(&name.lexeme, params.len(), arrow, *body),
+            _ => self.expr(value),
+        }
+    }
+
+    fn lambda(&mut self, name: &str, parameters: usize, arrow: &Token, body: ExprId) {
+        self.functions.push(FunctionMetrics {
+            name: name.to_string(),
+            line: arrow.line,
+            parameters,
+            statements: 0,
+            max_depth: 0,
+            complexity: 1,
+        });
+        let current = std::mem::replace(&mut self.current, self.functions.len() - 1);
+        let depth = std::mem::take(&mut self.depth);
+
+        // The function's own block is its top level, not a level of nesting.
+        match &self.ast[body] {
+            Expr::Block { statements, value } => self.block(statements, *value),
+            _ => self.expr(body),
+        }
+
+        self.current = current;
+        self.depth = depth;
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        match &ast[expr] {
+            Expr::Assign { name, value } => self.value(name, *value),
+            Expr::Binary { .. } | Expr::Logical { .. } => {
+                let (first, links) = ast.chain(expr);
+                self.expr(first);
+                for (operator, operand) in links {
+                    // Each `and` and `or` can skip its right operand.
+                    if matches!(operator.token_type, TokenType::And | TokenType::Or) {
+                        self.branch();
+                    }
+                    self.expr(operand);
+                }
+            },
+            Expr::Call { callee, paren: _, arguments } => {
+                self.expr(*callee);
+                for &argument in arguments {
+                    self.expr(argument);
+                }
+            },
+            Expr::Grouping { expression } | Expr::Unary { operator: _, right: expression } => self.expr(*expression),
+            Expr::Block { statements, value } => self.nested(|builder| builder.block(statements, *value)),
+            Expr::If { keyword: _, condition, then_branch, else_branch } => {
+                self.branch();
+                self.expr(*condition);
+                self.expr_body(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expr_body(*else_branch);
+                }
+            },
+            Expr::Lambda { params, arrow, body } => self.lambda("lambda", params.len(), arrow, *body),
+            Expr::Literal { value: _ } | Expr::Variable { name: _ } | Expr::Error { token: _ } => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+var count = (items, n) => {
+    var total = 0;
+    for (var i = 0; i < n and items; i = i + 1) {
+        if (i > 2) {
+            total = total + 1;
+        } else {
+            print i;
+        }
+    }
+    total
+};
+var twice = (f) => (x) => f(f(x));
+{
+    print count(1, 5) + twice((x) => x + 1)(0);
+}
+";
+
+    fn summary(functions: &[FunctionMetrics]) -> Vec<(&str, i32, usize, usize, usize, usize)> {
+        functions.iter()
+            .map(|function| (&*function.name, function.line, function.parameters, function.statements, function.max_depth, function.complexity))
+            .collect()
+    }
+
+    #[test]
+    fn metrics() {
+        let functions = measure(SOURCE).unwrap();
+        assert_eq!(summary(&functions), vec![
+            (SCRIPT, 0, 0, 3, 1, 1),
+            ("count", 1, 2, 6, 2, 4),
+            ("twice", 12, 1, 0, 0, 1),
+            ("lambda", 12, 1, 0, 0, 1),
+            ("lambda", 14, 1, 0, 0, 1),
+        ]);
+    }
+
+    #[test]
+    fn formats() {
+        let functions = measure("var f = (a) => if (a) { 1 } else { 2 };\nprint f(true);").unwrap();
+        assert_eq!(to_table(&functions), "\
+function  line  params  statements  depth  complexity
+<script>     0       0           2      0           1
+f            1       1           0      1           2
+");
+        assert_eq!(to_json(&functions)[1], json!({ "name": "f", "line": 1, "parameters": 1, "statements": 0, "max_depth": 1, "complexity": 2 }));
+    }
+}
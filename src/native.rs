@@ -0,0 +1,389 @@
+//! Functions written in Rust that scripts can call, bundled into modules that a script
+//! loads with `import native "name";`. Hosts register their own modules with
+//! `Interpreter::register_module`.
+
+use crate::interpreter::{Interpreter, Value};
+use crate::shared::{Lock, Shared};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A Rust function callable from Lox. It gets exactly `arity` arguments and reports
+/// failures as a message, which becomes a runtime error at the call.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeBody,
+}
+
+/// The Rust side of a native function.
+#[derive(Clone, Copy)]
+pub enum NativeBody {
+    /// Works from its arguments alone.
+    Pure(fn(&[Value]) -> Result<Value, String>),
+    /// Also writes to the interpreter's output, where `print` goes.
+    Output(fn(&mut dyn Write, &[Value]) -> Result<Value, String>),
+    /// Also writes to the interpreter's error output, which is stderr by default.
+    ErrorOutput(fn(&mut dyn Write, &[Value]) -> Result<Value, String>),
+    /// Also draws from the interpreter's random number generator.
+    Random(fn(&mut Rng, &[Value]) -> Result<Value, String>),
+    /// Also reads the interpreter's clock.
+    Clock(fn(&mut Clock, &[Value]) -> Result<Value, String>),
+    /// Also looks at the interpreter's state, like which scopes are alive.
+    Interpreter(fn(&Interpreter, &[Value]) -> Result<Value, String>),
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, arity: usize, function: fn(&[Value]) -> Result<Value, String>) -> Self {
+        Self { name: name.to_string(), arity, function: NativeBody::Pure(function) }
+    }
+
+    /// A native function that writes to the interpreter's output.
+    pub fn with_output(name: &str, arity: usize, function: fn(&mut dyn Write, &[Value]) -> Result<Value, String>) -> Self {
+        Self { name: name.to_string(), arity, function: NativeBody::Output(function) }
+    }
+
+    /// A native function that writes to the interpreter's error output.
+    pub fn with_error_output(name: &str, arity: usize, function: fn(&mut dyn Write, &[Value]) -> Result<Value, String>) -> Self {
+        Self { name: name.to_string(), arity, function: NativeBody::ErrorOutput(function) }
+    }
+
+    /// A native function that draws from the interpreter's random number generator.
+    pub fn with_rng(name: &str, arity: usize, function: fn(&mut Rng, &[Value]) -> Result<Value, String>) -> Self {
+        Self { name: name.to_string(), arity, function: NativeBody::Random(function) }
+    }
+
+    /// A native function that reads the interpreter's clock.
+    pub fn with_clock(name: &str, arity: usize, function: fn(&mut Clock, &[Value]) -> Result<Value, String>) -> Self {
+        Self { name: name.to_string(), arity, function: NativeBody::Clock(function) }
+    }
+
+    /// A native function that looks at the interpreter's state.
+    pub fn with_interpreter(name: &str, arity: usize, function: fn(&Interpreter, &[Value]) -> Result<Value, String>) -> Self {
+        Self { name: name.to_string(), arity, function: NativeBody::Interpreter(function) }
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        let same_body = match (self.function, other.function) {
+            (NativeBody::Pure(left), NativeBody::Pure(right)) => std::ptr::fn_addr_eq(left, right),
+            (NativeBody::Output(left), NativeBody::Output(right)) => std::ptr::fn_addr_eq(left, right),
+            (NativeBody::ErrorOutput(left), NativeBody::ErrorOutput(right)) => std::ptr::fn_addr_eq(left, right),
+            (NativeBody::Random(left), NativeBody::Random(right)) => std::ptr::fn_addr_eq(left, right),
+            (NativeBody::Clock(left), NativeBody::Clock(right)) => std::ptr::fn_addr_eq(left, right),
+            (NativeBody::Interpreter(left), NativeBody::Interpreter(right)) => std::ptr::fn_addr_eq(left, right),
+            _ => false,
+        };
+        self.name == other.name && self.arity == other.arity && same_body
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFunction({}/{})", self.name, self.arity)
+    }
+}
+
+/// A bundle of native functions, such as bindings to a database library. Importing the
+/// module defines each of its functions as a variable in the importing scope.
+pub trait NativeModule: Send + Sync {
+    fn name(&self) -> &str;
+    fn functions(&self) -> Vec<NativeFunction>;
+}
+
+/// The modules every interpreter can import, as `Interpreter::new` registers them. Hosts
+/// that want scripts to start threads or write heap snapshots register `ThreadsModule` and
+/// `DebugModule` themselves.
+pub fn builtin_modules() -> Vec<Box<dyn NativeModule>> {
+    vec![Box::new(TimeModule), Box::new(StringsModule), Box::new(IoModule), Box::new(RandomModule)]
+}
+
+/// `import native "time";` gives `clock()`, the seconds since the Unix epoch.
+pub struct TimeModule;
+
+impl NativeModule for TimeModule {
+    fn name(&self) -> &str {
+        "time"
+    }
+
+    fn functions(&self) -> Vec<NativeFunction> {
+        vec![NativeFunction::with_clock("clock", 0, clock)]
+    }
+}
+
+fn clock(clock: &mut Clock, _arguments: &[Value]) -> Result<Value, String> {
+    clock.now().map(Value::Number)
+}
+
+/// The time as the interpreter's natives see it. It can note down every reading, or hand
+/// out the readings of an earlier run instead, so that run can be replayed exactly.
+#[derive(Debug, Default)]
+pub struct Clock {
+    // Every reading so far, while recording.
+    recorded: Option<Vec<f64>>,
+    // Readings still to hand out, while replaying.
+    replaying: Option<VecDeque<f64>>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The seconds since the Unix epoch.
+    pub fn now(&mut self) -> Result<f64, String> {
+        let now = match &mut self.replaying {
+            Some(readings) => readings.pop_front().ok_or_else(|| String::from("The replayed run read the clock fewer times."))?,
+            None => match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(elapsed) => elapsed.as_secs_f64(),
+                Err(e) => { return Err(format!("System clock is before the Unix epoch: {e}.")); }
+            },
+        };
+
+        if let Some(recorded) = &mut self.recorded {
+            recorded.push(now);
+        }
+        Ok(now)
+    }
+
+    pub fn record(&mut self) {
+        self.recorded = Some(Vec::new());
+    }
+
+    /// The readings since `record` was called.
+    pub fn recorded(&self) -> Vec<f64> {
+        self.recorded.clone().unwrap_or_default()
+    }
+
+    pub fn replay(&mut self, readings: Vec<f64>) {
+        self.replaying = Some(readings.into());
+    }
+}
+
+/// `print(value)` and `println(value)`, which stand in for the `print` statement in the
+/// `print_function` dialect.
+pub fn print_functions() -> Vec<NativeFunction> {
+    vec![NativeFunction::with_output("print", 1, print), NativeFunction::with_output("println", 1, println)]
+}
+
+fn print(output: &mut dyn Write, arguments: &[Value]) -> Result<Value, String> {
+    write!(output, "{}", arguments[0]).map_err(|e| format!("Could not print: {e}."))?;
+    Ok(Value::Nil)
+}
+
+fn println(output: &mut dyn Write, arguments: &[Value]) -> Result<Value, String> {
+    writeln!(output, "{}", arguments[0]).map_err(|e| format!("Could not print: {e}."))?;
+    Ok(Value::Nil)
+}
+
+/// `import native "io";` gives `eprint(value)` and `eprintln(value)`, which write to the
+/// interpreter's error output so diagnostics stay out of what the program prints.
+pub struct IoModule;
+
+impl NativeModule for IoModule {
+    fn name(&self) -> &str {
+        "io"
+    }
+
+    fn functions(&self) -> Vec<NativeFunction> {
+        vec![NativeFunction::with_error_output("eprint", 1, print), NativeFunction::with_error_output("eprintln", 1, println)]
+    }
+}
+
+/// The random number generator an interpreter owns, so that seeding it with
+/// `Interpreter::set_seed` makes every random native repeat the same numbers. It is
+/// SplitMix64: fast and good enough for simulations, but not for anything secret.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Seeded from the clock, so every run draws different numbers.
+    pub fn from_clock() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to but not including 1.
+    pub fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill a double's mantissa exactly.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// `import native "random";` gives `random()`, a number from 0 up to but not including 1,
+/// and `randomInt(low, high)`, a whole number from `low` up to but not including `high`.
+pub struct RandomModule;
+
+impl NativeModule for RandomModule {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn functions(&self) -> Vec<NativeFunction> {
+        vec![NativeFunction::with_rng("random", 0, random), NativeFunction::with_rng("randomInt", 2, random_int)]
+    }
+}
+
+fn random(rng: &mut Rng, _arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(rng.next_f64()))
+}
+
+fn random_int(rng: &mut Rng, arguments: &[Value]) -> Result<Value, String> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::Number(low), Value::Number(high)) if low.fract() == 0.0 && high.fract() == 0.0 && low < high => {
+            Ok(Value::Number(low + (rng.next_f64() * (high - low)).floor()))
+        },
+        _ => Err(String::from("randomInt expects two whole numbers, the first smaller than the second.")),
+    }
+}
+
+/// `import native "debug";` gives `dumpHeap(path)`, which writes a snapshot of what the
+/// program keeps alive to the file at `path`, as described in `heap`. It can write any file
+/// the process may, so it isn't built in: hosts like `jlox` register it.
+pub struct DebugModule;
+
+impl NativeModule for DebugModule {
+    fn name(&self) -> &str {
+        "debug"
+    }
+
+    fn functions(&self) -> Vec<NativeFunction> {
+        vec![NativeFunction::with_interpreter("dumpHeap", 1, dump_heap)]
+    }
+}
+
+fn dump_heap(interpreter: &Interpreter, arguments: &[Value]) -> Result<Value, String> {
+    let Value::Str(path) = &arguments[0] else {
+        return Err(String::from("dumpHeap expects the path of the file to write."));
+    };
+    File::create(&**path)
+        .and_then(|file| interpreter.write_heap_snapshot(BufWriter::new(file)))
+        .map_err(|e| format!("Could not write the heap snapshot to '{path}': {e}."))?;
+    Ok(Value::Nil)
+}
+
+/// Text that grows in place. Joining strings with `+` copies both sides every time, so
+/// building a long string that way in a loop takes quadratic time; appending to a builder
+/// doesn't.
+#[derive(Debug, Default)]
+pub struct StringBuilder {
+    text: Lock<String>,
+}
+
+impl StringBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn append(&self, text: &str) {
+        self.text.borrow_mut().push_str(text);
+    }
+
+    /// A copy of the text so far.
+    pub fn text(&self) -> String {
+        self.text.borrow().clone()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.text.borrow().capacity()
+    }
+}
+
+// Builders are mutable, so two of them are only equal if they are the same builder.
+impl PartialEq for StringBuilder {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// `import native "strings";` gives `StringBuilder()`, `append(builder, value)`, which
+/// adds `value` as `print` would show it, and `toString(builder)`. `toFixed(number, digits)`
+/// writes a number with exactly `digits` digits after the point, from 0 to 100. Like
+/// `printf`, it rounds an exact tie to the even digit, so `toFixed(2.5, 0)` is "2".
+pub struct StringsModule;
+
+impl NativeModule for StringsModule {
+    fn name(&self) -> &str {
+        "strings"
+    }
+
+    fn functions(&self) -> Vec<NativeFunction> {
+        vec![
+            NativeFunction::new("StringBuilder", 0, string_builder),
+            NativeFunction::with_interpreter("append", 2, append),
+            NativeFunction::new("toString", 1, to_string),
+            NativeFunction::new("toFixed", 2, to_fixed),
+        ]
+    }
+}
+
+fn string_builder(_arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::Builder(Shared::new(StringBuilder::new())))
+}
+
+// The builder grows where it is, so it checks the memory limit itself.
+fn append(interpreter: &Interpreter, arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::Builder(builder) => {
+            let text = arguments[1].to_string();
+            if interpreter.over_memory_limit(text.len()) {
+                return Err(String::from("Memory limit exceeded."));
+            }
+            // The builder grows in place, so the interpreter counts the bytes here rather
+            // than when a variable is assigned.
+            let capacity = builder.capacity();
+            builder.append(&text);
+            interpreter.track_memory(None, builder.capacity() - capacity);
+            Ok(Value::Nil)
+        },
+        _ => Err(String::from("append expects a string builder.")),
+    }
+}
+
+fn to_string(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::Builder(builder) => Ok(Value::from(builder.text())),
+        _ => Err(String::from("toString expects a string builder.")),
+    }
+}
+
+fn to_fixed(arguments: &[Value]) -> Result<Value, String> {
+    let number = match arguments[0] {
+        Value::Number(number) => number,
+        _ => { return Err(String::from("toFixed expects a number.")); }
+    };
+    let digits = match arguments[1] {
+        Value::Number(digits) if digits.fract() == 0.0 && (0.0..=100.0).contains(&digits) => digits as usize,
+        _ => { return Err(String::from("toFixed expects a whole number of digits from 0 to 100.")); }
+    };
+
+    if number.is_finite() {
+        Ok(Value::from(format!("{number:.digits$}")))
+    } else {
+        Ok(Value::from(arguments[0].to_string()))
+    }
+}
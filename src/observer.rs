@@ -0,0 +1,146 @@
+//! Callbacks that let a host watch the interpreter run, for tracers, debuggers and
+//! instrumentation. Register one with `Interpreter::add_observer`.
+
+use crate::ast_printer;
+use crate::environment::Environment;
+use crate::interpreter::Value;
+use crate::shared::Output;
+use crate::syntax::{Ast, Stmt, StmtId};
+
+use std::io::{self, Write};
+
+/// Every method does nothing by default, so an observer only implements what it needs.
+/// `line` is `None` when the statement holds no tokens to take a line from, like `1;`.
+pub trait Observer {
+    fn before_statement(&mut self, _ast: &Ast, _stmt: StmtId, _line: Option<i32>, _environment: &Environment) {}
+    fn after_statement(&mut self, _ast: &Ast, _stmt: StmtId, _line: Option<i32>, _environment: &Environment) {}
+    fn enter_function(&mut self, _name: &str, _arguments: &[Value], _line: i32, _environment: &Environment) {}
+    /// `result` is `None` when the call failed.
+    fn exit_function(&mut self, _name: &str, _result: Option<&Value>, _line: i32, _environment: &Environment) {}
+    /// Called after a variable is defined or assigned.
+    fn assign(&mut self, _name: &str, _value: &Value, _line: i32) {}
+    /// Called when a `debugger;` statement runs. Without observers the statement does nothing.
+    fn breakpoint(&mut self, _line: i32, _environment: &Environment) {}
+}
+
+// With the `sync` feature the interpreter is `Send`, so everything it owns has to be too.
+#[cfg(not(feature = "sync"))]
+pub(crate) type BoxedObserver = Box<dyn Observer>;
+#[cfg(feature = "sync")]
+pub(crate) type BoxedObserver = Box<dyn Observer + Send>;
+
+/// Logs every statement, assignment and call as it happens, for `jlox --trace`.
+pub struct Trace {
+    output: Output,
+}
+
+impl Trace {
+    pub fn new(output: Output) -> Self {
+        Self { output }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(Box::new(io::stderr()))
+    }
+
+    fn log(&mut self, line: Option<i32>, message: &str) {
+        let line = line.map_or(String::from("?"), |line| line.to_string());
+        // Tracing is best effort; a closed stderr shouldn't stop the program.
+        writeln!(self.output, "[line {line}] {message}").ok();
+    }
+}
+
+// Strings are quoted so they can be told apart from identifiers and numbers.
+fn show(value: &Value) -> String {
+    match value {
+        Value::Str(text) => format!("{text:?}"),
+        _ => value.to_string(),
+    }
+}
+
+impl Observer for Trace {
+    fn before_statement(&mut self, ast: &Ast, stmt: StmtId, line: Option<i32>, _environment: &Environment) {
+        // The statements inside a block are traced on their own.
+        if let Stmt::Block { statements: _ } = ast[stmt] {
+            return;
+        }
+
+        // Only the first line, so a loop isn't printed along with its whole body.
+        let printed = ast_printer::print_statements(ast, &[stmt]);
+        self.log(line, printed.lines().next().unwrap_or_default());
+    }
+
+    fn enter_function(&mut self, name: &str, arguments: &[Value], line: i32, _environment: &Environment) {
+        let arguments: Vec<String> = arguments.iter().map(show).collect();
+        self.log(Some(line), &format!("call {name}({})", arguments.join(", ")));
+    }
+
+    fn exit_function(&mut self, name: &str, result: Option<&Value>, line: i32, _environment: &Environment) {
+        match result {
+            Some(value) => self.log(Some(line), &format!("{name} returned {}", show(value))),
+            None => self.log(Some(line), &format!("{name} failed")),
+        }
+    }
+
+    fn assign(&mut self, name: &str, value: &Value, line: i32) {
+        self.log(Some(line), &format!("{name} = {}", show(value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::native::NativeFunction;
+    use crate::shared::{Lock, Shared};
+
+    struct Buffer(Shared<Lock<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace() {
+        let buffer = Shared::new(Lock::new(Vec::new()));
+        let mut interpreter = Interpreter::new(false);
+        interpreter.add_observer(Box::new(Trace::new(Box::new(Buffer(Shared::clone(&buffer))))));
+        let double = NativeFunction::new("double", 1, |arguments| Ok(Value::Number(arguments[0].as_number().unwrap() * 2.0)));
+        interpreter.set_global("double", Value::Native(Shared::new(double)));
+
+        interpreter.eval("var a = 1;\nvar b = \"x\";\nwhile (a < 2) {\n  a = double(a);\n}\nprint a;").unwrap();
+
+        let trace = String::from_utf8(buffer.borrow().clone()).unwrap();
+        assert_eq!(trace.lines().collect::<Vec<&str>>(), vec![
+            "[line 1] (var a 1)",
+            "[line 1] a = 1",
+            "[line 2] (var b \"x\")",
+            "[line 2] b = \"x\"",
+            "[line 3] (while (< a 2)",
+            "[line 4] (; (= a (call double a)))",
+            "[line 4] call double(1)",
+            "[line 4] double returned 2",
+            "[line 4] a = 2",
+            "[line 6] (print a)",
+        ]);
+    }
+
+    #[test]
+    fn trace_long_chain() {
+        let buffer = Shared::new(Lock::new(Vec::new()));
+        let mut interpreter = Interpreter::new(false);
+        interpreter.add_observer(Box::new(Trace::new(Box::new(Buffer(Shared::clone(&buffer))))));
+
+        interpreter.eval(&format!("print {};", vec!["1"; 20_000].join(" + "))).unwrap();
+
+        let trace = String::from_utf8(buffer.borrow().clone()).unwrap();
+        assert!(trace.starts_with(&format!("[line 1] (print {}1 1)", "(+ ".repeat(19_999))), "{}", &trace[..100]);
+        assert!(trace.trim_end().ends_with(" 1) 1) 1))"));
+    }
+}
@@ -1,63 +1,213 @@
-use crate::scanner::{Token, TokenType, Literal};
-use crate::syntax::{Expr, Stmt};
+use crate::codes::ErrorCode;
+use crate::error::{CompileError, ParseError, ScanError};
+use crate::scanner::{self, Dialect, Scanner, Token, TokenType, Literal};
+use crate::suggest;
+use crate::syntax::{Ast, Expr, ExprId, Span, Stmt, StmtId};
+
+/// Parses already scanned tokens, reporting every parse error.
+pub fn parse_tokens(tokens: Vec<Token>) -> Result<Ast, Vec<ParseError>> {
+    let (ast, errors) = Parser::new(tokens).parse_lossy();
+
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Scans and parses `source`, reporting every scan and parse error in source order.
+/// Parsing still runs when scanning fails so that both kinds of errors show up at once.
+pub fn parse_source(source: &str) -> Result<Ast, Vec<CompileError>> {
+    parse_source_in(source, Dialect::default())
+}
+
+/// Like `parse_source`, for a program written in `dialect`.
+pub fn parse_source_in(source: &str, dialect: Dialect) -> Result<Ast, Vec<CompileError>> {
+    parse_source_with_max_depth(source, dialect, DEFAULT_MAX_DEPTH)
+}
+
+/// Like `parse_source_in`, with `max_depth` in place of `DEFAULT_MAX_DEPTH`.
+pub fn parse_source_with_max_depth(source: &str, dialect: Dialect, max_depth: usize) -> Result<Ast, Vec<CompileError>> {
+    let (ast, errors) = parse_lossy_in(source, dialect, max_depth);
+
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Like `parse_source`, but always returns the syntax tree. Parts that failed to parse
+/// are replaced by `Stmt::Error` and `Expr::Error` nodes, so tools can still work with
+/// the rest of the file.
+pub fn parse_source_lossy(source: &str) -> (Ast, Vec<CompileError>) {
+    parse_lossy_in(source, Dialect::default(), DEFAULT_MAX_DEPTH)
+}
 
-pub fn parse_tokens(tokens: Vec<Token>) -> Result<Vec<Stmt>, ()> {
-    let mut parser_runner = Parser::new(tokens);
-    parser_runner.parse()
+fn parse_lossy_in(source: &str, dialect: Dialect, max_depth: usize) -> (Ast, Vec<CompileError>) {
+    let _parse = tracing::debug_span!("parse", bytes = source.len()).entered();
+    let mut parser = Parser::from_scanner(Scanner::new(source).with_dialect(dialect));
+    parser.set_max_depth(max_depth);
+    let (ast, parse_errors) = parser.parse_lossy();
+
+    let mut errors: Vec<CompileError> = parser.take_scan_errors().into_iter().map(CompileError::Scan).collect();
+    errors.extend(parse_errors.into_iter().map(CompileError::Parse));
+    errors.sort_by_key(|error| (error.line(), error.column()));
+
+    (ast, errors)
 }
 
+/// How deeply statements and expressions may nest before the parser gives up. Each level
+/// costs several stack frames in the parser, the interpreter and every tool that walks the
+/// tree, so this keeps pathological input like thousands of open parentheses from
+/// overflowing the stack. Chains of operators like `1 + 1 + ...` don't count, since
+/// everything walks them in a loop, with `Ast::chain`.
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
 pub struct Parser {
+    /// The tokens read so far. With a scanner as the source, that is up to the one being
+    /// parsed; otherwise it is all of them.
     pub tokens: Vec<Token>,
+    // Where more tokens come from, once `tokens` runs out.
+    scanner: Option<Scanner>,
+    // Entered while the scanner runs, which happens in between parsing.
+    scanning: tracing::Span,
+    scan_errors: Vec<ScanError>,
+    // The tree being built.
+    ast: Ast,
     current: usize,
     loop_count: usize,
+    depth: usize,
+    max_depth: usize,
+    // Errors the parser recovered from on the spot, without synchronizing.
+    errors: Vec<ParseError>,
+    dialect: Dialect,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        // The scanner always ends with EOF, but tokens built by hand might not, and the
+        // parser relies on it to know where to stop.
+        if tokens.last().is_none_or(|token| token.token_type != TokenType::EOF) {
+            let (line, end) = tokens.last().map_or((1, 0), |token| (token.line, token.span.end));
+            tokens.push(Token { token_type: TokenType::EOF, lexeme: "".into(), literal: None, line, column: 0, span: scanner::Span::new(end, end) });
+        }
+
+        Self::with_source(tokens, None)
+    }
+
+    /// A parser that pulls tokens from `scanner` as it needs them, instead of scanning
+    /// everything up front. Scan errors are set aside for `take_scan_errors`.
+    pub fn from_scanner(scanner: Scanner) -> Self {
+        let mut parser = Self::with_source(Vec::new(), Some(scanner));
+        parser.read_token();
+        parser
+    }
+
+    fn with_source(tokens: Vec<Token>, scanner: Option<Scanner>) -> Self {
         Self {
+            dialect: scanner.as_ref().map_or(Dialect::default(), Scanner::dialect),
             tokens,
+            scanning: if scanner.is_some() { tracing::debug_span!("scan") } else { tracing::Span::none() },
+            scanner,
+            scan_errors: Vec::new(),
+            ast: Ast::new(),
             current: 0,
             loop_count: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            errors: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ()> {
-        let mut is_error = false;
-        let mut statements: Vec<Stmt> = Vec::new();
+    /// The scan errors met so far, when parsing from a scanner.
+    pub fn take_scan_errors(&mut self) -> Vec<ScanError> {
+        std::mem::take(&mut self.scan_errors)
+    }
+
+    /// Overrides `DEFAULT_MAX_DEPTH` for this parser.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
 
+    pub fn parse_lossy(&mut self) -> (Ast, Vec<ParseError>) {
         while !self.is_at_end() {
-            match self.declaration() {
-                Ok(stmt) => statements.push(stmt),
-                Err(e) => {
-                    is_error = true;
-                    println!("{}", e);
+            let statement = self.declaration_or_error();
+            self.ast.statements.push(statement);
+        }
+
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|error| (error.token.line, error.token.column));
+        (std::mem::take(&mut self.ast), errors)
+    }
+
+    /// Parses the next top-level declaration, recovering from errors like `parse_lossy`,
+    /// for callers that want the statements one at a time. `None` at the end of the input.
+    pub(crate) fn parse_next(&mut self) -> Option<StmtId> {
+        if self.is_at_end() {
+            return None;
+        }
+        let statement = self.declaration_or_error();
+        self.ast.statements.push(statement);
+        Some(statement)
+    }
+
+    /// The parse errors since the last call, in the order they were found.
+    pub(crate) fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// The last token a statement was made of, and the furthest one the parser has looked
+    /// at, which can be well past it.
+    pub(crate) fn last_tokens(&self) -> (&Token, &Token) {
+        (self.previous(), self.tokens.last().expect("the parser always has a token"))
+    }
+
+    fn declaration_or_error(&mut self) -> StmtId {
+        let start = self.current;
+        let loop_count = self.loop_count;
+
+        match self.declaration() {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                let too_deep = e.code == ErrorCode::TooDeeplyNested;
+                self.errors.push(e);
+                self.loop_count = loop_count;
+                if too_deep {
+                    self.skip_nested();
+                }
+                // A brace left after skipping closes the enclosing block.
+                if !(too_deep && self.check(TokenType::RightBrace)) {
                     self.synchronize();
                 }
+                let tokens = self.tokens[start..self.current].to_vec();
+                let span = match (tokens.first(), tokens.last()) {
+                    (Some(first), Some(last)) => Span::between(first, last),
+                    _ => Span::between(self.peek(), self.peek()),
+                };
+                self.ast.add_stmt(Stmt::Error { tokens }, span)
             }
         }
-
-        if is_error {
-            Err(())
-        } else {
-            Ok(statements)
-        }
     }
 
-    fn declaration(&mut self) -> Result<Stmt, String> {
+    fn declaration(&mut self) -> Result<StmtId, ParseError> {
         if self.match_tokens(&[TokenType::Var]) {
             self.var_declaration()
+        } else if self.match_tokens(&[TokenType::Import]) {
+            self.import_declaration()
         } else {
             self.statement()
         }
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, String> {
+    fn var_declaration(&mut self) -> Result<StmtId, ParseError> {
+        let start = self.previous_span();
         let name = match self.consume(TokenType::Identifier) {
             Some(token) => token,
-            None => { return Err(self.generate_error("Expect variable name.")); }
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect variable name.")); }
         };
 
-        let mut initializer: Option<Expr> = None;
+        let mut initializer: Option<ExprId> = None;
         if self.match_tokens(&[TokenType::Equal]) {
             initializer = match self.expression() {
                 Ok(expr) => Some(expr),
@@ -67,13 +217,43 @@ impl Parser {
 
         match self.consume(TokenType::Semicolon) {
             Some(_token) => {},
-            None => { return Err(self.generate_error("Expect ';' after variable decleration.")); }
+            None => {
+                let message = if self.dialect.jlox { "Expect ';' after variable declaration." } else { "Expect ';' after variable decleration." };
+                return Err(self.generate_error(ErrorCode::ExpectedToken, message));
+            }
         }
 
-        Ok(Stmt::Variable { name, initializer })
+        Ok(self.add_stmt(Stmt::Variable { name, initializer }, start))
     }
 
-    fn statement(&mut self) -> Result<Stmt, String> {
+    fn import_declaration(&mut self) -> Result<StmtId, ParseError> {
+        let keyword = self.previous().clone();
+        let start = self.previous_span();
+
+        // 'native' is only special here, so it stays usable as a variable name.
+        if !(self.check(TokenType::Identifier) && &*self.peek().lexeme == "native") {
+            return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect 'native' after 'import'."));
+        }
+        self.advance();
+
+        let module = match self.consume(TokenType::String) {
+            Some(token) => token,
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect module name.")); }
+        };
+
+        match self.consume(TokenType::Semicolon) {
+            Some(_token) => {},
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' after import.")); }
+        }
+
+        Ok(self.add_stmt(Stmt::Import { keyword, module }, start))
+    }
+
+    fn statement(&mut self) -> Result<StmtId, ParseError> {
+        self.nested("Statement too deeply nested.", Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<StmtId, ParseError> {
         if self.match_tokens(&[TokenType::Print]) {
             self.print_statement()
         } else if self.match_tokens(&[TokenType::While]) {
@@ -82,59 +262,70 @@ impl Parser {
             self.for_statement()
         } else if self.match_tokens(&[TokenType::Break]) {
             self.break_statement()
+        } else if self.match_tokens(&[TokenType::Debugger]) {
+            self.debugger_statement()
         } else if self.match_tokens(&[TokenType::If]) {
             self.if_statement()
         } else if self.match_tokens(&[TokenType::LeftBrace]) {
+            let start = self.previous_span();
             let statements = match self.block() {
                 Ok(statements) => statements,
                 Err(e) => { return Err(e); }
             };
 
-            Ok(Stmt::Block { statements })
+            Ok(self.add_stmt(Stmt::Block { statements }, start))
         } else {
             self.expression_statement()
         }
     }
 
-    fn print_statement(&mut self) -> Result<Stmt, String> {
+    fn print_statement(&mut self) -> Result<StmtId, ParseError> {
+        let keyword = self.previous().clone();
+        let start = self.previous_span();
         let value = match self.expression() {
             Ok(expr) => expr,
             Err(e) => { return Err(e); }
         };
 
         match self.consume(TokenType::Semicolon) {
-            Some(_token) => Ok(Stmt::Print { expression: value }),
-            None => Err(self.generate_error("Expect ';' after value."))
+            Some(_token) => Ok(self.add_stmt(Stmt::Print { keyword, expression: value }, start)),
+            None => Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' after value."))
         }
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, String> {
+    fn while_statement(&mut self) -> Result<StmtId, ParseError> {
+        let keyword = self.previous().clone();
+        let start = self.previous_span();
+
         match self.consume(TokenType::LeftParen) {
             Some(_) => {},
-            None => { return Err(self.generate_error("Expect '(' after 'while'.")); }
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '(' after 'while'.")); }
         }
 
         let condition = self.expression()?;
 
         match self.consume(TokenType::RightParen) {
             Some(_) => {},
-            None => { return Err(self.generate_error("Expect ')' after condition.")); }
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ')' after condition.")); }
         }
 
         self.loop_count += 1;
         let body = self.statement()?;
         self.loop_count -= 1;
 
-        Ok(Stmt::While { condition, body: Box::new(body) })
+        Ok(self.add_stmt(Stmt::While { keyword, condition, body }, start))
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, String> {
+    fn for_statement(&mut self) -> Result<StmtId, ParseError> {
+        let keyword = self.previous().clone();
+        let start = self.previous_span();
+
         match self.consume(TokenType::LeftParen) {
             Some(_) => {},
-            None => { return Err(self.generate_error("Expect '(' after 'for'.")); }
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '(' after 'for'.")); }
         }
 
-        let mut initializer: Option<Stmt> = None;
+        let mut initializer: Option<StmtId> = None;
         if self.match_tokens(&[TokenType::Semicolon]) {
             // Do nothing, initializer is already None
         } else if self.match_tokens(&[TokenType::Var]) {
@@ -143,258 +334,290 @@ impl Parser {
             initializer = Some(self.expression_statement()?);
         }
 
-        let mut condition: Option<Expr> = None;
+        let mut condition: Option<ExprId> = None;
         if !self.check(TokenType::Semicolon) {
             condition = Some(self.expression()?);
         }
 
         match self.consume(TokenType::Semicolon) {
             Some(_) => {},
-            None => { return Err(self.generate_error("Expect ';' after loop condition.")); }
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' after loop condition.")); }
         }
 
-        let mut increment: Option<Expr> = None;
+        let mut increment: Option<ExprId> = None;
         if !self.check(TokenType::RightParen) {
             increment = Some(self.expression()?);
         }
 
         match self.consume(TokenType::RightParen) {
             Some(_) => {},
-            None => { return Err(self.generate_error("Expect ')' after for clauses.")); }
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ')' after for clauses.")); }
         }
 
         self.loop_count += 1;
-        let mut body = self.statement()?;
+        let body = self.statement()?;
         self.loop_count -= 1;
 
-        if increment.is_some() {
-            body = Stmt::Block { statements: vec![body, Stmt::Expression { expression: increment.unwrap() }] }
-        }
+        Ok(self.add_stmt(Stmt::For { keyword, initializer, condition, increment, body }, start))
+    }
 
-        if condition.is_none() {
-            condition = Some(Expr::Literal { value: Literal::Bool(true) });
+    fn break_statement(&mut self) -> Result<StmtId, ParseError> {
+        if !self.is_in_loop() {
+            return Err(ParseError::new(ErrorCode::BreakOutsideLoop, self.previous(), "'break' statement must be in a loop block."));
         }
-        body = Stmt::While { condition: condition.unwrap(), body: Box::new(body) };
+        let start = self.previous_span();
 
-        if initializer.is_some() {
-            body = Stmt::Block { statements: vec![initializer.unwrap(), body] };
+        match self.consume(TokenType::Semicolon) {
+            Some(_token) => Ok(self.add_stmt(Stmt::Break {}, start)),
+            None => Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' after 'break'."))
         }
-
-        Ok(body)
     }
 
-    fn break_statement(&mut self) -> Result<Stmt, String> {
-        if !self.is_in_loop() {
-            return Err(self.generate_error("'break' statement must be in a loop block."));
-        }
+    fn debugger_statement(&mut self) -> Result<StmtId, ParseError> {
+        let keyword = self.previous().clone();
+        let start = self.previous_span();
 
         match self.consume(TokenType::Semicolon) {
-            Some(_token) => Ok(Stmt::Break {}),
-            None => Err(self.generate_error("Expect ';' after 'break'."))
+            Some(_token) => Ok(self.add_stmt(Stmt::Debugger { keyword }, start)),
+            None => Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' after 'debugger'."))
         }
     }
 
-    fn if_statement(&mut self) -> Result<Stmt, String> {
+    fn if_statement(&mut self) -> Result<StmtId, ParseError> {
+        let keyword = self.previous().clone();
+        let start = self.previous_span();
+
         match self.consume(TokenType::LeftParen) {
             Some(_token) => {},
-            None => {return Err(self.generate_error("Expect '(' after 'if'.")); }
+            None => {return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '(' after 'if'.")); }
         }
 
         let condition = self.expression()?;
 
         match self.consume(TokenType::RightParen) {
             Some(_token) => {},
-            None => {return Err(self.generate_error("Expect ')' after if condition.")); }
+            None => {return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ')' after if condition.")); }
         }
 
         let then_branch = self.statement()?;
-        let mut else_branch: Option<Box<Stmt>> = None;
+        let mut else_branch: Option<StmtId> = None;
         if self.match_tokens(&[TokenType::Else]) {
             else_branch = match self.statement() {
-                Ok(statement) => Some(Box::new(statement)),
+                Ok(statement) => Some(statement),
                 Err(e) => { return Err(e); }
             }
         }
 
-        Ok(Stmt::If { condition, then_branch: Box::new(then_branch), else_branch })
+        Ok(self.add_stmt(Stmt::If { keyword, condition, then_branch, else_branch }, start))
     }
 
-    fn block(&mut self) -> Result<Vec<Stmt>, String> {
-        let mut statements: Vec<Stmt> = Vec::new();
+    fn block(&mut self) -> Result<Vec<StmtId>, ParseError> {
+        let mut statements: Vec<StmtId> = Vec::new();
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            let declaration = match self.declaration() {
-                Ok(declaration) => declaration,
-                Err(e) => { return Err(e); }
-            };
-
-            statements.push(declaration);
+            statements.push(self.declaration_or_error());
         }
 
         match self.consume(TokenType::RightBrace) {
             Some(_token) => Ok(statements),
-            None => Err(self.generate_error("Expect '}' after block."))
+            None => Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '}' after block."))
         }
     }
 
-    fn expression_statement(&mut self) -> Result<Stmt, String> {
+    fn expression_statement(&mut self) -> Result<StmtId, ParseError> {
+        let first = self.peek().clone();
+        let start = Span::between(&first, &first);
+
         let value = match self.expression() {
             Ok(expr) => expr,
-            Err(e) => { return Err(e); }
+            Err(e) if self.dialect.jlox => { return Err(e); },
+            Err(e) => { return Err(suggest_keyword(e, &first)); }
         };
 
         match self.consume(TokenType::Semicolon, ) {
-            Some(_token) => Ok(Stmt::Expression { expression: value }),
-            None => Err(self.generate_error("Expect ';' after value."))
+            Some(_token) => Ok(self.add_stmt(Stmt::Expression { expression: value }, start)),
+            None if self.dialect.jlox => Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' after expression.")),
+            None => Err(suggest_keyword(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' after value."), &first))
         }
     }
 
-    fn expression(&mut self) -> Result<Expr, String> {
-        self.assignment()
+    fn expression(&mut self) -> Result<ExprId, ParseError> {
+        self.nested("Expression too deeply nested.", |parser| parser.parse_precedence(Precedence::Assignment))
     }
 
-    fn assignment(&mut self) -> Result<Expr, String> {
-        let expr = self.or()?;
-
-        if self.match_tokens(&[TokenType::Equal]) {
-            let value = self.assignment()?;
-
-            match expr {
-                Expr::Variable { name } => {
-                    return Ok(Expr::Assign { name, value: Box::new(value) });
-                },
-                _ => { return Err(self.generate_error("Invalid assignment target.")); }
+    /// Parses an expression whose operators all bind at least as tightly as `precedence`.
+    /// The token at the start picks the prefix rule, and every following operator with a
+    /// high enough precedence hands the expression so far to its infix rule.
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<ExprId, ParseError> {
+        let token_type = self.peek().token_type;
+        let prefix = match rule(token_type).prefix {
+            // In the book's Lox, blocks and ifs are only statements.
+            Some(_) if self.dialect.jlox && matches!(token_type, TokenType::LeftBrace | TokenType::If) => None,
+            prefix => prefix,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix,
+            None => {
+                let message = if self.dialect.jlox { "Expect expression." } else { "Primary token not found." };
+                return Err(self.generate_error(ErrorCode::ExpectedExpression, message));
             }
-        }
+        };
+        self.advance();
+        let expr = prefix(self)?;
 
-        Ok(expr)
+        let depth = self.depth;
+        let result = self.parse_infix(precedence, expr);
+        self.depth = depth;
+        result
     }
 
-    fn or(&mut self) -> Result<Expr, String> {
-        let mut expr = self.and()?;
+    // Hands `expr` to every following operator that binds at least as tightly as
+    // `precedence`. Each one nests the expression so far a level deeper. Chains of binary
+    // and logical operators like `1 + 1 + ...` are walked in a loop by everything that
+    // reads the tree, so only other chains, like calls, count against the nesting limit.
+    fn parse_infix(&mut self, precedence: Precedence, mut expr: ExprId) -> Result<ExprId, ParseError> {
+        while precedence <= rule(self.peek().token_type).precedence {
+            if !rule(self.peek().token_type).precedence.is_chain() {
+                if self.depth >= self.max_depth {
+                    return Err(self.generate_error(ErrorCode::TooDeeplyNested, "Expression too deeply nested."));
+                }
+                self.depth += 1;
+            }
 
-        while self.match_tokens(&[TokenType::Or]) {
-            let operator = self.previous().clone();
-            let right = self.and()?;
-            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) }
+            let infix = match rule(self.advance().token_type).infix {
+                Some(infix) => infix,
+                None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect an operator.")); }
+            };
+            expr = infix(self, expr)?;
         }
 
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, String> {
-        let mut expr = self.equality()?;
-
-        while self.match_tokens(&[TokenType::And]) {
-            let operator = self.previous().clone();
-            let right = self.and()?;
-            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) }
-        }
+    fn literal(&mut self) -> Result<ExprId, ParseError> {
+        let value = match self.previous().token_type {
+            TokenType::False => Literal::Bool(false),
+            TokenType::True => Literal::Bool(true),
+            TokenType::Nil => Literal::Nil,
+            _ => match self.previous().literal.clone() {
+                Some(literal) => literal,
+                None => { return Err(ParseError::new(ErrorCode::LiteralWithoutValue, self.previous(), "Literal token has no value.")); }
+            },
+        };
 
-        Ok(expr)
+        Ok(self.add_expr(Expr::Literal { value }, self.previous_span()))
     }
 
-    fn equality(&mut self) -> Result<Expr, String> {
-        let mut expr = self.comparison()?;
+    fn variable(&mut self) -> Result<ExprId, ParseError> {
+        let name = self.previous().clone();
+        Ok(self.add_expr(Expr::Variable { name }, self.previous_span()))
+    }
 
-        while self.match_tokens(&[TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous().clone();
-            let right = self.comparison()?;
-            expr = Expr::Binary {
-                left: Box::from(expr),
-                operator,
-                right: Box::from(right),
-            };
+    fn grouping(&mut self) -> Result<ExprId, ParseError> {
+        let start = self.previous_span();
+        if self.at_arrow_parameters() {
+            return self.arrow_function(start);
         }
 
-        Ok(expr)
-    }
+        let expr = self.expression()?;
 
-    fn comparison(&mut self) -> Result<Expr, String> {
-        let mut expr = self.term()?;
+        match self.consume(TokenType::RightParen) {
+            Some(_token) => Ok(self.add_expr(Expr::Grouping { expression: expr }, start)),
+            None => { Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ')' after expression.")) }
+        }
+    }
 
-        while self.match_tokens(&[TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
-            let operator = self.previous().clone();
-            let right = self.term()?;
-            expr = Expr::Binary { 
-                left: Box::from(expr), 
-                operator,
-                right: Box::from(right),
-            };
+    // Whether the `(` just consumed opens the parameters of an arrow function rather than
+    // a grouping, which takes looking past the `)` for a `=>`.
+    fn at_arrow_parameters(&mut self) -> bool {
+        let mut distance = 0;
+        if self.peek_ahead(0).token_type == TokenType::Identifier {
+            distance = 1;
+            while self.peek_ahead(distance).token_type == TokenType::Comma && self.peek_ahead(distance + 1).token_type == TokenType::Identifier {
+                distance += 2;
+            }
         }
 
-        Ok(expr)
+        self.peek_ahead(distance).token_type == TokenType::RightParen && self.peek_ahead(distance + 1).token_type == TokenType::Arrow
     }
 
-    fn term(&mut self) -> Result<Expr, String> {
-        let mut expr = self.factor()?;
-
-        while self.match_tokens(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous().clone();
-            let right = self.factor()?;
-            expr = Expr::Binary { 
-                left: Box::from(expr), 
-                operator,
-                right: Box::from(right),
-            };
+    fn arrow_function(&mut self, start: Span) -> Result<ExprId, ParseError> {
+        let mut params: Vec<Token> = Vec::new();
+        while let Some(param) = self.consume(TokenType::Identifier) {
+            if params.len() >= 255 {
+                let error = ParseError::new(ErrorCode::TooManyArguments, &param, "Can't have more than 255 parameters.");
+                self.errors.push(error);
+            }
+            params.push(param);
+            if !self.match_tokens(&[TokenType::Comma]) {
+                break;
+            }
         }
 
-        Ok(expr)
+        // `at_arrow_parameters` already checked both.
+        self.consume(TokenType::RightParen);
+        let arrow = match self.consume(TokenType::Arrow) {
+            Some(token) => token,
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '=>' after parameters.")); }
+        };
+
+        // A `break` in the body would leave the function, not a loop around it.
+        let loop_count = std::mem::replace(&mut self.loop_count, 0);
+        let body = self.expression();
+        self.loop_count = loop_count;
+
+        let body = body?;
+        Ok(self.add_expr(Expr::Lambda { params, arrow, body }, start))
     }
 
-    fn factor(&mut self) -> Result<Expr, String> {
-        let mut expr = self.unary()?;
+    fn unary(&mut self) -> Result<ExprId, ParseError> {
+        let operator = self.previous().clone();
+        let start = self.previous_span();
+        let right = self.nested("Expression too deeply nested.", |parser| parser.parse_precedence(Precedence::Unary))?;
 
-        while self.match_tokens(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            expr = Expr::Binary { 
-                left: Box::from(expr), 
-                operator,
-                right: Box::from(right),
-            };
-        }
+        Ok(self.add_expr(Expr::Unary { operator, right }, start))
+    }
 
-        Ok(expr)
+    fn binary(&mut self, left: ExprId) -> Result<ExprId, ParseError> {
+        let operator = self.previous().clone();
+        let right = self.parse_precedence(rule(operator.token_type).precedence.next())?;
+
+        Ok(self.add_expr(Expr::Binary { left, operator, right }, self.ast.expr_span(left)))
     }
 
-    fn unary(&mut self) -> Result<Expr, String> {
-        if self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            return Ok(Expr::Unary { 
-                operator,
-                right: Box::from(right),
-            });
-        }
+    fn logical(&mut self, left: ExprId) -> Result<ExprId, ParseError> {
+        let operator = self.previous().clone();
+        let right = self.parse_precedence(rule(operator.token_type).precedence.next())?;
 
-        self.call()
+        Ok(self.add_expr(Expr::Logical { left, operator, right }, self.ast.expr_span(left)))
     }
 
-    fn call(&mut self) -> Result<Expr, String> {
-        let mut expr = self.primary()?;
+    fn assignment(&mut self, target: ExprId) -> Result<ExprId, ParseError> {
+        let equals = self.previous().clone();
+        // Assignment is right-associative, so the value is parsed at the same precedence.
+        let value = self.nested("Expression too deeply nested.", |parser| parser.parse_precedence(Precedence::Assignment))?;
 
-        loop {
-            if self.match_tokens(&[TokenType::LeftParen]) {
-                expr = self.finish_call(expr)?;
-            } else {
-                break;
-            }
+        match &self.ast[target] {
+            Expr::Variable { name } => {
+                let name = name.clone();
+                Ok(self.add_expr(Expr::Assign { name, value }, self.ast.expr_span(target)))
+            },
+            _ => { Err(ParseError::new(ErrorCode::InvalidAssignmentTarget, &equals, "Invalid assignment target.")) }
         }
-
-        Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, String> {
-        let mut arguments: Vec<Expr> = Vec::new();
+    fn finish_call(&mut self, callee: ExprId) -> Result<ExprId, ParseError> {
+        let mut arguments: Vec<ExprId> = Vec::new();
         if !self.check(TokenType::RightParen) {
-            if arguments.len() >= 255 {
-                return Err("Can't have more than 255 arguments.".to_string());
-            }
-
             loop {
+                if arguments.len() >= 255 {
+                    // Too many arguments doesn't confuse the parser, so report it and carry on.
+                    let error = self.generate_error(ErrorCode::TooManyArguments, "Can't have more than 255 arguments.");
+                    self.errors.push(error);
+                }
+
                 arguments.push(self.expression()?);
-                if self.match_tokens(&[TokenType::Comma]) {
+                if !self.match_tokens(&[TokenType::Comma]) {
                     break;
                 }
             }
@@ -402,33 +625,125 @@ impl Parser {
 
         let paren = match self.consume(TokenType::RightParen) {
             Some(token) => token,
-            None => { return Err("Expect ')' after arguments.".to_string()); }
+            None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ')' after arguments.")); }
         };
 
-        Ok(Expr::Call { callee: Box::from(callee), paren, arguments })
-    }
-
-    fn primary(&mut self) -> Result<Expr, String> {
-        if self.match_tokens(&[TokenType::False]) {
-            Ok(Expr::Literal { value: Literal::Bool(false) })
-        } else if self.match_tokens(&[TokenType::True]) {
-            Ok(Expr::Literal { value: Literal::Bool(true) })
-        } else if self.match_tokens(&[TokenType::Nil]) {
-            Ok(Expr::Literal { value: Literal::Nil })
-        } else if self.match_tokens(&[TokenType::Number, TokenType::String]) {
-            Ok(Expr::Literal { value: self.previous().clone().literal.unwrap() })
-        } else if self.match_tokens(&[TokenType::Identifier]) {
-            Ok(Expr::Variable { name: self.previous().clone() })
-        } else if self.match_tokens(&[TokenType::LeftParen]) {
-            let expr = self.expression()?;
-
-            match self.consume(TokenType::RightParen) {
-                Some(_token) => Ok(Expr::Grouping { expression: Box::new(expr) }),
-                None => { return Err(self.generate_error("Expect ')' after expression.")); }
+        Ok(self.add_expr(Expr::Call { callee, paren, arguments }, self.ast.expr_span(callee)))
+    }
+
+    fn block_expression(&mut self) -> Result<ExprId, ParseError> {
+        let start = self.previous_span();
+        // A `break` can't leave in the middle of an expression, so a loop around the block
+        // doesn't count.
+        let loop_count = std::mem::replace(&mut self.loop_count, 0);
+        let result = self.block_expression_body();
+        self.loop_count = loop_count;
+
+        let (statements, value) = result?;
+        Ok(self.add_expr(Expr::Block { statements, value }, start))
+    }
+
+    // The statements of a block expression, and the expression it ends with if there is one.
+    fn block_expression_body(&mut self) -> Result<(Vec<StmtId>, Option<ExprId>), ParseError> {
+        let mut statements: Vec<StmtId> = Vec::new();
+
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            // Blocks and ifs at the start are statements, as they are anywhere else.
+            let token_type = self.peek().token_type;
+            if rule(token_type).prefix.is_none() || matches!(token_type, TokenType::LeftBrace | TokenType::If) {
+                statements.push(self.declaration_or_error());
+                continue;
             }
-        } else {
-            Err(self.generate_error("Primary token not found."))
+
+            let start = Span::between(self.peek(), self.peek());
+            let expression = self.expression()?;
+            if self.match_tokens(&[TokenType::RightBrace]) {
+                return Ok((statements, Some(expression)));
+            }
+            match self.consume(TokenType::Semicolon) {
+                Some(_token) => statements.push(self.add_stmt(Stmt::Expression { expression }, start)),
+                None => { return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ';' or '}' after value.")); }
+            }
+        }
+
+        match self.consume(TokenType::RightBrace) {
+            Some(_token) => Ok((statements, None)),
+            None => Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '}' after block."))
+        }
+    }
+
+    fn if_expression(&mut self) -> Result<ExprId, ParseError> {
+        let keyword = self.previous().clone();
+        let start = self.previous_span();
+
+        match self.consume(TokenType::LeftParen) {
+            Some(_token) => {},
+            None => {return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '(' after 'if'.")); }
+        }
+
+        let condition = self.expression()?;
+
+        match self.consume(TokenType::RightParen) {
+            Some(_token) => {},
+            None => {return Err(self.generate_error(ErrorCode::ExpectedToken, "Expect ')' after if condition.")); }
+        }
+
+        let then_branch = self.if_branch()?;
+        let mut else_branch: Option<ExprId> = None;
+        if self.match_tokens(&[TokenType::Else]) {
+            else_branch = if self.match_tokens(&[TokenType::If]) {
+                Some(self.nested("Expression too deeply nested.", Self::if_expression)?)
+            } else {
+                Some(self.if_branch()?)
+            };
         }
+
+        Ok(self.add_expr(Expr::If { keyword, condition, then_branch, else_branch }, start))
+    }
+
+    fn if_branch(&mut self) -> Result<ExprId, ParseError> {
+        match self.consume(TokenType::LeftBrace) {
+            Some(_token) => self.block_expression(),
+            None => Err(self.generate_error(ErrorCode::ExpectedToken, "Expect '{' before the branch of an if expression.")),
+        }
+    }
+
+    /// Error production for a binary operator at the start of an expression, like `+ 3`.
+    /// The right operand is parsed with the operator's precedence and the missing left
+    /// operand becomes an error node, so parsing carries on past the mistake.
+    fn missing_left_operand(&mut self) -> Result<ExprId, ParseError> {
+        let operator = self.previous().clone();
+        self.errors.push(ParseError::new(ErrorCode::MissingLeftOperand, &operator, "Binary operator without left operand."));
+
+        let span = Span::between(&operator, &operator);
+        let left = self.ast.add_expr(Expr::Error { token: operator }, span);
+        self.binary(left)
+    }
+
+    // Adds a node that runs from the start of `start` to the token just parsed.
+    fn add_expr(&mut self, expr: Expr, start: Span) -> ExprId {
+        let span = start.to(self.previous());
+        self.ast.add_expr(expr, span)
+    }
+
+    fn add_stmt(&mut self, stmt: Stmt, start: Span) -> StmtId {
+        let span = start.to(self.previous());
+        self.ast.add_stmt(stmt, span)
+    }
+
+    fn previous_span(&self) -> Span {
+        Span::between(self.previous(), self.previous())
+    }
+
+    fn nested<T>(&mut self, message: &str, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(self.generate_error(ErrorCode::TooDeeplyNested, message));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
     fn match_tokens(&mut self, token_types: &[TokenType]) -> bool {
@@ -446,16 +761,39 @@ impl Parser {
         if self.is_at_end() {
             return false;
         }
-        return self.peek().token_type == token_type;
+        self.peek().token_type == token_type
     }
 
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
+            if self.current == self.tokens.len() {
+                self.read_token();
+            }
         }
         self.previous()
     }
 
+    // Pulls the next token from the scanner. The scanner ends with EOF, which the parser
+    // never advances past, so there is always one more to read when this is called.
+    fn read_token(&mut self) {
+        let scanner = match &mut self.scanner {
+            Some(scanner) => scanner,
+            None => { return; }
+        };
+
+        let _scanning = self.scanning.enter();
+        for result in scanner.by_ref() {
+            match result {
+                Ok(token) => {
+                    self.tokens.push(token);
+                    return;
+                },
+                Err(error) => self.scan_errors.push(error),
+            }
+        }
+    }
+
     fn consume(&mut self, token_type: TokenType) -> Option<Token> {
         if self.check(token_type) {
             Some(self.advance().clone())
@@ -480,7 +818,10 @@ impl Parser {
                 TokenType::If |
                 TokenType::While |
                 TokenType::Print |
-                TokenType::Return => return,
+                TokenType::Return |
+                TokenType::Import |
+                // Leave the closing brace for the enclosing block.
+                TokenType::RightBrace => return,
                 _ => {}
             }
 
@@ -488,6 +829,22 @@ impl Parser {
         }
     }
 
+    // Skips the rest of a construct that nested too deeply, up to the end of its statement
+    // or the bracket closing the one it is in. Synchronizing would stop at every one of its
+    // own closing braces and report each as another error.
+    fn skip_nested(&mut self) {
+        let mut open = 0;
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                TokenType::LeftBrace | TokenType::LeftParen => open += 1,
+                TokenType::RightBrace | TokenType::RightParen | TokenType::Semicolon if open == 0 => return,
+                TokenType::RightBrace | TokenType::RightParen => open -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
     fn is_at_end(&self) -> bool {
         self.peek().token_type == TokenType::EOF
     }
@@ -500,71 +857,329 @@ impl Parser {
         &self.tokens[self.current]
     }
 
+    // The token `distance` places after the current one, reading ahead as far as needed.
+    // Past the end of the source, that is EOF.
+    fn peek_ahead(&mut self, distance: usize) -> &Token {
+        while self.tokens.len() <= self.current + distance && self.tokens.last().is_some_and(|token| token.token_type != TokenType::EOF) {
+            self.read_token();
+        }
+        &self.tokens[(self.current + distance).min(self.tokens.len() - 1)]
+    }
+
     fn previous(&self) -> &Token {
         &self.tokens[self.current-1]
     }
 
-    fn generate_error(&mut self, message: &str) -> String {
-        let line = self.previous().line;
-        format!("[line {line}] Error: {message}")
+    fn generate_error(&mut self, code: ErrorCode, message: &str) -> ParseError {
+        ParseError::new(code, self.peek(), message)
+    }
+}
+
+/// Binding power of the infix operators, from loosest to tightest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+}
+
+impl Precedence {
+    // Whether operators at this level make binary or logical expressions.
+    fn is_chain(self) -> bool {
+        matches!(self, Precedence::Or | Precedence::And | Precedence::Equality | Precedence::Comparison | Precedence::Term | Precedence::Factor)
+    }
+
+    // The precedence one step tighter, used for the right operand of left-associative operators.
+    fn next(self) -> Self {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary | Precedence::Call => Precedence::Call,
+        }
     }
 }
 
+type PrefixRule = fn(&mut Parser) -> Result<ExprId, ParseError>;
+type InfixRule = fn(&mut Parser, ExprId) -> Result<ExprId, ParseError>;
+
+struct ParseRule {
+    prefix: Option<PrefixRule>,
+    infix: Option<InfixRule>,
+    precedence: Precedence,
+}
+
+/// The expression grammar in one table: how each token starts an expression, how it
+/// continues one, and how tightly it binds when it does. New operators only need a row here.
+fn rule(token_type: TokenType) -> ParseRule {
+    let (prefix, infix, precedence): (Option<PrefixRule>, Option<InfixRule>, Precedence) = match token_type {
+        TokenType::LeftParen => (Some(Parser::grouping), Some(Parser::finish_call), Precedence::Call),
+        TokenType::Minus => (Some(Parser::unary), Some(Parser::binary), Precedence::Term),
+        TokenType::Plus => (Some(Parser::missing_left_operand), Some(Parser::binary), Precedence::Term),
+        TokenType::Slash | TokenType::Star => (Some(Parser::missing_left_operand), Some(Parser::binary), Precedence::Factor),
+        TokenType::Bang => (Some(Parser::unary), None, Precedence::None),
+        TokenType::BangEqual | TokenType::EqualEqual => (Some(Parser::missing_left_operand), Some(Parser::binary), Precedence::Equality),
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            (Some(Parser::missing_left_operand), Some(Parser::binary), Precedence::Comparison)
+        },
+        TokenType::Equal => (None, Some(Parser::assignment), Precedence::Assignment),
+        TokenType::And => (None, Some(Parser::logical), Precedence::And),
+        TokenType::Or => (None, Some(Parser::logical), Precedence::Or),
+        TokenType::Identifier => (Some(Parser::variable), None, Precedence::None),
+        TokenType::LeftBrace => (Some(Parser::block_expression), None, Precedence::None),
+        TokenType::If => (Some(Parser::if_expression), None, Precedence::None),
+        TokenType::Number | TokenType::String | TokenType::True | TokenType::False | TokenType::Nil => {
+            (Some(Parser::literal), None, Precedence::None)
+        },
+        _ => (None, None, Precedence::None),
+    };
+
+    ParseRule { prefix, infix, precedence }
+}
+
+/// A statement that fails to parse and starts with something like `whle` was most likely
+/// meant to start with a keyword, so point that out.
+fn suggest_keyword(mut error: ParseError, first: &Token) -> ParseError {
+    // Very short names are too close to too many keywords to make a useful guess.
+    if first.token_type != TokenType::Identifier || first.lexeme.chars().count() < 3 {
+        return error;
+    }
+
+    let keywords = scanner::KEYWORDS.iter().map(|&(text, _)| text);
+    if let Some(keyword) = suggest::did_you_mean(&first.lexeme, keywords) {
+        error.message.push_str(&format!(" Did you mean '{keyword}' instead of '{}'?", first.lexeme));
+    }
+    error
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scanner::Span;
 
     #[test]
     fn parse() {
         let tokens: Vec<Token> = vec![
             Token {
                 token_type: TokenType::Var,
-                lexeme: String::from("var"),
+                lexeme: "var".into(),
                 literal: None,
                 line: 1,
+                column: 1,
+                span: Span::new(0, 3),
             },
             Token {
                 token_type: TokenType::Identifier,
-                lexeme: String::from("a"),
-                literal: Some(Literal::Identifier("a".to_string())),
+                lexeme: "a".into(),
+                literal: Some(Literal::Identifier("a".into())),
                 line: 1,
+                column: 5,
+                span: Span::new(4, 5),
             },
             Token {
                 token_type: TokenType::Equal,
-                lexeme: String::from("="),
+                lexeme: "=".into(),
                 literal: None,
                 line: 1,
+                column: 7,
+                span: Span::new(6, 7),
             },
             Token {
                 token_type: TokenType::Number,
-                lexeme: String::from("5"),
+                lexeme: "5".into(),
                 literal: Some(Literal::Number(5.0)),
                 line: 1,
+                column: 9,
+                span: Span::new(8, 9),
             },
             Token {
                 token_type: TokenType::Semicolon,
-                lexeme: String::from(";"),
+                lexeme: ";".into(),
                 literal: None,
                 line: 1,
+                column: 10,
+                span: Span::new(9, 10),
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: String::from(";"),
+                lexeme: ";".into(),
                 literal: None,
                 line: 1,
+                column: 11,
+                span: Span::new(10, 10),
             },
         ];
 
-        let cmp_statements: Vec<Stmt> = vec![
-            Stmt::Variable {
-                name: tokens[1].clone(),
-                initializer: Some(Expr::Literal { value: Literal::Number(5.0) }),
+        let mut expected = Ast::new();
+        let initializer = expected.add_expr(Expr::Literal { value: Literal::Number(5.0) }, crate::syntax::Span { start: 8, end: 9, line: 1 });
+        let statement = expected.add_stmt(Stmt::Variable { name: tokens[1].clone(), initializer: Some(initializer) }, crate::syntax::Span { start: 0, end: 10, line: 1 });
+        expected.statements.push(statement);
+
+        let ast = parse_tokens(tokens).unwrap();
+
+        assert_eq!(ast.statements.len(), 1);
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn spans() {
+        let source = "print (1 + 2) * f(3);\nif (a)\n  { b = !c; }";
+        let ast = parse_source(source).unwrap();
+
+        let print = ast.statements[0];
+        assert_eq!(ast.stmt_span(print).text(source), "print (1 + 2) * f(3);");
+        let Stmt::Print { expression, .. } = &ast[print] else { panic!("expected a print statement") };
+        let Expr::Binary { left, right, .. } = &ast[*expression] else { panic!("expected a binary expression") };
+        assert_eq!(ast.expr_span(*expression).text(source), "(1 + 2) * f(3)");
+        assert_eq!(ast.expr_span(*left).text(source), "(1 + 2)");
+        assert_eq!(ast.expr_span(*right).text(source), "f(3)");
+
+        let if_statement = ast.statements[1];
+        assert_eq!(ast.stmt_span(if_statement).text(source), "if (a)\n  { b = !c; }");
+        let Stmt::If { then_branch, .. } = &ast[if_statement] else { panic!("expected an if statement") };
+        assert_eq!(ast.stmt_span(*then_branch).text(source), "{ b = !c; }");
+        assert_eq!(ast.stmt_span(*then_branch).line, 3);
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        let errors = parse_source("var a = @1;\nprint a\nvar b = 1;\nprint 1 +;\n").unwrap_err();
+
+        let locations: Vec<(i32, usize)> = errors.iter().map(|error| (error.line(), error.column())).collect();
+        assert_eq!(locations, vec![(1, 9), (3, 1), (4, 10)]);
+        assert!(matches!(errors[0], CompileError::Scan(_)));
+        assert!(matches!(errors[1], CompileError::Parse(_)));
+    }
+
+    #[test]
+    fn binary_operator_without_left_operand() {
+        let errors = parse_source("print * 2;\nvar a = == 1 + 2;\nprint 3;").unwrap_err();
+
+        let messages: Vec<(i32, String)> = errors.iter().map(|error| (error.line(), error.to_string())).collect();
+        assert_eq!(messages, vec![
+            (1, String::from("[line 1] Error at '*': Binary operator without left operand.")),
+            (2, String::from("[line 2] Error at '==': Binary operator without left operand.")),
+        ]);
+    }
+
+    #[test]
+    fn error_nodes() {
+        let (ast, errors) = parse_source_lossy("print 1;\nvar = 2;\n{ print; print 3; }\nprint + 4;");
+
+        assert_eq!(errors.len(), 3);
+        assert_eq!(ast.statements.len(), 4);
+        assert!(matches!(&ast[ast.statements[1]], Stmt::Error { tokens } if tokens.len() == 4));
+        match &ast[ast.statements[2]] {
+            Stmt::Block { statements } => {
+                assert!(matches!(ast[statements[0]], Stmt::Error { .. }));
+                assert!(matches!(ast[statements[1]], Stmt::Print { .. }));
             },
-        ];
+            stmt => panic!("expected a block, got {stmt:?}"),
+        }
+        match &ast[ast.statements[3]] {
+            Stmt::Print { expression, .. } => {
+                assert!(matches!(&ast[*expression], Expr::Binary { left, .. } if matches!(ast[*left], Expr::Error { .. })));
+            },
+            stmt => panic!("expected a print statement, got {stmt:?}"),
+        }
+    }
 
-        let statements = parse_tokens(tokens).unwrap();
+    #[test]
+    fn nesting_limit() {
+        let source = format!("print {}1{};", "(".repeat(10_000), ")".repeat(10_000));
+        let errors = parse_source(&source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().ends_with("Expression too deeply nested."));
+
+        let source = format!("print {}true; print 1 +;", "!".repeat(10_000));
+        assert_eq!(parse_source(&source).unwrap_err().len(), 2);
+
+        // The blocks left open past the limit are skipped rather than reported one by one.
+        let source = format!("{}{} print 1 +;", "{".repeat(10_000), "}".repeat(10_000));
+        let errors = parse_source(&source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().ends_with("Statement too deeply nested."));
+        let source = format!("{{ print {}1{}; }}", "(".repeat(10_000), ")".repeat(10_000));
+        assert_eq!(parse_source(&source).unwrap_err().len(), 1);
+
+        // Long operator chains are flat, however deep the tree they make.
+        assert!(parse_source(&format!("print {};", vec!["1"; 10_000].join(" + "))).is_ok());
+        assert!(parse_source(&format!("print {};", vec!["a"; 10_000].join(" or "))).is_ok());
+        assert!(parse_source(&format!("f{};", "()".repeat(10_000))).is_err());
+
+        let mut parser = Parser::new(scanner::scan_tokens("print ((1));").unwrap());
+        parser.set_max_depth(2);
+        assert_eq!(parser.parse_lossy().1.len(), 1);
+    }
 
-        assert_eq!(statements.len(), 1);
-        assert_eq!(statements, cmp_statements);
+    #[test]
+    fn precedence() {
+        let print = |source: &str| {
+            let ast = parse_source(source).unwrap();
+            crate::ast_printer::print_statements(&ast, &ast.statements)
+        };
+
+        assert_eq!(print("a = b = 1 or 2 and 3;"), "(; (= a (= b (or 1 (and 2 3)))))");
+        assert_eq!(print("1 == 2 < 3 - 4 / -5;"), "(; (== 1 (< 2 (- 3 (/ 4 (- 5))))))");
+        assert_eq!(print("1 - 2 - 3;"), "(; (- (- 1 2) 3))");
+        assert_eq!(print("!f()();"), "(; (! (call (call f))))");
+        assert!(parse_source("a + b = 1;").is_err());
+    }
+
+    #[test]
+    fn calls_and_imports() {
+        let print = |source: &str| {
+            let ast = parse_source(source).unwrap();
+            crate::ast_printer::print_statements(&ast, &ast.statements)
+        };
+
+        assert_eq!(print("import native \"time\";"), "(import native \"time\")");
+        assert_eq!(print("f(1, g(2), 3)();"), "(; (call (call f 1 (call g 2) 3)))");
+        assert_eq!(print("var native = 1;"), "(var native 1)");
+        assert!(parse_source("import \"time\";").is_err());
+        assert_eq!(print("debugger;"), "(debugger)");
+        assert!(parse_source("debugger").is_err());
+
+        let arguments = vec!["1"; 256].join(", ");
+        let errors = parse_source(&format!("f({arguments});")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().ends_with("Can't have more than 255 arguments."));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn from_scanner() {
+        let mut parser = Parser::from_scanner(Scanner::new("print 1;\nprint @ 2;"));
+        // Nothing past the first token is scanned until the parser gets to it.
+        assert_eq!(parser.tokens.len(), 1);
+
+        let (ast, errors) = parser.parse_lossy();
+        assert_eq!(ast.statements.len(), 2);
+        assert!(errors.is_empty());
+
+        let scan_errors = parser.take_scan_errors();
+        assert_eq!(scan_errors.iter().map(|error| (error.line, error.column)).collect::<Vec<_>>(), vec![(2, 7)]);
+    }
+
+    #[test]
+    fn hand_made_tokens() {
+        assert_eq!(parse_tokens(Vec::new()).unwrap().statements.len(), 0);
+
+        let mut tokens = scanner::scan_tokens("print 1;").unwrap();
+        tokens.pop();
+        tokens[1].literal = None;
+        let errors = parse_tokens(tokens).unwrap_err();
+        assert_eq!(errors[0].to_string(), "[line 1] Error at '1': Literal token has no value.");
+    }
+}
@@ -0,0 +1,165 @@
+//! Renaming a variable, as `jlox rename` does it. Only the declaration and the names that
+//! resolve to it change, found through `symbols`, so another variable of the same name in
+//! a different scope is left alone.
+//!
+//! A rename that would make any name in the program refer to a different declaration than
+//! before, by shadowing or being shadowed by the new name, is refused.
+
+use crate::error::CompileError;
+use crate::parser;
+use crate::scanner::{self, Token, TokenType};
+use crate::symbols::{Position, SymbolTable};
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// A replacement of the bytes in `range` of the source, at `line` and `column`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub line: i32,
+    pub column: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenameError {
+    Compile(Vec<CompileError>),
+    /// No variable is declared or used at the position.
+    NotFound(Position),
+    /// The variable at the position has another name than the one to rename.
+    WrongName { position: Position, found: String },
+    /// The new name is not an identifier, like a keyword.
+    InvalidName(String),
+    /// The rename would make the name at `token` refer to another declaration.
+    Conflict { token: Token, name: String },
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameError::Compile(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{error}")?;
+                }
+                Ok(())
+            },
+            RenameError::NotFound(position) =>
+                write!(f, "No variable at line {}, column {}.", position.line, position.column),
+            RenameError::WrongName { position, found } =>
+                write!(f, "The variable at line {}, column {} is '{found}'.", position.line, position.column),
+            RenameError::InvalidName(name) => write!(f, "'{name}' is not a valid variable name."),
+            RenameError::Conflict { token, name } =>
+                write!(f, "Renaming to '{name}' would change what '{}' at line {}, column {} refers to.", token.lexeme, token.line, token.column),
+        }
+    }
+}
+
+impl Error for RenameError {}
+
+impl From<Vec<CompileError>> for RenameError {
+    fn from(errors: Vec<CompileError>) -> Self {
+        RenameError::Compile(errors)
+    }
+}
+
+/// The edits that rename the variable `old`, declared or used at `position`, to `new`, in
+/// source order.
+pub fn rename(source: &str, position: Position, old: &str, new: &str) -> Result<Vec<Edit>, RenameError> {
+    let table = SymbolTable::build(&parser::parse_source(source)?);
+    let symbol = table.symbol_at(position).ok_or(RenameError::NotFound(position))?;
+    let declaration = &table.symbols[symbol].name;
+    if &*declaration.lexeme != old {
+        return Err(RenameError::WrongName { position, found: declaration.lexeme.to_string() });
+    }
+    if !is_identifier(new) {
+        return Err(RenameError::InvalidName(new.to_string()));
+    }
+
+    let mut edits: Vec<Edit> = std::iter::once(declaration)
+        .chain(table.references_to(symbol).map(|reference| &reference.name))
+        .map(|token| Edit { range: token.span.start..token.span.end, line: token.line, column: token.column, text: new.to_string() })
+        .collect();
+    edits.sort_by_key(|edit| edit.range.start);
+
+    // Symbols and references are numbered in source order, which the rename keeps, so every
+    // reference must still resolve to the same symbol.
+    let renamed = SymbolTable::build(&parser::parse_source(&apply(source, &edits))?);
+    let changed = table.references.iter().zip(&renamed.references)
+        .find(|(before, after)| before.symbol != after.symbol);
+    if let Some((reference, _)) = changed {
+        return Err(RenameError::Conflict { token: reference.name.clone(), name: new.to_string() });
+    }
+
+    Ok(edits)
+}
+
+/// `source` with `edits` made, which must be in source order and not overlap.
+pub fn apply(source: &str, edits: &[Edit]) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut copied = 0;
+    for edit in edits {
+        result.push_str(&source[copied..edit.range.start]);
+        result.push_str(&edit.text);
+        copied = edit.range.end;
+    }
+    result.push_str(&source[copied..]);
+    result
+}
+
+fn is_identifier(name: &str) -> bool {
+    match scanner::scan_tokens(name).as_deref() {
+        Ok([token, _eof]) => token.token_type == TokenType::Identifier && token.span.len() == name.len(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: i32, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    fn renamed(source: &str, position: Position, old: &str, new: &str) -> Result<String, RenameError> {
+        rename(source, position, old, new).map(|edits| apply(source, &edits))
+    }
+
+    #[test]
+    fn renames_one_variable() {
+        let source = "var a = 1;\n{\n  var a = a + 1;\n  a = a * 2;\n  print a;\n}\nprint a;";
+        assert_eq!(
+            renamed(source, at(3, 7), "a", "inner").unwrap(),
+            "var a = 1;\n{\n  var inner = a + 1;\n  inner = inner * 2;\n  print inner;\n}\nprint a;",
+        );
+        // From a use as well as from the declaration, and a global used before it is declared.
+        let source = "var f = () => count;\nvar count = 0;\nprint count;";
+        assert_eq!(renamed(source, at(3, 7), "count", "total").unwrap(), "var f = () => total;\nvar total = 0;\nprint total;");
+
+        let source = "var add = (a, b) => a + b;\nprint add(1, 2);";
+        let edits = rename(source, at(1, 12), "a", "left").unwrap();
+        assert_eq!(edits.iter().map(|edit| (edit.line, edit.column)).collect::<Vec<_>>(), vec![(1, 12), (1, 21)]);
+    }
+
+    #[test]
+    fn refusals() {
+        let source = "var a = 1;\n{\n  var b = 2;\n  print a + b;\n}";
+        assert_eq!(rename(source, at(1, 9), "a", "c"), Err(RenameError::NotFound(at(1, 9))));
+        assert_eq!(rename(source, at(1, 5), "b", "c"), Err(RenameError::WrongName { position: at(1, 5), found: String::from("a") }));
+        assert_eq!(rename(source, at(1, 5), "a", "while"), Err(RenameError::InvalidName(String::from("while"))));
+        assert_eq!(rename(source, at(1, 5), "a", "c d"), Err(RenameError::InvalidName(String::from("c d"))));
+
+        // The inner declaration would hide the global from `print a`.
+        let error = rename(source, at(3, 7), "b", "a").unwrap_err();
+        assert_eq!(error.to_string(), "Renaming to 'a' would change what 'a' at line 4, column 9 refers to.");
+        // And a native function would be hidden by the global.
+        assert!(matches!(rename("import native \"time\";\nvar a = clock();", at(2, 5), "a", "clock"), Err(RenameError::Conflict { .. })));
+
+        assert!(matches!(rename("var a = ;", at(1, 5), "a", "b"), Err(RenameError::Compile(_))));
+    }
+}
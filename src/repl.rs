@@ -0,0 +1,351 @@
+use crate::run;
+
+use crafting_interpreters_rust::ast_printer;
+use crafting_interpreters_rust::completion::Completions;
+use crafting_interpreters_rust::diagnostics::Diagnostics;
+use crafting_interpreters_rust::error::LoxError;
+use crafting_interpreters_rust::interpreter::Interpreter;
+use crafting_interpreters_rust::native::DebugModule;
+use crafting_interpreters_rust::observer::Trace;
+use crafting_interpreters_rust::parser;
+use crafting_interpreters_rust::source::read_source;
+use crafting_interpreters_rust::threads::ThreadsModule;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+const DEFAULT_SESSION_FILE: &str = "session.lox";
+
+const COMMANDS: [&str; 8] = ["ast", "edit", "env", "load", "paste", "restore", "save", "time"];
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".lox_history"))
+}
+
+// The file run at startup: $LOX_INIT if set, ~/.loxrc otherwise. Only a file named
+// explicitly in $LOX_INIT is expected to exist.
+fn init_path() -> Option<(PathBuf, bool)> {
+    match env::var_os("LOX_INIT") {
+        Some(path) => Some((PathBuf::from(path), true)),
+        None => env::var_os("HOME").map(|home| (PathBuf::from(home).join(".loxrc"), false)),
+    }
+}
+
+// Completes the names defined in the session and the REPL's commands on Tab.
+#[derive(Default)]
+struct LoxHelper {
+    completions: Completions,
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        if let Some(command) = line[..pos].trim_start().strip_prefix(':') {
+            if !command.contains(char::is_whitespace) {
+                let commands = COMMANDS.iter().filter(|name| name.starts_with(command)).map(|name| name.to_string()).collect();
+                return Ok((pos - command.len(), commands));
+            }
+        }
+        Ok(self.completions.complete(line, pos))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+struct Repl {
+    interpreter: Interpreter,
+    editor: Editor<LoxHelper, DefaultHistory>,
+    // Source of every input that executed successfully, in order.
+    session: Vec<String>,
+    diagnostics: Diagnostics,
+    // Set by Ctrl-C while a program runs, to drop back to the prompt.
+    interrupt: Arc<AtomicBool>,
+    trace: bool,
+}
+
+impl Repl {
+    fn new(diagnostics: Diagnostics, trace: bool) -> Self {
+        let interrupt = Arc::new(AtomicBool::new(false));
+
+        // While reading a line the editor handles Ctrl-C itself, so the handler only
+        // sees the ones pressed while a program is running.
+        let handler_interrupt = Arc::clone(&interrupt);
+        if let Err(e) = ctrlc::set_handler(move || handler_interrupt.store(true, Ordering::Relaxed)) {
+            eprintln!("Ctrl-C will not interrupt running programs: {e}");
+        }
+
+        let mut editor = Editor::new().expect("Failed to initialize line editor.");
+        editor.set_helper(Some(LoxHelper::default()));
+
+        Self {
+            interpreter: new_interpreter(&interrupt, trace),
+            editor,
+            session: Vec::new(),
+            diagnostics,
+            interrupt,
+            trace,
+        }
+    }
+
+    fn execute(&mut self, source: &str) {
+        self.interrupt.store(false, Ordering::Relaxed);
+        if run(&mut self.interpreter, source, &self.diagnostics).is_ok() {
+            self.session.push(source.trim_end().to_string());
+        }
+    }
+
+    fn run_command(&mut self, command: &str) {
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command.trim(), ""),
+        };
+
+        match name {
+            "load" => self.load_file(argument),
+            "save" => self.save_session(argument),
+            "restore" => self.restore_session(argument),
+            "ast" => self.print_ast(argument),
+            "time" => self.time(argument),
+            "paste" => self.paste(),
+            "edit" => self.edit(),
+            "env" => self.print_env(),
+            _ => eprintln!("Unknown command ':{name}'."),
+        }
+    }
+
+    fn time(&mut self, source: &str) {
+        let start = Instant::now();
+        self.execute(source);
+        println!("Elapsed: {:.3?}", start.elapsed());
+    }
+
+    fn paste(&mut self) {
+        println!("Paste mode: end with a line containing only '.' or Ctrl-D.");
+
+        let mut buffer = String::new();
+        loop {
+            match self.editor.readline("") {
+                Ok(line) => {
+                    if line.trim() == "." {
+                        break;
+                    }
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                },
+                Err(ReadlineError::Eof) => break,
+                Err(ReadlineError::Interrupted) => {
+                    eprintln!("Paste cancelled.");
+                    return;
+                },
+                Err(e) => {
+                    eprintln!("Failed to read line: {e}");
+                    return;
+                }
+            }
+        }
+
+        self.execute(&buffer);
+    }
+
+    fn edit(&mut self) {
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| String::from("vi"));
+        let path = env::temp_dir().join(format!("lox-edit-{}.lox", std::process::id()));
+
+        if let Err(e) = fs::write(&path, "") {
+            eprintln!("Could not create '{}': {e}", path.display());
+            return;
+        }
+
+        match Command::new(&editor).arg(&path).status() {
+            Ok(status) if status.success() => {
+                match read_source(&path) {
+                    Ok(contents) => self.execute(&contents),
+                    Err(e) => eprintln!("Could not read '{}': {e}", path.display()),
+                }
+            },
+            Ok(status) => eprintln!("Editor '{editor}' exited with {status}."),
+            Err(e) => eprintln!("Could not start editor '{editor}': {e}"),
+        }
+
+        fs::remove_file(&path).ok();
+    }
+
+    // Every frame of the call stack with its variables, scope by scope.
+    fn print_env(&self) {
+        for frame in self.interpreter.stack_frames() {
+            match frame.line {
+                Some(line) => println!("{} [line {line}]", frame.function),
+                None => println!("{}", frame.function),
+            }
+
+            for (depth, scope) in frame.scopes.iter().rev().enumerate() {
+                for (name, value) in scope {
+                    println!("{}{name} = {value}", "  ".repeat(depth + 1));
+                }
+            }
+        }
+    }
+
+    fn print_ast(&self, source: &str) {
+        let (ast, errors) = parser::parse_source_lossy(source);
+        if !errors.is_empty() {
+            self.diagnostics.emit_error(&LoxError::from(errors), source);
+        }
+
+        println!("{}", ast_printer::print_statements(&ast, &ast.statements));
+    }
+
+    fn load_file(&mut self, filename: &str) {
+        if filename.is_empty() {
+            eprintln!("Usage: :load <file>");
+            return;
+        }
+
+        match read_source(filename) {
+            Ok(contents) => {
+                if run(&mut self.interpreter, &contents, &self.diagnostics).is_ok() {
+                    self.session.push(contents.trim_end().to_string());
+                    println!("Loaded {filename}");
+                }
+            },
+            Err(e) => eprintln!("Could not read '{filename}': {e}"),
+        }
+    }
+
+    // Definitions from the init file are available in the session, but aren't part of it
+    // when it is saved.
+    fn run_init_file(&mut self) {
+        let (path, required) = match init_path() {
+            Some(init) => init,
+            None => { return; }
+        };
+
+        match read_source(&path) {
+            Ok(contents) => {
+                if run(&mut self.interpreter, &contents, &self.diagnostics).is_err() {
+                    eprintln!("Failed to run init file {}", path.display());
+                }
+            },
+            Err(e) => {
+                if required {
+                    eprintln!("Could not read '{}': {e}", path.display());
+                }
+            }
+        }
+    }
+
+    fn save_session(&self, filename: &str) {
+        let filename = if filename.is_empty() { DEFAULT_SESSION_FILE } else { filename };
+
+        let mut contents = self.session.join("\n");
+        contents.push('\n');
+
+        match fs::write(filename, contents) {
+            Ok(_) => println!("Saved {} entries to {filename}", self.session.len()),
+            Err(e) => eprintln!("Could not write '{filename}': {e}"),
+        }
+    }
+
+    fn restore_session(&mut self, filename: &str) {
+        let filename = if filename.is_empty() { DEFAULT_SESSION_FILE } else { filename };
+
+        let contents = match read_source(filename) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("Could not read '{filename}': {e}");
+                return;
+            }
+        };
+
+        let mut interpreter = new_interpreter(&self.interrupt, self.trace);
+        if run(&mut interpreter, &contents, &self.diagnostics).is_err() {
+            eprintln!("Failed to restore session from {filename}");
+            return;
+        }
+
+        self.interpreter = interpreter;
+        self.session = vec![contents.trim_end().to_string()];
+        println!("Restored session from {filename}");
+    }
+}
+
+fn new_interpreter(interrupt: &Arc<AtomicBool>, trace: bool) -> Interpreter {
+    let mut interpreter = Interpreter::new(true);
+    interpreter.register_module(Box::new(ThreadsModule));
+    interpreter.register_module(Box::new(DebugModule));
+    interpreter.set_interrupt(Arc::clone(interrupt));
+    if trace {
+        interpreter.add_observer(Box::new(Trace::stderr()));
+    }
+    interpreter
+}
+
+pub fn run_prompt(diagnostics: Diagnostics, init: bool, trace: bool) {
+    println!("Running prompt");
+
+    let mut repl = Repl::new(diagnostics, trace);
+    if init {
+        repl.run_init_file();
+    }
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session.
+        repl.editor.load_history(path).ok();
+    }
+
+    loop {
+        if let Some(helper) = repl.editor.helper_mut() {
+            helper.completions = Completions::of(&repl.interpreter);
+        }
+
+        match repl.editor.readline("> ") {
+            Ok(line) => {
+                if line.trim().is_empty() {
+                    break;
+                }
+
+                repl.editor.add_history_entry(line.as_str()).ok();
+                match line.trim_start().strip_prefix(':') {
+                    Some(command) => repl.run_command(command),
+                    None => repl.execute(&line),
+                }
+            },
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Failed to read line: {e}");
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        repl.editor.save_history(path).ok();
+    }
+
+    println!("Exiting interactive prompt.");
+}
@@ -0,0 +1,63 @@
+//! Recordings of what a run read from outside the program, so it can be run again and
+//! take exactly the same path: the seed of the random natives and every clock reading.
+//! `jlox --record=<file>` writes one and `jlox --replay=<file>` runs a script with it.
+//!
+//! Scripts have no other inputs yet. Workers started by `spawn` run in interpreters of
+//! their own, which aren't recorded, and how threads interleave isn't either.
+
+use serde_json::{json, Value as Json};
+
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Recording {
+    pub seed: u64,
+    /// Seconds since the Unix epoch, in the order the program read them.
+    pub clock: Vec<f64>,
+}
+
+impl Recording {
+    /// Writes the recording as JSON.
+    pub fn write(&self, writer: impl Write) -> io::Result<()> {
+        serde_json::to_writer(writer, &json!({ "seed": self.seed, "clock": self.clock }))?;
+        Ok(())
+    }
+
+    pub fn read(reader: impl Read) -> io::Result<Self> {
+        let json: Json = serde_json::from_reader(reader)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a recording of a run");
+
+        let seed = json["seed"].as_u64().ok_or_else(invalid)?;
+        let clock = json["clock"].as_array().ok_or_else(invalid)?
+            .iter()
+            .map(|reading| reading.as_f64().ok_or_else(invalid))
+            .collect::<io::Result<Vec<f64>>>()?;
+        Ok(Self { seed, clock })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn replay() {
+        let source = "import native \"time\"; import native \"random\"; clock() + random();";
+        let mut recorded = Interpreter::new(false);
+        recorded.start_recording();
+        let value = recorded.eval(source).unwrap();
+
+        let mut file: Vec<u8> = Vec::new();
+        recorded.recording().write(&mut file).unwrap();
+        let recording = Recording::read(file.as_slice()).unwrap();
+        assert_eq!(recording.clock.len(), 1);
+
+        let mut replayed = Interpreter::new(false);
+        replayed.replay(&recording);
+        assert_eq!(replayed.eval(source).unwrap(), value);
+        assert!(replayed.eval("clock();").is_err());
+
+        assert!(Recording::read("{\"seed\": -1}".as_bytes()).is_err());
+    }
+}
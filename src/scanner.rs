@@ -1,11 +1,43 @@
-use std::collections::HashMap;
+use crate::codes::ErrorCode;
+use crate::error::ScanError;
+use crate::intern::{Interner, Symbol};
+use crate::shared::Shared;
 
-pub fn scan_tokens(source: &str) -> Result<Vec<Token>, ()> {
-    let mut scanner = Scanner::new(source);
-    match scanner.scan_tokens() {
-        Ok(_) => Ok(scanner.tokens),
-        Err(_) => Err(())
+use std::collections::{HashMap, VecDeque};
+
+pub fn scan_tokens(source: &str) -> Result<Vec<Token>, Vec<ScanError>> {
+    let (tokens, errors) = scan_tokens_lossy(source);
+
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Scans the whole source, skipping over invalid characters. The returned tokens are
+/// everything that scanned cleanly, so they can still be parsed for further errors.
+pub fn scan_tokens_lossy(source: &str) -> (Vec<Token>, Vec<ScanError>) {
+    collect(Scanner::new(source))
+}
+
+/// Like `scan_tokens_lossy`, but keeps every `//` comment as a `Comment` token so tools
+/// that rewrite source can put the comments back. The parser does not accept these tokens.
+pub fn scan_tokens_with_comments(source: &str) -> (Vec<Token>, Vec<ScanError>) {
+    collect(Scanner::with_comments(source))
+}
+
+fn collect(scanner: Scanner) -> (Vec<Token>, Vec<ScanError>) {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut errors: Vec<ScanError> = Vec::new();
+    for result in scanner {
+        match result {
+            Ok(token) => tokens.push(token),
+            Err(error) => errors.push(error),
+        }
     }
+
+    (tokens, errors)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -16,7 +48,7 @@ pub enum TokenType {
 
     // One or two character tokens.
     Bang, BangEqual,
-    Equal, EqualEqual,
+    Equal, EqualEqual, Arrow,
     Greater, GreaterEqual,
     Less, LessEqual,
 
@@ -25,82 +57,180 @@ pub enum TokenType {
 
     // Keywords.
     And, Class, Else, False, Fun, For, If, Nil, Or,
-    Print, Return, Super, This, True, Var, While, Break,
+    Print, Return, Super, This, True, Var, While, Break, Import, Debugger,
+
+    // Trivia, only produced when the scanner is asked to keep it.
+    Comment,
 
     EOF
 }
 
+pub const KEYWORDS: [(&str, TokenType); 19] = [
+    ("and", TokenType::And),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("fun", TokenType::Fun),
+    ("for", TokenType::For),
+    ("if", TokenType::If),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+    ("break", TokenType::Break),
+    ("import", TokenType::Import),
+    ("debugger", TokenType::Debugger),
+];
+
+// Keywords that the book's Lox doesn't have.
+const EXTENSION_KEYWORDS: [&str; 3] = ["break", "import", "debugger"];
+
+pub(crate) fn keywords(dialect: Dialect) -> HashMap<String, TokenType> {
+    KEYWORDS.iter()
+        .filter(|&&(text, _)| !(dialect.print_function && text == "print"))
+        .filter(|&&(text, _)| !(dialect.jlox && EXTENSION_KEYWORDS.contains(&text)))
+        .map(|&(text, token_type)| (text.to_string(), token_type))
+        .collect()
+}
+
+/// Variations on the language that a host can opt into. The default is the Lox of the book
+/// with this crate's extensions; `jlox` takes the extensions away again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dialect {
+    /// `print` is an ordinary name instead of a statement, and programs print by calling
+    /// the `print` and `println` natives.
+    pub print_function: bool,
+    /// Behaves exactly like jlox, the reference interpreter from the book. The syntax this
+    /// crate adds is gone, so `break`, `import` and `debugger` are ordinary names. At run
+    /// time, `==` on different types is false instead of an error, `!` works on any value,
+    /// dividing by zero gives infinity or NaN, and numbers print the way Java prints them.
+    /// Runtime errors use the book's messages.
+    pub jlox: bool,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
-    Identifier(String),
-    Str(String),
+    Identifier(Symbol),
+    Str(Shared<str>),
     Number(f64),
     Bool(bool),
     Nil
 }
 
+/// Where a token sits in the source, as a range of byte offsets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// A token points back into the source it was scanned from through `span`. The lexeme is
+/// kept too, interned, because errors and environments need the text after the source
+/// is gone, as in the REPL.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Option<Literal>,
-    pub line: i32
+    pub line: i32,
+    pub column: usize,
+    pub span: Span,
 }
 
-struct Scanner {
+impl Token {
+    /// The token's text in `source`, which must be the source it was scanned from.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.start..self.span.end]
+    }
+}
+
+/// Scans tokens one at a time, for callers that don't need them all at once. Scanning
+/// goes on past errors, and the last item is always the `EOF` token.
+pub struct Scanner {
     source: Vec<char>,
-    tokens: Vec<Token>,
+    // The byte offset of every character in `source`, and of the end.
+    offsets: Vec<usize>,
+    // What the last scanned token produced, waiting to be handed out.
+    pending: VecDeque<Result<Token, ScanError>>,
+    finished: bool,
     start: usize,
     current: usize,
     line: i32,
-    is_error: bool,
+    // Offset of the first character of the current line, for computing columns.
+    line_start: usize,
+    // Position of the first character of the token being scanned.
+    token_line: i32,
+    token_column: usize,
     keywords: HashMap<String, TokenType>,
+    dialect: Dialect,
+    keep_comments: bool,
+    interner: Interner,
+    // Reused to build each lexeme before it is interned.
+    lexeme: String,
 }
 
 impl Scanner {
-    fn new(source: &str) -> Self {
+    pub fn new(source: &str) -> Self {
         Self {
             source: source.chars().collect(),
-            tokens: vec![],
+            offsets: source.char_indices().map(|(offset, _)| offset).chain(std::iter::once(source.len())).collect(),
+            pending: VecDeque::new(),
+            finished: false,
             start: 0,
             current: 0,
             line: 1,
-            is_error: false,
-            keywords: HashMap::from([
-                (String::from("and"), TokenType::And),
-                (String::from("class"), TokenType::Class),
-                (String::from("else"), TokenType::Else),
-                (String::from("false"), TokenType::False),
-                (String::from("fun"), TokenType::Fun),
-                (String::from("for"), TokenType::For),
-                (String::from("if"), TokenType::If),
-                (String::from("nil"), TokenType::Nil),
-                (String::from("or"), TokenType::Or),
-                (String::from("print"), TokenType::Print),
-                (String::from("return"), TokenType::Return),
-                (String::from("super"), TokenType::Super),
-                (String::from("this"), TokenType::This),
-                (String::from("true"), TokenType::True),
-                (String::from("var"), TokenType::Var),
-                (String::from("while"), TokenType::While),
-                (String::from("break"), TokenType::Break),
-            ])
+            line_start: 0,
+            token_line: 1,
+            token_column: 1,
+            keywords: keywords(Dialect::default()),
+            dialect: Dialect::default(),
+            keep_comments: false,
+            interner: Interner::new(),
+            lexeme: String::new(),
         }
     }
 
-    fn scan_tokens(&mut self) -> Result<(), ()> {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token();
-        }
+    /// A scanner that also produces a `Comment` token for every `//` comment.
+    pub fn with_comments(source: &str) -> Self {
+        Self { keep_comments: true, ..Self::new(source) }
+    }
 
-        self.add_token(TokenType::EOF);
+    /// A scanner for `dialect` instead of the default language.
+    pub fn with_dialect(self, dialect: Dialect) -> Self {
+        Self { keywords: keywords(dialect), dialect, ..self }
+    }
 
-        if !self.is_error {
-            Ok(())
-        } else {
-            Err(())
-        }
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    fn mark_token_start(&mut self) {
+        self.token_line = self.line;
+        self.token_column = self.current - self.line_start + 1;
+    }
+
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn is_at_end(&self) -> bool {
@@ -131,6 +261,8 @@ impl Scanner {
             '=' => {
                 if self.check_next('=') {
                     self.add_token(TokenType::EqualEqual)
+                } else if !self.dialect.jlox && self.check_next('>') {
+                    self.add_token(TokenType::Arrow)
                 } else {
                     self.add_token(TokenType::Equal)
                 }
@@ -154,27 +286,31 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(TokenType::Comment);
+                    }
                 } else {
                     self.add_token(TokenType::Slash);
                 }
             },
             ' ' | '\r' | '\t' => {},
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             '"' => self.scan_string(),
             _ => {
-                if character.is_digit(10) {
+                if character.is_ascii_digit() {
                     self.scan_number();
                 } else if character.is_alphabetic() || character == '_' {
                     self.scan_identifier();
                 } else {
-                    self.report_error("unknown character.");
+                    let message = if self.dialect.jlox { "Unexpected character." } else { "unknown character." };
+                    self.report_error(ErrorCode::UnexpectedCharacter, message);
                 }
             }
         }
     }
 
     fn advance(&mut self) -> char {
-        let next = self.source[self.current as usize];
+        let next = self.source[self.current];
         self.current += 1;
         next
     }
@@ -184,9 +320,13 @@ impl Scanner {
     }
 
     fn add_token_literal(&mut self, token_type: TokenType, literal: Option<Literal>) {
-        let lexeme = String::from_iter(self.source[self.start..self.current].iter());
+        self.lexeme.clear();
+        self.lexeme.extend(&self.source[self.start..self.current]);
+        let lexeme = self.interner.intern(&self.lexeme);
 
-        self.tokens.push(Token{token_type, lexeme, literal, line: self.line})
+        let span = Span::new(self.offsets[self.start], self.offsets[self.current]);
+
+        self.pending.push_back(Ok(Token{token_type, lexeme, literal, line: self.token_line, column: self.token_column, span}))
     }
 
     fn check_next(&mut self, expected: char) -> bool {
@@ -202,52 +342,57 @@ impl Scanner {
         true
     }
 
+    fn previous(&self) -> char {
+        self.source[self.current - 1]
+    }
+
     fn peek(&self) -> char {
         if self.is_at_end() {
-            return '\0';
+            '\0'
         } else {
-            return self.source[self.current];
+            self.source[self.current]
         }
     }
 
     fn peek_next(&self) -> char {
         if self.current + 1 >= self.source.len() {
-            return '\0';
+            '\0'
         } else {
-            return self.source[self.current+1];
+            self.source[self.current+1]
         }
     }
 
     fn scan_string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
             self.advance();
+            if self.previous() == '\n' {
+                self.new_line();
+            }
         }
 
         if self.is_at_end() {
-            self.report_error("unterminated string.");
+            let message = if self.dialect.jlox { "Unterminated string." } else { "unterminated string." };
+            self.report_error(ErrorCode::UnterminatedString, message);
             return;
         }
 
         self.advance();
 
         let text = String::from_iter(self.source[self.start+1..self.current-1].iter());
-        self.add_token_literal(TokenType::String, Some(Literal::Str(text)));
+        self.add_token_literal(TokenType::String, Some(Literal::Str(text.into())));
     }
 
     fn scan_number(&mut self) {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
         // Look for a fractional part.
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             // Consume the "."
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
@@ -265,14 +410,43 @@ impl Scanner {
         let text = String::from_iter(self.source[self.start..self.current].iter());
         match self.keywords.get(&text) {
             Some(&token_type) => self.add_token(token_type),
-            None => self.add_token_literal(TokenType::Identifier, Some(Literal::Identifier(text))),
+            None => {
+                let name = self.interner.intern(&text);
+                self.add_token_literal(TokenType::Identifier, Some(Literal::Identifier(name)))
+            },
         }
     }
 
-    fn report_error(&mut self, message: &str) {
-        self.is_error = true;
-        let line = self.line;
-        println!("[line {line}] Error: {message}");
+    fn report_error(&mut self, code: ErrorCode, message: &str) {
+        // Point at the last character consumed, which is where scanning gave up.
+        let column = (self.current - self.line_start).max(1);
+        self.pending.push_back(Err(ScanError::new(code, self.line, column, message)));
+    }
+}
+
+impl Iterator for Scanner {
+    type Item = Result<Token, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Whitespace and comments produce nothing, so it can take a few characters to get
+        // to the next token.
+        loop {
+            if let Some(result) = self.pending.pop_front() {
+                return Some(result);
+            }
+            if self.finished {
+                return None;
+            }
+
+            self.start = self.current;
+            self.mark_token_start();
+            if self.is_at_end() {
+                self.finished = true;
+                self.add_token(TokenType::EOF);
+            } else {
+                self.scan_token();
+            }
+        }
     }
 }
 
@@ -286,39 +460,51 @@ mod tests {
         let cmp_tokens: Vec<Token> = vec![
             Token {
                 token_type: TokenType::Var,
-                lexeme: String::from("var"),
+                lexeme: "var".into(),
                 literal: None,
                 line: 1,
+                column: 1,
+                span: Span::new(0, 3),
             },
             Token {
                 token_type: TokenType::Identifier,
-                lexeme: String::from("a"),
-                literal: Some(Literal::Identifier("a".to_string())),
+                lexeme: "a".into(),
+                literal: Some(Literal::Identifier("a".into())),
                 line: 1,
+                column: 5,
+                span: Span::new(4, 5),
             },
             Token {
                 token_type: TokenType::Equal,
-                lexeme: String::from("="),
+                lexeme: "=".into(),
                 literal: None,
                 line: 1,
+                column: 7,
+                span: Span::new(6, 7),
             },
             Token {
                 token_type: TokenType::Number,
-                lexeme: String::from("5"),
+                lexeme: "5".into(),
                 literal: Some(Literal::Number(5.0)),
                 line: 1,
+                column: 9,
+                span: Span::new(8, 9),
             },
             Token {
                 token_type: TokenType::Semicolon,
-                lexeme: String::from(";"),
+                lexeme: ";".into(),
                 literal: None,
                 line: 1,
+                column: 10,
+                span: Span::new(9, 10),
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: String::from(";"),
+                lexeme: "".into(),
                 literal: None,
                 line: 1,
+                column: 11,
+                span: Span::new(10, 10),
             },
         ];
 
@@ -382,4 +568,53 @@ mod tests {
         assert_eq!(tokens[3].token_type, TokenType::Semicolon);
         assert_eq!(tokens[4].token_type, TokenType::EOF);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn columns() {
+        let source = "var a = 1;\n  print \"a\nb\" + a;";
+
+        let tokens = scan_tokens(source).unwrap();
+
+        let positions: Vec<(i32, usize)> = tokens.iter().map(|token| (token.line, token.column)).collect();
+        assert_eq!(positions, vec![(1, 1), (1, 5), (1, 7), (1, 9), (1, 10), (2, 3), (2, 9), (3, 4), (3, 6), (3, 7), (3, 8)]);
+    }
+
+    #[test]
+    fn iterator() {
+        let mut scanner = Scanner::new("a @ b");
+
+        assert_eq!(scanner.next().unwrap().unwrap().lexeme, "a".into());
+        assert_eq!(scanner.next().unwrap().unwrap_err().column, 3);
+        assert_eq!(scanner.next().unwrap().unwrap().lexeme, "b".into());
+        assert_eq!(scanner.next().unwrap().unwrap().token_type, TokenType::EOF);
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn spans() {
+        let source = "print \"héllo\" + x;";
+
+        let tokens = scan_tokens(source).unwrap();
+
+        let spans: Vec<(usize, usize)> = tokens.iter().map(|token| (token.span.start, token.span.end)).collect();
+        assert_eq!(spans, vec![(0, 5), (6, 14), (15, 16), (17, 18), (18, 19), (19, 19)]);
+        assert_eq!(tokens[1].text(source), "\"héllo\"");
+        assert!(tokens.iter().all(|token| token.text(source) == &*token.lexeme));
+    }
+
+    #[test]
+    fn comments() {
+        let source = "// header\nvar a = 1; // trailing\n";
+
+        assert_eq!(scan_tokens(source).unwrap().len(), 6);
+
+        let (tokens, errors) = scan_tokens_with_comments(source);
+        let comments: Vec<(&str, i32, usize)> = tokens.iter()
+            .filter(|token| token.token_type == TokenType::Comment)
+            .map(|token| (&*token.lexeme, token.line, token.column))
+            .collect();
+
+        assert!(errors.is_empty());
+        assert_eq!(comments, vec![("// header", 1, 1), ("// trailing", 2, 12)]);
+    }
+}
@@ -0,0 +1,97 @@
+//! `serde` support for `Value`, so hosts can move structured data in and out of scripts.
+//! Lox values map onto the serde data model as numbers, strings, booleans and unit for
+//! `nil`; a string builder is written out as its text. Lox has no lists or maps, so
+//! deserializing those fails, and functions and channels cannot be serialized.
+
+use crate::interpreter::Value;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+
+use std::fmt;
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Identifier(text) | Value::Str(text) => serializer.serialize_str(text),
+            Value::Number(number) => serializer.serialize_f64(*number),
+            Value::Bool(value) => serializer.serialize_bool(*value),
+            Value::Nil => serializer.serialize_unit(),
+            Value::Builder(builder) => serializer.serialize_str(&builder.text()),
+            Value::Native(function) => Err(ser::Error::custom(format!("cannot serialize native function '{}'", function.name))),
+            Value::Function(_) => Err(ser::Error::custom("cannot serialize function")),
+            Value::Channel(_) => Err(ser::Error::custom("cannot serialize channel")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number, string, boolean or nil")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    // Lox only has doubles, so integers are widened like number literals in source are.
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::Number(value as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        Ok(Value::Number(value as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+        Ok(Value::Number(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let values = vec![Value::Number(1.5), Value::from("lox"), Value::Bool(true), Value::Nil];
+
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(json, r#"[1.5,"lox",true,null]"#);
+        assert_eq!(serde_json::from_str::<Vec<Value>>(&json).unwrap(), values);
+
+        assert_eq!(serde_json::from_str::<Value>("3").unwrap(), Value::Number(3.0));
+        assert!(serde_json::from_str::<Value>("[1, 2]").is_err());
+        assert!(serde_json::from_str::<Value>(r#"{"a": 1}"#).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! Shared, interior-mutable state for the interpreter. By default this is `Rc` and
+//! `RefCell`; the `sync` feature swaps in `Arc` and `RwLock` so that an `Interpreter`
+//! is `Send` and can be moved to a worker thread, at the cost of atomic reference counts.
+
+#[cfg(not(feature = "sync"))]
+mod imp {
+    use std::cell::{Ref, RefCell, RefMut};
+    use std::io::Write;
+
+    pub type Shared<T> = std::rc::Rc<T>;
+    pub type Weak<T> = std::rc::Weak<T>;
+
+    /// Where the interpreter writes what a program prints.
+    pub type Output = Box<dyn Write>;
+
+    #[derive(Debug, Default)]
+    pub struct Lock<T>(RefCell<T>);
+
+    impl<T> Lock<T> {
+        pub fn new(value: T) -> Self {
+            Self(RefCell::new(value))
+        }
+
+        pub fn borrow(&self) -> Ref<'_, T> {
+            self.0.borrow()
+        }
+
+        pub fn borrow_mut(&self) -> RefMut<'_, T> {
+            self.0.borrow_mut()
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+mod imp {
+    use std::io::Write;
+    use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    pub type Shared<T> = std::sync::Arc<T>;
+    pub type Weak<T> = std::sync::Weak<T>;
+
+    /// Where the interpreter writes what a program prints.
+    pub type Output = Box<dyn Write + Send>;
+
+    #[derive(Debug, Default)]
+    pub struct Lock<T>(RwLock<T>);
+
+    // A panic while a lock is held already aborts the script, so poisoning is ignored.
+    impl<T> Lock<T> {
+        pub fn new(value: T) -> Self {
+            Self(RwLock::new(value))
+        }
+
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+}
+
+pub use imp::{Lock, Output, Shared, Weak};
+
+impl<T: Clone> Clone for Lock<T> {
+    fn clone(&self) -> Self {
+        Lock::new(self.borrow().clone())
+    }
+}
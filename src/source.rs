@@ -0,0 +1,45 @@
+//! Loading scripts from disk. Lox source is UTF-8; a byte order mark, which some Windows
+//! editors put at the start of a file, is dropped rather than scanned as a stray character.
+
+use crate::error::SourceError;
+
+use std::fs;
+use std::path::Path;
+
+const BOM: &str = "\u{feff}";
+
+/// Reads the script at `path`.
+pub fn read_source(path: impl AsRef<Path>) -> Result<String, SourceError> {
+    decode(fs::read(path)?)
+}
+
+/// Turns the bytes of a script into its text, without a leading byte order mark.
+pub fn decode(bytes: Vec<u8>) -> Result<String, SourceError> {
+    let mut text = String::from_utf8(bytes)
+        .map_err(|e| SourceError::InvalidUtf8 { offset: e.utf8_error().valid_up_to() })?;
+    if text.starts_with(BOM) {
+        text.drain(..BOM.len());
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_bom() {
+        assert_eq!(decode(b"\xef\xbb\xbfprint 1;".to_vec()).unwrap(), "print 1;");
+        assert_eq!(decode(b"print \"\xef\xbb\xbf\";".to_vec()).unwrap(), "print \"\u{feff}\";");
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let error = decode(b"print \"\xff\";".to_vec()).unwrap_err();
+        assert!(matches!(error, SourceError::InvalidUtf8 { offset: 7 }));
+        assert_eq!(error.to_string(), "invalid UTF-8 at byte 7");
+
+        let error = read_source("does/not/exist.lox").unwrap_err();
+        assert!(matches!(error, SourceError::Io(_)));
+    }
+}
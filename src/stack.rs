@@ -0,0 +1,85 @@
+//! The interpreter's call stack, which tools can inspect while a program runs. The
+//! bottom frame is the script itself and every native function call pushes another.
+
+use crate::environment::Environment;
+use crate::interpreter::Value;
+use crate::shared::{Lock, Shared};
+
+/// The name of the frame at the bottom of the stack, for code outside any function.
+pub const SCRIPT: &str = "<script>";
+
+pub(crate) struct Frame {
+    pub(crate) function: String,
+    pub(crate) line: Option<i32>,
+    // The innermost scope of the frame. Native functions run outside any Lox scope.
+    pub(crate) environment: Option<Shared<Environment>>,
+}
+
+/// A read-only copy of one frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameView {
+    pub function: String,
+    /// The line the frame is executing, or `None` for native functions and before the
+    /// first statement runs.
+    pub line: Option<i32>,
+    /// The variables of every scope visible from the frame, innermost first and ending
+    /// with the globals. Each scope is sorted by name.
+    pub scopes: Vec<Vec<(String, Value)>>,
+}
+
+/// A handle on an interpreter's call stack, from `Interpreter::call_stack`. Observers can
+/// keep one to look at the stack from inside their callbacks. Cloning is cheap.
+#[derive(Clone)]
+pub struct CallStack(pub(crate) Shared<Lock<Vec<Frame>>>);
+
+impl CallStack {
+    pub(crate) fn new(globals: Shared<Environment>) -> Self {
+        let script = Frame { function: String::from(SCRIPT), line: None, environment: Some(globals) };
+        Self(Shared::new(Lock::new(vec![script])))
+    }
+
+    /// Every frame, innermost first.
+    pub fn frames(&self) -> Vec<FrameView> {
+        self.0.borrow().iter().rev()
+            .map(|frame| FrameView {
+                function: frame.function.clone(),
+                line: frame.line,
+                scopes: frame.environment.as_deref().map(scopes).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub(crate) fn push(&self, frame: Frame) {
+        self.0.borrow_mut().push(frame);
+    }
+
+    pub(crate) fn pop(&self) {
+        self.0.borrow_mut().pop();
+    }
+
+    pub(crate) fn set_line(&self, line: i32) {
+        if let Some(frame) = self.0.borrow_mut().last_mut() {
+            frame.line = Some(line);
+        }
+    }
+
+    pub(crate) fn set_environment(&self, environment: &Shared<Environment>) {
+        if let Some(frame) = self.0.borrow_mut().last_mut() {
+            frame.environment = Some(Shared::clone(environment));
+        }
+    }
+}
+
+fn scopes(environment: &Environment) -> Vec<Vec<(String, Value)>> {
+    let mut scopes: Vec<Vec<(String, Value)>> = Vec::new();
+    let mut current = Some(environment);
+    while let Some(environment) = current {
+        scopes.push(environment.values());
+        current = environment.enclosing.as_deref();
+    }
+    scopes
+}
@@ -0,0 +1,76 @@
+//! Checkpoints of the globals a script has built up, written as a JSON object from name
+//! to value so a long-running tool can stop and pick up where it left off. Only plain
+//! data is kept: numbers, strings, booleans and `nil`. Functions are made again by running
+//! their source, and string builders or numbers JSON cannot hold, like `NaN`, are left out
+//! rather than coming back as something else.
+
+use crate::interpreter::Value;
+
+use serde_json::{Map, Number, Value as Json};
+
+use std::io::{self, Read, Write};
+
+fn to_json(value: &Value) -> Option<Json> {
+    match value {
+        Value::Str(text) => Some(Json::String(text.to_string())),
+        Value::Number(number) => Number::from_f64(*number).map(Json::Number),
+        Value::Bool(value) => Some(Json::Bool(*value)),
+        Value::Nil => Some(Json::Null),
+        Value::Identifier(_) | Value::Native(_) | Value::Builder(_) | Value::Function(_) | Value::Channel(_) => None,
+    }
+}
+
+fn from_json(json: Json) -> Option<Value> {
+    match json {
+        Json::String(text) => Some(Value::from(text)),
+        Json::Number(number) => number.as_f64().map(Value::Number),
+        Json::Bool(value) => Some(Value::Bool(value)),
+        Json::Null => Some(Value::Nil),
+        Json::Array(_) | Json::Object(_) => None,
+    }
+}
+
+pub(crate) fn dump(globals: &[(String, Value)], writer: impl Write) -> io::Result<()> {
+    let state: Map<String, Json> = globals.iter()
+        .filter_map(|(name, value)| to_json(value).map(|json| (name.clone(), json)))
+        .collect();
+    serde_json::to_writer_pretty(writer, &state)?;
+    Ok(())
+}
+
+pub(crate) fn load(reader: impl Read) -> io::Result<Vec<(String, Value)>> {
+    let state: Map<String, Json> = serde_json::from_reader(reader)?;
+    state.into_iter()
+        .map(|(name, json)| match from_json(json) {
+            Some(value) => Ok((name, value)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("global '{name}' is not a number, string, boolean or nil"))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let globals = vec![
+            (String::from("count"), Value::Number(3.0)),
+            (String::from("done"), Value::Bool(false)),
+            (String::from("missing"), Value::Number(f64::NAN)),
+            (String::from("name"), Value::from("lox")),
+            (String::from("nothing"), Value::Nil),
+        ];
+
+        let mut buffer: Vec<u8> = Vec::new();
+        dump(&globals, &mut buffer).unwrap();
+        let loaded = load(buffer.as_slice()).unwrap();
+
+        let mut expected = globals;
+        expected.remove(2);
+        assert_eq!(loaded, expected);
+
+        assert_eq!(load(r#"{"list": [1, 2]}"#.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(load("[]".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! Counters for what a program costs to run, as printed by `jlox --stats`. Counting is
+//! off unless enabled with `Interpreter::enable_stats`.
+
+use crate::interpreter::Value;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Scopes opened, including the globals.
+    pub environments: u64,
+    /// Values created by evaluating expressions, by type name. Reading a variable
+    /// doesn't create a value, it copies one.
+    pub values: BTreeMap<&'static str, u64>,
+    /// The most frames that were on the call stack at once.
+    pub peak_call_depth: usize,
+    pub statements: u64,
+}
+
+impl Stats {
+    pub(crate) fn count_value(&mut self, value: &Value) {
+        *self.values.entry(value.type_name()).or_default() += 1;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Statements executed: {}", self.statements)?;
+        writeln!(f, "Environments created: {}", self.environments)?;
+        writeln!(f, "Peak call depth: {}", self.peak_call_depth)?;
+        write!(f, "Values allocated: {}", self.values.values().sum::<u64>())?;
+        for (kind, count) in &self.values {
+            write!(f, "\n  {kind}: {count}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+/// Picks the candidate closest to `name`, if any is close enough to plausibly be a typo.
+/// Replacing every character of `name`, like `_` for `a`, never counts as a typo.
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let length = name.chars().count();
+    let max_distance = (length / 3).max(1);
+
+    candidates.into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance && *distance < length)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("counter", "counter"), 0);
+        assert_eq!(edit_distance("countr", "counter"), 1);
+        assert_eq!(edit_distance("whiel", "while"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["counter", "total", "index"];
+        assert_eq!(did_you_mean("countr", names), Some("counter"));
+        assert_eq!(did_you_mean("totl", names), Some("total"));
+        assert_eq!(did_you_mean("x", names), None);
+
+        // One-letter names are all one edit apart, so they never suggest each other.
+        assert_eq!(did_you_mean("a", ["_", "b", "ab"]), None);
+        assert_eq!(did_you_mean("ab", ["a", "_"]), Some("a"));
+    }
+}
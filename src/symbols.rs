@@ -0,0 +1,356 @@
+//! Scope analysis shared by the tools that need it: which variables a program declares,
+//! in which scope, and where each of them is read or assigned. Build a table with
+//! `SymbolTable::build` and query it by id or by source position.
+//!
+//! Names resolve the way the interpreter looks them up: to the innermost declaration
+//! that comes before the use. Globals are late-bound, so a global can also be used
+//! before the line that declares it.
+
+use crate::scanner::Token;
+use crate::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+pub type ScopeId = usize;
+pub type SymbolId = usize;
+
+/// A line and column in the source, both starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: i32,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn of(token: &Token) -> Self {
+        Self { line: token.line, column: token.column }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    /// `None` for the global scope, which is always scope 0.
+    pub parent: Option<ScopeId>,
+    pub symbols: Vec<SymbolId>,
+    /// From the first to the last token in the scope, or `None` if it holds no tokens.
+    pub span: Option<(Position, Position)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: Token,
+    pub scope: ScopeId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: Token,
+    pub access: Access,
+    /// The scope the reference appears in.
+    pub scope: ScopeId,
+    /// `None` for names the program never declares, like native functions.
+    pub symbol: Option<SymbolId>,
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    pub scopes: Vec<Scope>,
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+}
+
+pub const GLOBAL_SCOPE: ScopeId = 0;
+
+impl SymbolTable {
+    pub fn build(ast: &Ast) -> Self {
+        let mut builder = Builder {
+            ast,
+            table: SymbolTable { scopes: vec![Scope { parent: None, symbols: Vec::new(), span: None }], ..Default::default() },
+            current: GLOBAL_SCOPE,
+        };
+        for &statement in &ast.statements {
+            builder.stmt(statement);
+        }
+        builder.resolve_late_globals();
+        builder.table
+    }
+
+    /// The reads and writes of `symbol`, in source order.
+    pub fn references_to(&self, symbol: SymbolId) -> impl Iterator<Item = &Reference> {
+        self.references.iter().filter(move |reference| reference.symbol == Some(symbol))
+    }
+
+    pub fn is_read(&self, symbol: SymbolId) -> bool {
+        self.references_to(symbol).any(|reference| reference.access == Access::Read)
+    }
+
+    /// The declaration that `name` refers to at `position` in `scope`.
+    pub fn resolve(&self, scope: ScopeId, name: &str, position: Position) -> Option<SymbolId> {
+        self.enclosing(scope).find_map(|scope| {
+            self.scopes[scope].symbols.iter().rev().copied().find(|&symbol| {
+                let declaration = &self.symbols[symbol].name;
+                &*declaration.lexeme == name && Position::of(declaration) < position
+            })
+        })
+    }
+
+    /// `scope` followed by every scope around it, out to the globals.
+    pub fn enclosing(&self, scope: ScopeId) -> impl Iterator<Item = ScopeId> + '_ {
+        std::iter::successors(Some(scope), |&scope| self.scopes[scope].parent)
+    }
+
+    /// The innermost scope whose span holds `position`.
+    pub fn scope_at(&self, position: Position) -> ScopeId {
+        // Scopes are numbered in the order they open, so the last match is the innermost.
+        self.scopes.iter().enumerate()
+            .rfind(|(_, scope)| scope.span.is_some_and(|(start, end)| start <= position && position <= end))
+            .map_or(GLOBAL_SCOPE, |(id, _)| id)
+    }
+
+    /// The symbol declared or referenced by the token at `position`, for features like
+    /// go to definition and rename.
+    pub fn symbol_at(&self, position: Position) -> Option<SymbolId> {
+        let covers = |token: &Token| token.line == position.line
+            && (token.column..token.column + token.lexeme.chars().count()).contains(&position.column);
+
+        self.symbols.iter().position(|symbol| covers(&symbol.name))
+            .or_else(|| self.references.iter().find(|reference| covers(&reference.name)).and_then(|reference| reference.symbol))
+    }
+
+    /// Every symbol visible at `position`, innermost first, for completion.
+    pub fn visible_at(&self, position: Position) -> Vec<SymbolId> {
+        let mut names: Vec<&str> = Vec::new();
+        let mut visible: Vec<SymbolId> = Vec::new();
+
+        for scope in self.enclosing(self.scope_at(position)) {
+            for &symbol in self.scopes[scope].symbols.iter().rev() {
+                let name = &self.symbols[symbol].name;
+                let declared = scope == GLOBAL_SCOPE || Position::of(name) < position;
+                if declared && !names.contains(&&*name.lexeme) {
+                    names.push(&name.lexeme);
+                    visible.push(symbol);
+                }
+            }
+        }
+
+        visible
+    }
+}
+
+struct Builder<'a> {
+    ast: &'a Ast,
+    table: SymbolTable,
+    current: ScopeId,
+}
+
+impl Builder<'_> {
+    fn begin_scope(&mut self) {
+        self.table.scopes.push(Scope { parent: Some(self.current), symbols: Vec::new(), span: None });
+        self.current = self.table.scopes.len() - 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current = self.table.scopes[self.current].parent.unwrap_or(GLOBAL_SCOPE);
+    }
+
+    // Widens the spans of the current scope and those around it to take in `token`.
+    fn visit(&mut self, token: &Token) {
+        let position = Position::of(token);
+        let mut scope = Some(self.current);
+
+        while let Some(id) = scope {
+            let span = &mut self.table.scopes[id].span;
+            *span = match *span {
+                Some((start, end)) => Some((start.min(position), end.max(position))),
+                None => Some((position, position)),
+            };
+            scope = self.table.scopes[id].parent;
+        }
+    }
+
+    fn declare(&mut self, name: &Token) {
+        self.visit(name);
+        self.table.symbols.push(Symbol { name: name.clone(), scope: self.current });
+        let symbol = self.table.symbols.len() - 1;
+        self.table.scopes[self.current].symbols.push(symbol);
+    }
+
+    fn reference(&mut self, name: &Token, access: Access) {
+        self.visit(name);
+        let symbol = self.table.resolve(self.current, &name.lexeme, Position::of(name));
+        self.table.references.push(Reference { name: name.clone(), access, scope: self.current, symbol });
+    }
+
+    // A name used before its global declaration still reaches it once that has run.
+    fn resolve_late_globals(&mut self) {
+        for reference in &mut self.table.references {
+            if reference.symbol.is_none() {
+                reference.symbol = self.table.scopes[GLOBAL_SCOPE].symbols.iter().copied()
+                    .find(|&symbol| self.table.symbols[symbol].name.lexeme == reference.name.lexeme);
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        match &ast[stmt] {
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                for &statement in statements {
+                    self.stmt(statement);
+                }
+                self.end_scope();
+            },
+            Stmt::Expression { expression } => self.expr(*expression),
+            Stmt::If { keyword, condition, then_branch, else_branch } => {
+                self.visit(keyword);
+                self.expr(*condition);
+                self.stmt(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(*else_branch);
+                }
+            },
+            Stmt::Print { keyword, expression } => {
+                self.visit(keyword);
+                self.expr(*expression);
+            },
+            Stmt::Variable { name, initializer } => {
+                // The initializer runs before the variable exists, so it sees the outer one.
+                if let Some(initializer) = initializer {
+                    self.expr(*initializer);
+                }
+                self.declare(name);
+            },
+            Stmt::While { keyword, condition, body } => {
+                self.visit(keyword);
+                self.expr(*condition);
+                self.stmt(*body);
+            },
+            Stmt::For { keyword, initializer, condition, increment, body } => {
+                self.begin_scope();
+                self.visit(keyword);
+                if let Some(initializer) = initializer {
+                    self.stmt(*initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expr(*condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(*increment);
+                }
+                self.stmt(*body);
+                self.end_scope();
+            },
+            Stmt::Import { keyword, module: _ } | Stmt::Debugger { keyword } => self.visit(keyword),
+            Stmt::Break {} | Stmt::Error { tokens: _ } => {},
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        match &ast[expr] {
+            Expr::Assign { name, value } => {
+                self.expr(*value);
+                self.reference(name, Access::Write);
+            },
+            Expr::Binary { .. } | Expr::Logical { .. } => {
+                let (first, links) = ast.chain(expr);
+                self.expr(first);
+                for (operator, operand) in links {
+                    self.visit(operator);
+                    self.expr(operand);
+                }
+            },
+            Expr::Call { callee, paren, arguments } => {
+                self.expr(*callee);
+                for &argument in arguments {
+                    self.expr(argument);
+                }
+                self.visit(paren);
+            },
+            Expr::Grouping { expression } => self.expr(*expression),
+            Expr::Literal { value: _ } => {},
+            Expr::Unary { operator, right } => {
+                self.visit(operator);
+                self.expr(*right);
+            },
+            Expr::Variable { name } => self.reference(name, Access::Read),
+            Expr::Block { statements, value } => {
+                self.begin_scope();
+                for &statement in statements {
+                    self.stmt(statement);
+                }
+                if let Some(value) = value {
+                    self.expr(*value);
+                }
+                self.end_scope();
+            },
+            Expr::If { keyword, condition, then_branch, else_branch } => {
+                self.visit(keyword);
+                self.expr(*condition);
+                self.expr(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expr(*else_branch);
+                }
+            },
+            Expr::Lambda { params, arrow, body } => {
+                self.begin_scope();
+                for param in params {
+                    self.declare(param);
+                }
+                self.visit(arrow);
+                self.expr(*body);
+                self.end_scope();
+            },
+            Expr::Error { token } => self.visit(token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    fn at(line: i32, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn resolution() {
+        let source = "print a;\nvar a = 1;\n{\n  var a = a + 1;\n  a = 3;\n  print a;\n}\nclock();";
+        let table = SymbolTable::build(&parse_source(source).unwrap());
+
+        assert_eq!(table.scopes.len(), 2);
+        assert_eq!(table.symbols.len(), 2);
+
+        let resolved: Vec<(&str, i32, Option<i32>)> = table.references.iter()
+            .map(|reference| (&*reference.name.lexeme, reference.name.line, reference.symbol.map(|symbol| table.symbols[symbol].name.line)))
+            .collect();
+        assert_eq!(resolved, vec![("a", 1, Some(2)), ("a", 4, Some(2)), ("a", 5, Some(4)), ("a", 6, Some(4)), ("clock", 8, None)]);
+
+        assert!(table.is_read(1));
+        assert_eq!(table.references_to(1).count(), 2);
+    }
+
+    #[test]
+    fn queries() {
+        let source = "var a = 1;\n{\n  var b = a;\n  print b;\n}\nvar c;";
+        let table = SymbolTable::build(&parse_source(source).unwrap());
+
+        assert_eq!(table.scopes[1].span, Some((at(3, 7), at(4, 9))));
+        assert_eq!(table.scope_at(at(4, 3)), 1);
+        assert_eq!(table.scope_at(at(6, 1)), GLOBAL_SCOPE);
+
+        assert_eq!(table.symbol_at(at(4, 9)), Some(1));
+        assert_eq!(table.symbol_at(at(3, 11)), Some(0));
+        assert_eq!(table.symbol_at(at(1, 1)), None);
+
+        let visible: Vec<&str> = table.visible_at(at(4, 3)).into_iter().map(|symbol| &*table.symbols[symbol].name.lexeme).collect();
+        assert_eq!(visible, vec!["b", "c", "a"]);
+    }
+}
@@ -1,33 +1,97 @@
 use crate::scanner::{Token, Literal};
 
-#[derive(Debug, PartialEq)]
+use std::ops::Index;
+
+/// Refers to an expression stored in an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+/// Refers to a statement stored in an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StmtId(u32);
+
+impl ExprId {
+    /// Where the expression sits in its arena, for tables that hold something per node.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl StmtId {
+    /// Where the statement sits in its arena, for tables that hold something per node.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// The stretch of source a node was parsed from, as byte offsets from the start of its
+/// first token to the end of its last, along with the line it starts on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: i32,
+}
+
+impl Span {
+    /// From the start of `first` to the end of `last`.
+    pub fn between(first: &Token, last: &Token) -> Self {
+        Self { start: first.span.start, end: last.span.end, line: first.line }
+    }
+
+    /// From the start of this span to the end of `last`.
+    pub fn to(self, last: &Token) -> Self {
+        Self { end: last.span.end, ..self }
+    }
+
+    /// The node's text in `source`, which must be the source it was parsed from.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
+/// A parsed program. Rather than boxing every node, expressions and statements live in two
+/// flat arenas and refer to their children by index, which keeps a tree in a handful of
+/// allocations and its nodes close together in memory. Every node has a `Span`, kept
+/// beside the arenas.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ast {
+    exprs: Vec<Expr>,
+    stmts: Vec<Stmt>,
+    expr_spans: Vec<Span>,
+    stmt_spans: Vec<Span>,
+    /// The top-level statements, in source order.
+    pub statements: Vec<StmtId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
 	Assign {
 		name: Token,
-		value: Box<Expr>,
+		value: ExprId,
 	},
 
 	Binary {
-		left: Box<Expr>,
+		left: ExprId,
 		operator: Token,
-		right: Box<Expr>,
+		right: ExprId,
 	},
 
 	Call {
-		callee: Box<Expr>,
+		callee: ExprId,
 		paren: Token,
-		arguments: Vec<Expr>,
+		arguments: Vec<ExprId>,
 	},
 
     /*
     Get {
-        object: Box<Expr>,
+        object: ExprId,
         name: Token,
     },
      */
 
     Grouping {
-        expression: Box<Expr>,
+        expression: ExprId,
     },
 
     Literal {
@@ -35,16 +99,16 @@ pub enum Expr {
     },
 
     Logical {
-        left: Box<Expr>,
+        left: ExprId,
         operator: Token,
-        right: Box<Expr>,
+        right: ExprId,
     },
 
     /*
     Set {
-        object: Box<Expr>,
+        object: ExprId,
         name: Token,
-        value: Box<Expr>,
+        value: ExprId,
     },
 
     Super {
@@ -59,18 +123,47 @@ pub enum Expr {
 
     Unary {
         operator: Token,
-        right: Box<Expr>,
+        right: ExprId,
     },
 
     Variable {
         name: Token,
     },
+
+    // A block in expression position, as in `var x = { var y = 2; y * y };`. `value` is
+    // the expression it ends with, written without a `;`; without one the block is nil.
+    Block {
+        statements: Vec<StmtId>,
+        value: Option<ExprId>,
+    },
+
+    // `if` in expression position. Both branches are `Block` expressions, except that the
+    // else branch of an `else if` is another `If`. Without an else branch it can be nil.
+    If {
+        keyword: Token,
+        condition: ExprId,
+        then_branch: ExprId,
+        else_branch: Option<ExprId>,
+    },
+
+    // An arrow function, `(a, b) => a + b`. Calling it evaluates `body` and returns its
+    // value; a block body returns the value the block ends with.
+    Lambda {
+        params: Vec<Token>,
+        arrow: Token,
+        body: ExprId,
+    },
+
+    // Placeholder for an expression that failed to parse.
+    Error {
+        token: Token,
+    },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     Block {
-        statements: Vec<Stmt>,
+        statements: Vec<StmtId>,
     },
 
     /*
@@ -88,17 +181,19 @@ pub enum Stmt {
      */
 
     Expression {
-        expression: Expr,
+        expression: ExprId,
     },
 
     If {
-        condition: Expr,
-        then_branch: Box<Stmt>,
-        else_branch: Option<Box<Stmt>>,
+        keyword: Token,
+        condition: ExprId,
+        then_branch: StmtId,
+        else_branch: Option<StmtId>,
     },
 
     Print {
-        expression: Expr,
+        keyword: Token,
+        expression: ExprId,
     },
 
     /*
@@ -110,13 +205,114 @@ pub enum Stmt {
 
     Variable {
         name: Token,
-        initializer: Option<Expr>,
+        initializer: Option<ExprId>,
     },
 
+    // `keyword` is the 'while' token, for reporting errors about the loop itself.
     While {
-        condition: Expr,
-        body: Box<Stmt>,
+        keyword: Token,
+        condition: ExprId,
+        body: StmtId,
+    },
+
+    // Kept as written rather than desugared into a while loop, so tools like the
+    // formatter can print it back. A missing condition means the loop runs until a break.
+    For {
+        keyword: Token,
+        initializer: Option<StmtId>,
+        condition: Option<ExprId>,
+        increment: Option<ExprId>,
+        body: StmtId,
     },
 
     Break {},
-}
\ No newline at end of file
+
+    // `import native "name";`, which loads a module of native functions.
+    Import {
+        keyword: Token,
+        module: Token,
+    },
+
+    // `debugger;`, which hands control to the observers as if a breakpoint was hit.
+    Debugger {
+        keyword: Token,
+    },
+
+    // Placeholder for a statement that failed to parse, holding the tokens the parser
+    // skipped while recovering.
+    Error {
+        tokens: Vec<Token>,
+    },
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trees built by hand rather than parsed can give nodes `Span::default()`.
+    pub fn add_expr(&mut self, expr: Expr, span: Span) -> ExprId {
+        self.exprs.push(expr);
+        self.expr_spans.push(span);
+        ExprId((self.exprs.len() - 1) as u32)
+    }
+
+    pub fn add_stmt(&mut self, stmt: Stmt, span: Span) -> StmtId {
+        self.stmts.push(stmt);
+        self.stmt_spans.push(span);
+        StmtId((self.stmts.len() - 1) as u32)
+    }
+
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn stmt_count(&self) -> usize {
+        self.stmts.len()
+    }
+
+    pub fn expr_span(&self, expr: ExprId) -> Span {
+        self.expr_spans[expr.index()]
+    }
+
+    pub fn stmt_span(&self, stmt: StmtId) -> Span {
+        self.stmt_spans[stmt.index()]
+    }
+
+    /// A chain of binary or logical operators like `a + b or c`: its leftmost operand, then
+    /// every operator with its right operand, in source order. Chains nest to the left, so
+    /// walking them in a loop rather than recursing keeps thousands of terms from
+    /// overflowing the stack. Anything else is a chain of one operand.
+    pub fn chain(&self, expr: ExprId) -> (ExprId, Vec<(&Token, ExprId)>) {
+        let mut links = Vec::new();
+        let mut left = expr;
+        while let Expr::Binary { left: inner, operator, right } | Expr::Logical { left: inner, operator, right } = &self[left] {
+            links.push((operator, *right));
+            left = *inner;
+        }
+        links.reverse();
+        (left, links)
+    }
+
+    /// The operands of a chain of operators, as `chain` finds it, leftmost first.
+    pub fn chain_operands(&self, expr: ExprId) -> Vec<ExprId> {
+        let (first, links) = self.chain(expr);
+        std::iter::once(first).chain(links.into_iter().map(|(_, operand)| operand)).collect()
+    }
+}
+
+impl Index<ExprId> for Ast {
+    type Output = Expr;
+
+    fn index(&self, expr: ExprId) -> &Expr {
+        &self.exprs[expr.index()]
+    }
+}
+
+impl Index<StmtId> for Ast {
+    type Output = Stmt;
+
+    fn index(&self, stmt: StmtId) -> &Stmt {
+        &self.stmts[stmt.index()]
+    }
+}
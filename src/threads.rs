@@ -0,0 +1,358 @@
+//! `import native "threads";` gives `spawn(function)`, `channel()`, `send(channel, value)`
+//! and `recv(channel)`, to run Lox functions on worker threads.
+//!
+//! Every worker runs its function in an interpreter of its own, so scripts never share
+//! variables across threads. With the `sync` feature the worker gets the function's own
+//! tree; otherwise the tree can't cross threads and the function is copied over as source.
+//! It starts out seeing copies of the plain data, native functions and channels that were
+//! visible where it was made. Other Lox functions are not copied. Channels are the only thing threads share:
+//! values sent through them are copied too.
+//!
+//! A worker keeps to the memory limit and call depth of the interpreter that spawned it,
+//! and stops when its interrupt is raised, even while waiting in `recv`; whichever thread
+//! sees the flag first stops. Fuel can't be split between threads, so `spawn` fails while
+//! fuel is limited. The module isn't built in, since workers can outlive the script: hosts
+//! register it.
+
+use crate::error::LoxError;
+#[cfg(not(feature = "sync"))]
+use crate::formatter;
+use crate::function::Function;
+use crate::interpreter::{Interpreter, Value};
+use crate::native::{NativeFunction, NativeModule};
+#[cfg(feature = "sync")]
+use crate::shared::Shared;
+#[cfg(feature = "sync")]
+use crate::syntax::{Ast, ExprId};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub struct ThreadsModule;
+
+impl NativeModule for ThreadsModule {
+    fn name(&self) -> &str {
+        "threads"
+    }
+
+    fn functions(&self) -> Vec<NativeFunction> {
+        vec![
+            NativeFunction::with_interpreter("spawn", 1, spawn),
+            NativeFunction::new("channel", 0, channel),
+            NativeFunction::new("send", 2, send),
+            NativeFunction::with_interpreter("recv", 1, recv),
+        ]
+    }
+}
+
+/// A value as it travels between threads. Lox values share their text with `Rc` unless
+/// the `sync` feature is on, so they can't cross threads themselves.
+#[derive(Debug)]
+enum Message {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    Nil,
+    Native(NativeFunction),
+    Channel(Channel),
+}
+
+impl Message {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(number) => Some(Message::Number(*number)),
+            Value::Str(text) => Some(Message::Str(text.to_string())),
+            Value::Bool(value) => Some(Message::Bool(*value)),
+            Value::Nil => Some(Message::Nil),
+            Value::Native(function) => Some(Message::Native(NativeFunction::clone(function))),
+            Value::Channel(channel) => Some(Message::Channel(channel.clone())),
+            Value::Identifier(_) | Value::Builder(_) | Value::Function(_) => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Message::Number(number) => Value::Number(number),
+            Message::Str(text) => Value::from(text),
+            Message::Bool(value) => Value::Bool(value),
+            Message::Nil => Value::Nil,
+            Message::Native(function) => Value::Native(function.into()),
+            Message::Channel(channel) => Value::Channel(channel),
+        }
+    }
+}
+
+/// A queue that any number of threads send values to and receive them from, in order.
+/// Copies of it are the same channel.
+#[derive(Clone, Default)]
+pub struct Channel(Arc<(Mutex<Queue>, Condvar)>);
+
+// An `Err` makes `recv` fail with that message, which is how a worker's runtime error
+// reaches the thread waiting for its result.
+type Queue = VecDeque<Result<Message, String>>;
+
+// How often `recv` looks at the interrupt while it waits.
+const POLL: Duration = Duration::from_millis(20);
+
+impl Channel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&self, message: Result<Message, String>) {
+        let (queue, ready) = &*self.0;
+        queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push_back(message);
+        ready.notify_one();
+    }
+
+    // The same for every copy of the channel.
+    pub(crate) fn address(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+
+    // Waits until there is something to receive, or `None` once `interrupted` says to stop.
+    // Nothing wakes the wait when the flag is raised, so it is checked every `POLL`.
+    fn pop(&self, interrupted: impl Fn() -> bool) -> Option<Result<Message, String>> {
+        let (queue, ready) = &*self.0;
+        let mut queue = queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            if let Some(message) = queue.pop_front() {
+                return Some(message);
+            }
+            if interrupted() {
+                return None;
+            }
+            queue = ready.wait_timeout(queue, POLL).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+        }
+    }
+}
+
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Channel")
+    }
+}
+
+// A function as it travels to a worker: its tree and body with the `sync` feature, where
+// the tree can be shared between threads, and otherwise its body as source.
+#[cfg(feature = "sync")]
+type Program = (Shared<Ast>, ExprId);
+#[cfg(not(feature = "sync"))]
+type Program = String;
+
+#[cfg(feature = "sync")]
+fn program(function: &Function) -> Program {
+    (Shared::clone(&function.ast), function.body)
+}
+
+#[cfg(not(feature = "sync"))]
+fn program(function: &Function) -> Program {
+    format!("(() => {})();", formatter::format_expression(&function.ast, function.body))
+}
+
+#[cfg(feature = "sync")]
+fn run_program(interpreter: &mut Interpreter, (ast, body): Program) -> Result<Value, LoxError> {
+    Ok(interpreter.call_body(ast, body)?)
+}
+
+#[cfg(not(feature = "sync"))]
+fn run_program(interpreter: &mut Interpreter, source: Program) -> Result<Value, LoxError> {
+    interpreter.eval(&source)
+}
+
+fn sendable(value: &Value) -> Result<Message, String> {
+    Message::from_value(value).ok_or_else(|| {
+        format!("Cannot send a {} to another thread, only numbers, strings, booleans, nil, native functions and channels.", value.type_name())
+    })
+}
+
+/// Runs `function` on a new thread and returns a channel that gets its result. If the
+/// function fails, receiving from that channel fails with its error.
+fn spawn(interpreter: &Interpreter, arguments: &[Value]) -> Result<Value, String> {
+    let Value::Function(function) = &arguments[0] else {
+        return Err(format!("spawn expects a function, not a {}.", arguments[0].type_name()));
+    };
+    if function.arity() != 0 {
+        return Err(format!("spawn expects a function without parameters, not one with {}.", function.arity()));
+    }
+    if interpreter.remaining_fuel().is_some() {
+        return Err(String::from("Cannot spawn a thread while fuel is limited."));
+    }
+
+    let program = program(function);
+    let captured = captured(function);
+    let result = Channel::new();
+    let sender = result.clone();
+    let limits = interpreter.limits();
+
+    thread::Builder::new()
+        .name(String::from("lox worker"))
+        .spawn(move || {
+            let mut interpreter = Interpreter::new(false);
+            interpreter.set_limits(limits);
+            for (name, message) in captured {
+                interpreter.set_global(&name, message.into_value());
+            }
+            // The error shows up where the result is received, so its line is left out.
+            let message = match run_program(&mut interpreter, program) {
+                Ok(value) => sendable(&value),
+                Err(LoxError::Runtime(error)) => Err(format!("The thread failed: {}", error.message)),
+                Err(e) => Err(format!("The thread failed: {e}")),
+            };
+            sender.push(message);
+        })
+        .map_err(|e| format!("Could not start a thread: {e}."))?;
+
+    Ok(Value::Channel(result))
+}
+
+// What the worker sees of the scope `function` was made in. Inner scopes come first in
+// `names`, so a name is taken from the scope that shadows the others.
+fn captured(function: &Function) -> Vec<(String, Message)> {
+    let mut captured: Vec<(String, Message)> = Vec::new();
+    for name in function.closure.names() {
+        if captured.iter().any(|(seen, _)| *seen == name) {
+            continue;
+        }
+        if let Some(message) = function.closure.get_name(&name).as_ref().and_then(Message::from_value) {
+            captured.push((name, message));
+        }
+    }
+    captured
+}
+
+fn channel(_arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::Channel(Channel::new()))
+}
+
+fn send(arguments: &[Value]) -> Result<Value, String> {
+    let Value::Channel(channel) = &arguments[0] else {
+        return Err(format!("send expects a channel, not a {}.", arguments[0].type_name()));
+    };
+    channel.push(Ok(sendable(&arguments[1])?));
+    Ok(Value::Nil)
+}
+
+/// Blocks until a value arrives on the channel, or the interpreter's interrupt is raised.
+fn recv(interpreter: &Interpreter, arguments: &[Value]) -> Result<Value, String> {
+    let Value::Channel(channel) = &arguments[0] else {
+        return Err(format!("recv expects a channel, not a {}.", arguments[0].type_name()));
+    };
+    match channel.pop(|| interpreter.take_interrupt()) {
+        Some(message) => message.map(Message::into_value),
+        None => Err(String::from("Interrupted.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn run(source: &str) -> Result<Value, String> {
+        run_in(&mut Interpreter::new(false), source)
+    }
+
+    fn run_in(interpreter: &mut Interpreter, source: &str) -> Result<Value, String> {
+        interpreter.register_module(Box::new(ThreadsModule));
+        interpreter.eval(&format!("import native \"threads\"; {source}")).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn spawn_and_channels() {
+        let source = "var jobs = channel(); var base = 10;\n\
+                      var worker = spawn(() => { var job = recv(jobs); send(jobs, job + base); job * 2 });\n\
+                      send(jobs, 1); var result = recv(worker); recv(jobs) + result;";
+        assert_eq!(run(source), Ok(Value::Number(13.0)));
+
+        // Names from an enclosing block are found as well as globals.
+        assert_eq!(run("var base = 10; var r = nil; { var x = 2; r = recv(spawn(() => x * base)); } r;"), Ok(Value::Number(20.0)));
+    }
+
+    // Without `sync` the worker parses the formatted body, which has to give back the tree
+    // the function was made from.
+    #[cfg(not(feature = "sync"))]
+    #[test]
+    fn formatted_body_round_trips() {
+        use crate::ast_printer;
+        use crate::parser::parse_source;
+        use crate::syntax::{Expr, Stmt};
+
+        let bodies = [
+            "1 + 2 * -3",
+            "{ var a = \"x\"; while (a != \"xxx\") a = a + \"x\"; a }",
+            "if (true or false) { print 1; } else { nil }",
+            "{ var f = (a, b) => a - (b - 1); f(3, 4) }",
+            "{ for (var i = 0; i < 3; i = i + 1) { if (i == 1) break; } !(1 < 2 and 3 >= 4) }",
+        ];
+        for body in bodies {
+            let mut interpreter = Interpreter::new(false);
+            interpreter.eval(&format!("var f = () => {body};")).unwrap();
+            let Some(Value::Function(function)) = interpreter.get_global("f") else { panic!("{body}") };
+
+            let ast = parse_source(&program(&function)).unwrap();
+            let Stmt::Expression { expression } = &ast[ast.statements[0]] else { panic!("{body}") };
+            let Expr::Call { callee, .. } = &ast[*expression] else { panic!("{body}") };
+            let Expr::Grouping { expression: lambda } = &ast[*callee] else { panic!("{body}") };
+            let Expr::Lambda { body: parsed, .. } = &ast[*lambda] else { panic!("{body}") };
+            assert_eq!(ast_printer::print_expr(&ast, *parsed), ast_printer::print_expr(&function.ast, function.body), "{body}");
+        }
+    }
+
+    #[test]
+    fn isolated() {
+        // The worker gets a copy of `count`, so its assignment stays on its own thread.
+        assert_eq!(run("var count = 1; recv(spawn(() => { count = count + 1; count })) + count;"), Ok(Value::Number(3.0)));
+        // Lox functions aren't copied to the worker.
+        assert_eq!(run("var f = () => 1; recv(spawn(() => f()));"), Err(String::from("[line 1] Error: The thread failed: Variable 'f' is undefined.")));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(run("recv(spawn(() => -\"a\"));"), Err(String::from("[line 1] Error: The thread failed: cannot apply '-' operator on a non-number.")));
+        assert!(run("send(channel(), () => 1);").unwrap_err().contains("Cannot send a function to another thread"));
+        assert!(run("spawn((a) => a);").unwrap_err().contains("without parameters"));
+        assert!(Interpreter::new(false).eval("import native \"threads\";").is_err());
+    }
+
+    #[test]
+    fn limits() {
+        // Workers keep to the call depth of the interpreter that spawned them.
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_max_call_depth(3);
+        let error = run_in(&mut interpreter, "recv(spawn(() => { var a = () => 1; var b = () => a(); var c = () => b(); c() }));").unwrap_err();
+        assert!(error.contains("The thread failed: Stack overflow"), "{error}");
+
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_fuel(Some(1000));
+        let error = run_in(&mut interpreter, "spawn(() => 1);").unwrap_err();
+        assert_eq!(error, "[line 1] Error: Cannot spawn a thread while fuel is limited.");
+    }
+
+    #[test]
+    fn interrupt_recv() {
+        // Nobody sends to the channel, so only the interrupt ends the wait.
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut interpreter = Interpreter::new(false);
+        interpreter.set_interrupt(Arc::clone(&interrupt));
+        let setter = Arc::clone(&interrupt);
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            setter.store(true, Ordering::Relaxed);
+        });
+        let error = run_in(&mut interpreter, "recv(channel());").unwrap_err();
+        thread.join().unwrap();
+        assert_eq!(error, "[line 1] Error: Interrupted.");
+        assert!(!interrupt.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,61 @@
+//! Runs every program in `tests/programs` and compares what it prints, followed by the
+//! errors it stops with if any and their codes, against the `.expected` file next to it.
+//!
+//! After an intended change in behavior, rerun with `UPDATE_EXPECTED=1` to rewrite the
+//! expected files, and review the diff.
+
+use crafting_interpreters_rust::{Interpreter, LoxError};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn programs() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("programs");
+    let mut programs: Vec<PathBuf> = fs::read_dir(&directory)
+        .expect("tests/programs should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+fn run(source: &str) -> String {
+    let mut interpreter = Interpreter::new(false);
+    interpreter.capture_output();
+    let result = interpreter.eval(source);
+
+    let mut lines = interpreter.captured_output();
+    match result {
+        Ok(_) => {},
+        Err(LoxError::Compile(errors)) => lines.extend(errors.iter().map(|e| format!("error: {e} [{}]", e.code()))),
+        Err(LoxError::Runtime(e)) => lines.push(format!("error: {e} [{}]", e.code)),
+    }
+    lines.iter().map(|line| format!("{line}\n")).collect()
+}
+
+#[test]
+fn programs_match_expected_output() {
+    let update = env::var_os("UPDATE_EXPECTED").is_some();
+    let mut failures: Vec<String> = Vec::new();
+
+    for program in programs() {
+        let source = fs::read_to_string(&program).unwrap();
+        let actual = run(&source);
+        let expected_path = program.with_extension("expected");
+
+        if update {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {},
+            Ok(expected) => failures.push(format!("{}\n--- expected\n{expected}--- actual\n{actual}", program.display())),
+            Err(e) => failures.push(format!("{}: could not read {}: {e}", program.display(), expected_path.display())),
+        }
+    }
+
+    assert!(failures.is_empty(), "{} program(s) changed behavior:\n\n{}", failures.len(), failures.join("\n"));
+}
@@ -0,0 +1,286 @@
+//! Generates random syntax trees, prints them as Lox source and parses that source again,
+//! checking the parser gives back the tree it started from. The printer only adds the
+//! parentheses precedence requires, so this exercises the parser's precedence and
+//! associativity as much as the grammar itself. The formatter gets the same source and
+//! must keep the tree intact as well, and the concrete syntax tree must give it back byte
+//! for byte, as it must for any text at all.
+
+use crafting_interpreters_rust::ast_printer;
+use crafting_interpreters_rust::cst;
+use crafting_interpreters_rust::formatter;
+use crafting_interpreters_rust::parser;
+use crafting_interpreters_rust::scanner::{Literal, Span, Token, TokenType};
+use crafting_interpreters_rust::syntax::{Ast, Expr, ExprId, Stmt, StmtId};
+
+use proptest::prelude::*;
+
+// A syntax tree without tokens, which proptest can generate and shrink.
+#[derive(Debug, Clone)]
+enum Node {
+    Number(u8),
+    Str(String),
+    Bool(bool),
+    Nil,
+    Variable(&'static str),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+    Logical(&'static str, Box<Node>, Box<Node>),
+    Assign(&'static str, Box<Node>),
+    Call(Box<Node>, Vec<Node>),
+    Grouping(Box<Node>),
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Print(Node),
+    Expression(Node),
+    Variable(&'static str, Option<Node>),
+    Block(Vec<Statement>),
+    // The then branch is always a block, so an `else` can't attach to the wrong `if`.
+    If(Node, Vec<Statement>, Option<Box<Statement>>),
+    While(Node, Box<Statement>),
+}
+
+const NAMES: [&str; 3] = ["a", "b", "foo"];
+
+fn node() -> impl Strategy<Value = Node> {
+    let leaf = prop_oneof![
+        any::<u8>().prop_map(Node::Number),
+        "[a-z ]{0,5}".prop_map(Node::Str),
+        any::<bool>().prop_map(Node::Bool),
+        Just(Node::Nil),
+        prop::sample::select(&NAMES[..]).prop_map(Node::Variable),
+    ];
+
+    leaf.prop_recursive(4, 32, 3, |inner| prop_oneof![
+        (prop::sample::select(vec!["-", "!"]), inner.clone()).prop_map(|(operator, right)| Node::Unary(operator, Box::new(right))),
+        (prop::sample::select(vec!["+", "-", "*", "/", "<", "<=", ">", ">=", "==", "!="]), inner.clone(), inner.clone())
+            .prop_map(|(operator, left, right)| Node::Binary(operator, Box::new(left), Box::new(right))),
+        (prop::sample::select(vec!["and", "or"]), inner.clone(), inner.clone())
+            .prop_map(|(operator, left, right)| Node::Logical(operator, Box::new(left), Box::new(right))),
+        (prop::sample::select(&NAMES[..]), inner.clone()).prop_map(|(name, value)| Node::Assign(name, Box::new(value))),
+        (inner.clone(), prop::collection::vec(inner.clone(), 0..3)).prop_map(|(callee, arguments)| Node::Call(Box::new(callee), arguments)),
+        inner.prop_map(|expression| Node::Grouping(Box::new(expression))),
+    ])
+}
+
+fn statement() -> impl Strategy<Value = Statement> {
+    let simple = prop_oneof![
+        node().prop_map(Statement::Print),
+        node().prop_map(Statement::Expression),
+        (prop::sample::select(&NAMES[..]), prop::option::of(node())).prop_map(|(name, initializer)| Statement::Variable(name, initializer)),
+    ];
+
+    simple.prop_recursive(3, 16, 3, |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 0..3).prop_map(Statement::Block),
+        (node(), prop::collection::vec(inner.clone(), 0..3), prop::option::of(inner.clone()))
+            .prop_map(|(condition, then_branch, else_branch)| Statement::If(condition, then_branch, else_branch.map(Box::new))),
+        (node(), inner).prop_map(|(condition, body)| Statement::While(condition, Box::new(body))),
+    ])
+}
+
+fn token(token_type: TokenType, lexeme: &str) -> Token {
+    Token { token_type, lexeme: lexeme.into(), literal: None, line: 1, column: 1, span: Span::default() }
+}
+
+fn operator(lexeme: &str) -> Token {
+    let token_type = match lexeme {
+        "-" => TokenType::Minus,
+        "+" => TokenType::Plus,
+        "*" => TokenType::Star,
+        "/" => TokenType::Slash,
+        "!" => TokenType::Bang,
+        "!=" => TokenType::BangEqual,
+        "==" => TokenType::EqualEqual,
+        "<" => TokenType::Less,
+        "<=" => TokenType::LessEqual,
+        ">" => TokenType::Greater,
+        ">=" => TokenType::GreaterEqual,
+        "and" => TokenType::And,
+        "or" => TokenType::Or,
+        _ => unreachable!("not an operator: {lexeme}"),
+    };
+    token(token_type, lexeme)
+}
+
+// How tightly a binary operator binds, between assignment (1) and unary operators (8).
+fn binary_precedence(lexeme: &str) -> u8 {
+    match lexeme {
+        "or" => 2,
+        "and" => 3,
+        "==" | "!=" => 4,
+        "<" | "<=" | ">" | ">=" => 5,
+        "+" | "-" => 6,
+        _ => 7,
+    }
+}
+
+// How tightly an expression binds, from assignment up to primary expressions.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Assign { .. } => 1,
+        Expr::Binary { operator, .. } | Expr::Logical { operator, .. } => binary_precedence(&operator.lexeme),
+        Expr::Unary { .. } => 8,
+        Expr::Call { .. } => 9,
+        _ => 10,
+    }
+}
+
+// Groups `expr` if it binds looser than `minimum`.
+fn at_least(ast: &mut Ast, expr: ExprId, minimum: u8) -> ExprId {
+    if precedence(&ast[expr]) < minimum {
+        ast.add_expr(Expr::Grouping { expression: expr }, Default::default())
+    } else {
+        expr
+    }
+}
+
+/// The expression for `node`, with groupings added wherever the source needs parentheses.
+fn to_expr(ast: &mut Ast, node: &Node) -> ExprId {
+    let expr = match node {
+        Node::Number(number) => Expr::Literal { value: Literal::Number(f64::from(*number)) },
+        Node::Str(text) => Expr::Literal { value: Literal::Str(text.as_str().into()) },
+        Node::Bool(value) => Expr::Literal { value: Literal::Bool(*value) },
+        Node::Nil => Expr::Literal { value: Literal::Nil },
+        Node::Variable(name) => Expr::Variable { name: token(TokenType::Identifier, name) },
+        Node::Unary(lexeme, right) => {
+            let right = to_expr(ast, right);
+            Expr::Unary { operator: operator(lexeme), right: at_least(ast, right, 8) }
+        },
+        Node::Binary(lexeme, left, right) | Node::Logical(lexeme, left, right) => {
+            // Every binary operator is left-associative, so a right operand of the same
+            // precedence needs parentheses too.
+            let own = binary_precedence(lexeme);
+            let operator = operator(lexeme);
+            let left = to_expr(ast, left);
+            let left = at_least(ast, left, own);
+            let right = to_expr(ast, right);
+            let right = at_least(ast, right, own + 1);
+
+            if matches!(node, Node::Binary(..)) {
+                Expr::Binary { left, operator, right }
+            } else {
+                Expr::Logical { left, operator, right }
+            }
+        },
+        Node::Assign(name, value) => Expr::Assign { name: token(TokenType::Identifier, name), value: to_expr(ast, value) },
+        Node::Call(callee, arguments) => {
+            let callee = to_expr(ast, callee);
+            Expr::Call {
+                callee: at_least(ast, callee, 9),
+                paren: token(TokenType::RightParen, ")"),
+                arguments: arguments.iter().map(|argument| to_expr(ast, argument)).collect(),
+            }
+        },
+        Node::Grouping(expression) => Expr::Grouping { expression: to_expr(ast, expression) },
+    };
+    ast.add_expr(expr, Default::default())
+}
+
+fn to_stmt(ast: &mut Ast, statement: &Statement) -> StmtId {
+    let stmt = match statement {
+        Statement::Print(node) => Stmt::Print { keyword: token(TokenType::Print, "print"), expression: to_expr(ast, node) },
+        Statement::Expression(node) => Stmt::Expression { expression: to_expr(ast, node) },
+        Statement::Variable(name, initializer) => {
+            Stmt::Variable { name: token(TokenType::Identifier, name), initializer: initializer.as_ref().map(|initializer| to_expr(ast, initializer)) }
+        },
+        Statement::Block(statements) => Stmt::Block { statements: statements.iter().map(|statement| to_stmt(ast, statement)).collect() },
+        Statement::If(condition, then_branch, else_branch) => {
+            let condition = to_expr(ast, condition);
+            let then_branch = Stmt::Block { statements: then_branch.iter().map(|statement| to_stmt(ast, statement)).collect() };
+            Stmt::If {
+                keyword: token(TokenType::If, "if"),
+                condition,
+                then_branch: ast.add_stmt(then_branch, Default::default()),
+                else_branch: else_branch.as_ref().map(|else_branch| to_body(ast, else_branch)),
+            }
+        },
+        Statement::While(condition, body) => {
+            Stmt::While { keyword: token(TokenType::While, "while"), condition: to_expr(ast, condition), body: to_body(ast, body) }
+        },
+    };
+    ast.add_stmt(stmt, Default::default())
+}
+
+// A declaration can't be the body of an `if` or `while` on its own, so it goes in a block.
+fn to_body(ast: &mut Ast, statement: &Statement) -> StmtId {
+    match statement {
+        Statement::Variable(..) => {
+            let statements = vec![to_stmt(ast, statement)];
+            ast.add_stmt(Stmt::Block { statements }, Default::default())
+        },
+        _ => to_stmt(ast, statement),
+    }
+}
+
+fn expr_source(ast: &Ast, expr: ExprId) -> String {
+    match &ast[expr] {
+        Expr::Assign { name, value } => format!("{} = {}", name.lexeme, expr_source(ast, *value)),
+        Expr::Binary { left, operator, right } | Expr::Logical { left, operator, right } => {
+            format!("{} {} {}", expr_source(ast, *left), operator.lexeme, expr_source(ast, *right))
+        },
+        Expr::Call { callee, paren: _, arguments } => {
+            let arguments: Vec<String> = arguments.iter().map(|&argument| expr_source(ast, argument)).collect();
+            format!("{}({})", expr_source(ast, *callee), arguments.join(", "))
+        },
+        Expr::Grouping { expression } => format!("({})", expr_source(ast, *expression)),
+        Expr::Literal { value: Literal::Number(number) } => number.to_string(),
+        Expr::Literal { value: Literal::Str(text) } => format!("\"{text}\""),
+        Expr::Literal { value: Literal::Bool(value) } => value.to_string(),
+        Expr::Literal { value: _ } => String::from("nil"),
+        Expr::Unary { operator, right } => format!("{}{}", operator.lexeme, expr_source(ast, *right)),
+        Expr::Variable { name } => name.lexeme.to_string(),
+        expr => unreachable!("not generated: {expr:?}"),
+    }
+}
+
+fn stmt_source(ast: &Ast, stmt: StmtId) -> String {
+    match &ast[stmt] {
+        Stmt::Block { statements } => {
+            let statements: Vec<String> = statements.iter().map(|&statement| stmt_source(ast, statement)).collect();
+            format!("{{ {} }}", statements.join(" "))
+        },
+        Stmt::Expression { expression } => format!("{};", expr_source(ast, *expression)),
+        Stmt::Print { keyword: _, expression } => format!("print {};", expr_source(ast, *expression)),
+        Stmt::Variable { name, initializer: Some(initializer) } => format!("var {} = {};", name.lexeme, expr_source(ast, *initializer)),
+        Stmt::Variable { name, initializer: None } => format!("var {};", name.lexeme),
+        Stmt::If { keyword: _, condition, then_branch, else_branch: Some(else_branch) } => {
+            format!("if ({}) {} else {}", expr_source(ast, *condition), stmt_source(ast, *then_branch), stmt_source(ast, *else_branch))
+        },
+        Stmt::If { keyword: _, condition, then_branch, else_branch: None } => {
+            format!("if ({}) {}", expr_source(ast, *condition), stmt_source(ast, *then_branch))
+        },
+        Stmt::While { keyword: _, condition, body } => format!("while ({}) {}", expr_source(ast, *condition), stmt_source(ast, *body)),
+        stmt => unreachable!("not generated: {stmt:?}"),
+    }
+}
+
+proptest! {
+    #[test]
+    fn printed_programs_parse_back(statements in prop::collection::vec(statement(), 1..4)) {
+        let mut ast = Ast::new();
+        ast.statements = statements.iter().map(|statement| to_stmt(&mut ast, statement)).collect();
+        let source: Vec<String> = ast.statements.iter().map(|&statement| stmt_source(&ast, statement)).collect();
+        let source = source.join("\n");
+        let expected = ast_printer::print_statements(&ast, &ast.statements);
+
+        let parsed = parser::parse_source(&source).map_err(|errors| TestCaseError::fail(format!("{source}\n{errors:?}")))?;
+        prop_assert_eq!(ast_printer::print_statements(&parsed, &parsed.statements), expected.clone(), "{}", source);
+
+        let formatted = formatter::format_source(&source).unwrap();
+        let reparsed = parser::parse_source(&formatted).map_err(|errors| TestCaseError::fail(format!("{formatted}\n{errors:?}")))?;
+        prop_assert_eq!(ast_printer::print_statements(&reparsed, &reparsed.statements), expected, "{}", formatted);
+        prop_assert_eq!(formatter::format_source(&formatted).unwrap(), formatted);
+
+        prop_assert_eq!(cst::parse(&source).root.text(), source);
+    }
+
+    #[test]
+    fn syntax_trees_keep_any_text(source in "([a-z0-9 \t\n(){};,.=+*/<>!\"@é-]|//|=>|var |print ){0,40}") {
+        let tree = cst::parse(&source);
+        prop_assert_eq!(tree.root.text(), source.clone());
+        for token in tree.root.tokens() {
+            prop_assert_eq!(&source[token.range.clone()], token.text.as_str());
+        }
+    }
+}